@@ -0,0 +1,83 @@
+//! Benchmarks for scanning warriors, which spend most of their time comparing
+//! instructions with `CMP`, `SEQ` and `SNE`.
+
+use std::fs;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use corewars::core::{Core, CoreConfig, Dispatch};
+use corewars::load_file::Warrior;
+use corewars::parser;
+
+const SCANNERS: &[&str] = &["ScanMan", "HeScansAlone"];
+
+fn load_warrior(name: &str) -> Warrior {
+    let path = format!("testdata/input/wilmoo/{}.redcode", name);
+    let input =
+        fs::read_to_string(&path).unwrap_or_else(|err| panic!("Unable to read {}: {}", path, err));
+
+    parser::parse(&input).expect("Failed to parse warrior")
+}
+
+fn build_core(dispatch: Dispatch) -> Core {
+    Core::new(CoreConfig {
+        dispatch,
+        ..CoreConfig::default()
+    })
+    .expect("Failed to create core")
+}
+
+/// A scanner alone in the core scans it for the full battle, without ever
+/// finding anything to attack. Since there is only one warrior, this is
+/// deterministic.
+fn scan_empty_core(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scan_empty_core");
+    group.sample_size(20);
+
+    for &name in SCANNERS {
+        let warriors = [load_warrior(name)];
+
+        for &dispatch in &[Dispatch::Decoded, Dispatch::Generic] {
+            let mut core = build_core(dispatch);
+            let id = BenchmarkId::new(name, format!("{:?}", dispatch));
+
+            group.bench_function(id, |b| {
+                b.iter(|| {
+                    core.reset();
+                    core.load_warriors(&warriors)
+                        .expect("Failed to load warrior");
+                    core.run().expect("Failed to run")
+                });
+            });
+        }
+    }
+
+    group.finish();
+}
+
+/// The scanners battling each other. Warriors are placed randomly, so this
+/// is noisier than [`scan_empty_core`].
+fn battle(c: &mut Criterion) {
+    let mut group = c.benchmark_group("battle");
+    group.sample_size(20);
+
+    let warriors: Vec<Warrior> = SCANNERS.iter().map(|name| load_warrior(name)).collect();
+
+    for &dispatch in &[Dispatch::Decoded, Dispatch::Generic] {
+        let mut core = build_core(dispatch);
+
+        group.bench_function(format!("{:?}", dispatch), |b| {
+            b.iter(|| {
+                core.reset();
+                core.load_warriors(&warriors)
+                    .expect("Failed to load warriors");
+                core.run().expect("Failed to run")
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, scan_empty_core, battle);
+criterion_main!(benches);
@@ -1,17 +1,27 @@
-use env_logger;
-
 use anyhow::{anyhow, Result};
 use corewars::{
-    core::{Core, CoreConfig, WarriorID},
+    core::{BattleResult, Core, CoreConfig, Killer, Tee, WarriorID},
+    debugger::Debugger,
     load_file::Warrior,
-    parser,
+    parser::{self, ParseConfig},
+    render::{Animation, Image},
+    replay::{Player, Recorder, Replay},
+    trace::Tracer,
+    visualiser::Visualiser,
+};
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute, queue,
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use log::debug;
 use std::{
     collections::HashMap,
-    fs,
-    io::{self, Read},
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use lazy_static::lazy_static;
@@ -38,29 +48,93 @@ enum Command {
         #[structopt(long, short = "r")]
         rounds: Option<i32>,
 
-        /// The size of the core. Defaults to 8,000.
-        #[structopt(long, short = "s")]
-        core_size: Option<i32>,
+        #[structopt(flatten)]
+        core: CoreOptions,
+
+        /// Record the first round to a replay file
+        #[structopt(long, parse(from_os_str))]
+        record: Option<PathBuf>,
+
+        /// Write a JSON trace of every step of the first round, one object per
+        /// line. See the `corewars::trace` module for the schema.
+        #[structopt(long, parse(from_os_str))]
+        trace: Option<PathBuf>,
+
+        /// Print statistics about each warrior after every round
+        #[structopt(long)]
+        stats: bool,
+
+        /// Input files; use "-" to read from stdin
+        #[structopt(long, short, parse(from_os_str))]
+        warrior: Vec<PathBuf>,
+    },
+    /// Play back a battle recorded with `run --record`, printing each cycle
+    Replay {
+        /// Wait for Enter after each cycle, or stop at "q"
+        #[structopt(long)]
+        step: bool,
+
+        /// The replay file to play back
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Watch a battle in the terminal. Press +/- to change the speed, space to
+    /// pause and q to quit.
+    Watch {
+        /// The number of cycles to run between frames. Defaults to 16.
+        #[structopt(long)]
+        speed: Option<usize>,
+
+        #[structopt(flatten)]
+        core: CoreOptions,
+
+        /// Input files; use "-" to read from stdin
+        #[structopt(long, short, parse(from_os_str))]
+        warrior: Vec<PathBuf>,
+    },
+    /// Render a battle, or a replay, to a PNG heatmap of which warrior owns
+    /// each cell and how often it was written
+    Render {
+        /// The PNG file to write the heatmap to, at the end of the battle
+        #[structopt(long, short, parse(from_os_str))]
+        output: PathBuf,
+
+        /// Also write an animated GIF of the battle
+        #[structopt(long, parse(from_os_str))]
+        gif: Option<PathBuf>,
+
+        /// Also write each frame of the battle as a numbered PNG in this directory
+        #[structopt(long, parse(from_os_str))]
+        frames: Option<PathBuf>,
+
+        /// The number of cycles between frames. Defaults to 1,000.
+        #[structopt(long)]
+        frame_interval: Option<i32>,
 
-        /// The maximum number of cycles to run. Defaults to 80,000.
-        #[structopt(long, short = "c")]
-        max_cycles: Option<i32>,
+        /// The number of cells in each row of the image. Defaults to 100.
+        #[structopt(long)]
+        columns: Option<u32>,
 
-        /// The maximum number of processes. Defaults to 8,000.
-        #[structopt(long, short = "p")]
-        max_processes: Option<i32>,
+        /// The size of each cell in pixels. Defaults to 4.
+        #[structopt(long)]
+        scale: Option<u32>,
 
-        /// The maximum size of a warrior. Defaults to 100.
-        #[structopt(long, short = "l")]
-        max_warrior_length: Option<i32>,
+        /// Render a replay recorded with `run --record`, instead of running a
+        /// new battle
+        #[structopt(long, parse(from_os_str), conflicts_with = "warrior")]
+        replay: Option<PathBuf>,
 
-        /// The minimum separation distance. Defaults to 100.
-        #[structopt(long, short = "d")]
-        min_distance: Option<i32>,
+        #[structopt(flatten)]
+        core: CoreOptions,
 
-        /// The size of the P space. Defaults to 500.
-        #[structopt(long, short = "S")]
-        p_space: Option<i32>,
+        /// Input files; use "-" to read from stdin
+        #[structopt(long, short, parse(from_os_str))]
+        warrior: Vec<PathBuf>,
+    },
+    /// Step through a battle interactively, in the style of pMARS `cdb`
+    Debug {
+        #[structopt(flatten)]
+        core: CoreOptions,
 
         /// Input files; use "-" to read from stdin
         #[structopt(long, short, parse(from_os_str))]
@@ -68,6 +142,114 @@ enum Command {
     },
 }
 
+#[derive(Debug, StructOpt)]
+struct CoreOptions {
+    /// The size of the core. Defaults to 8,000.
+    #[structopt(long, short = "s")]
+    core_size: Option<i32>,
+
+    /// The maximum number of cycles to run. Defaults to 80,000.
+    #[structopt(long, short = "c")]
+    max_cycles: Option<i32>,
+
+    /// The maximum number of processes. Defaults to 8,000.
+    #[structopt(long, short = "p")]
+    max_processes: Option<i32>,
+
+    /// The maximum size of a warrior. Defaults to 100.
+    #[structopt(long, short = "l")]
+    max_warrior_length: Option<i32>,
+
+    /// The minimum separation distance. Defaults to 100.
+    #[structopt(long, short = "d")]
+    min_distance: Option<i32>,
+
+    /// The size of the P space. Defaults to 500.
+    #[structopt(long, short = "S")]
+    p_space: Option<i32>,
+
+    /// End a round early if the core repeats a previous state, since the
+    /// result can no longer change.
+    #[structopt(long)]
+    detect_loops: bool,
+
+    /// The seed used to place warriors in the core. Defaults to a random seed.
+    #[structopt(long)]
+    seed: Option<u64>,
+
+    /// Define a label for the warriors as LABEL=VALUE, overriding any
+    /// definition in the warriors themselves. May be given more than once.
+    /// Predefined labels like CORESIZE are set by their own options.
+    #[structopt(long, short = "D", number_of_values = 1, parse(try_from_str = parse_define))]
+    define: Vec<(String, i32)>,
+}
+
+impl CoreOptions {
+    fn config(&self) -> CoreConfig {
+        let mut config = CoreConfig::default();
+        if let Some(core_size) = self.core_size {
+            config.core_size = core_size;
+        }
+        if let Some(max_cycles) = self.max_cycles {
+            config.max_cycles = max_cycles;
+        }
+        if let Some(max_processes) = self.max_processes {
+            config.max_processes = max_processes;
+        }
+        if let Some(max_warrior_length) = self.max_warrior_length {
+            config.max_warrior_length = max_warrior_length;
+        }
+        if let Some(min_distance) = self.min_distance {
+            config.min_distance = min_distance;
+        }
+        if let Some(p_space) = self.p_space {
+            config.p_space = p_space;
+        }
+        config.detect_loops = self.detect_loops;
+        config.seed = self.seed;
+        config
+    }
+
+    fn parse_config(&self) -> ParseConfig {
+        ParseConfig {
+            labels: self.define.iter().cloned().collect(),
+            ..ParseConfig::from(&self.config())
+        }
+    }
+}
+
+/// Parse a `-D LABEL=VALUE` definition
+fn parse_define(define: &str) -> Result<(String, i32)> {
+    let (label, value) = define
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected LABEL=VALUE, found {:?}", define))?;
+
+    let label = label.trim();
+
+    // The predefined labels describe the battle, so they are set with their
+    // own options instead
+    let replacement = match label {
+        "CORESIZE" => Some("--core-size"),
+        "MAXCYCLES" => Some("--max-cycles"),
+        "MAXPROCESSES" => Some("--max-processes"),
+        "MAXLENGTH" => Some("--max-warrior-length"),
+        "MINDISTANCE" => Some("--min-distance"),
+        "ROUNDS" => Some("--rounds"),
+        "PSPACESIZE" => Some("--p-space"),
+        "WARRIORS" | "VERSION" | "CURLINE" => None,
+        _ => return Ok((label.to_owned(), value.trim().parse()?)),
+    };
+
+    Err(match replacement {
+        Some(option) => anyhow!(
+            "{} cannot be defined with -D; use {} instead",
+            label,
+            option
+        ),
+        None => anyhow!("{} is predefined and cannot be defined with -D", label),
+    })
+}
+
 fn main() -> Result<()> {
     env_logger::init();
     let options = Options::from_args();
@@ -75,58 +257,65 @@ fn main() -> Result<()> {
     match options.command {
         Command::Run {
             rounds,
-            core_size,
-            max_cycles,
-            max_processes,
-            max_warrior_length,
-            min_distance,
-            p_space,
+            core,
+            record,
+            trace,
+            stats,
             warrior,
         } => {
-            let warriors = warrior
-                .iter()
-                .map(|path| parse_warrior(path.as_path()))
-                .collect::<Result<Vec<Warrior>>>()?;
+            let rounds = rounds.unwrap_or(100);
+            let config = core.config();
 
-            let mut config = CoreConfig::default();
-            if let Some(core_size) = core_size {
-                config.core_size = core_size;
-            }
-            if let Some(max_cycles) = max_cycles {
-                config.max_cycles = max_cycles;
-            }
-            if let Some(max_processes) = max_processes {
-                config.max_processes = max_processes;
-            }
-            if let Some(max_warrior_length) = max_warrior_length {
-                config.max_warrior_length = max_warrior_length;
-            }
-            if let Some(min_distance) = min_distance {
-                config.min_distance = min_distance;
-            }
-            if let Some(p_space) = p_space {
-                config.p_space = p_space;
-            }
+            let parse_config = ParseConfig {
+                rounds,
+                ..core.parse_config()
+            };
+            let warriors = parse_warrior_files(&warrior, &parse_config)?;
 
             let mut scores: HashMap<WarriorID, (i32, i32, i32)> =
                 HashMap::with_capacity(warriors.len());
             let mut warrior_names = HashMap::with_capacity(warriors.len());
 
-            let rounds = rounds.unwrap_or(100);
-            for _ in 0..rounds {
-                let mut core = Core::new(config.clone());
+            let mut core = Core::new(config)?;
+            for round in 0..rounds {
+                core.reset();
                 core.load_warriors(&warriors)?;
                 for (id, warrior) in warriors.iter().enumerate() {
                     warrior_names.insert(id, warrior.metadata.name.to_owned());
                 }
-                let results = core.run();
+                let results = if round == 0 && (record.is_some() || trace.is_some()) {
+                    let mut recorder = Recorder::new();
+                    let mut tracer = trace
+                        .as_ref()
+                        .map(|path| -> Result<_> {
+                            let file = BufWriter::new(File::create(path)?);
+                            Ok(Tracer::new(file, &core, &warriors)?)
+                        })
+                        .transpose()?;
+
+                    let results = match tracer.as_mut() {
+                        Some(tracer) => core.run_with_observer(&mut Tee(&mut recorder, tracer))?,
+                        None => core.run_with_observer(&mut recorder)?,
+                    };
+
+                    if let Some(tracer) = tracer {
+                        tracer.finish()?;
+                    }
+                    if let Some(path) = &record {
+                        let replay = recorder.finish(&core, &warriors, &parse_config);
+                        replay.write_to(BufWriter::new(File::create(path)?))?;
+                    }
+                    results
+                } else {
+                    core.run()?
+                };
                 debug!("Battle Results after {} steps:", core.steps_taken());
-                for (id, r) in results {
+                for (&id, r) in &results {
                     let score = scores.entry(id).or_insert((0, 0, 0));
                     match r {
-                        corewars::core::BattleResult::Win => score.0 += 1,
-                        corewars::core::BattleResult::Loss(_) => score.1 += 1,
-                        corewars::core::BattleResult::Tie => score.2 += 1,
+                        BattleResult::Win => score.0 += 1,
+                        BattleResult::Loss(_) => score.1 += 1,
+                        BattleResult::Tie => score.2 += 1,
                     };
                     debug!(
                         "{}: {}",
@@ -136,6 +325,10 @@ fn main() -> Result<()> {
                         r
                     );
                 }
+                if stats {
+                    println!("Round {}:", round + 1);
+                    print_stats(&core, &warrior_names, &results);
+                }
             }
             for (id, (win, loss, tie)) in scores {
                 println!(
@@ -149,11 +342,299 @@ fn main() -> Result<()> {
                 );
             }
         }
+        Command::Replay { step, file } => {
+            let replay = Replay::read_from(BufReader::new(File::open(file)?))?;
+            let names: Vec<String> = replay
+                .parse_warriors()?
+                .iter()
+                .enumerate()
+                .map(|(id, warrior)| {
+                    warrior
+                        .metadata
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| id.to_string())
+                })
+                .collect();
+
+            let mut player = Player::new(&replay)?;
+            let mut input = io::stdin().lock().lines();
+            let mut cycles = 0;
+            while let Some((cycle, executed)) = player.step()? {
+                println!(
+                    "{:>6} {:<20} {:#06x} {}",
+                    cycles, names[cycle.warrior], cycle.program_counter, executed.instruction
+                );
+                for write in &cycle.writes {
+                    println!("{:>34} {:#06x} <- {}", "", write.offset, write.instruction);
+                }
+                cycles += 1;
+
+                if step {
+                    match input.next().transpose()? {
+                        Some(line) if line.trim() != "q" => {}
+                        _ => break,
+                    }
+                }
+            }
+            println!("Replayed {} cycles", cycles);
+        }
+        Command::Debug { core, warrior } => {
+            let warriors = parse_warrior_files(&warrior, &core.parse_config())?;
+
+            let mut core = Core::new(CoreConfig {
+                journal: true,
+                ..core.config()
+            })?;
+            core.load_warriors(&warriors)?;
+
+            let mut debugger = Debugger::new(core, &warriors);
+            debugger.run(io::stdin().lock(), io::stdout())?;
+        }
+        Command::Render {
+            output,
+            gif,
+            frames,
+            frame_interval,
+            columns,
+            scale,
+            replay,
+            core,
+            warrior,
+        } => {
+            let frame_interval = frame_interval.unwrap_or(1000).max(1);
+            let columns = columns.unwrap_or(100);
+            let scale = scale.unwrap_or(4);
+
+            let replay = replay
+                .map(|path| Replay::read_from(BufReader::new(File::open(path)?)))
+                .transpose()?;
+            let mut battle = match &replay {
+                Some(replay) => Battle::Replay(Player::new(replay)?),
+                None => {
+                    let warriors = parse_warrior_files(&warrior, &core.parse_config())?;
+                    let mut core = Core::new(CoreConfig {
+                        cell_stats: true,
+                        ..core.config()
+                    })?;
+                    core.load_warriors(&warriors)?;
+                    Battle::Run(core)
+                }
+            };
+
+            let heatmap = |core: &Core| Image::heatmap(core, columns, scale);
+
+            let first = heatmap(battle.core())?;
+            let mut animation = gif
+                .map(|path| {
+                    let file = BufWriter::new(File::create(path)?);
+                    Animation::new(file, first.width(), first.height(), 10)
+                })
+                .transpose()?;
+            if let Some(frames) = &frames {
+                fs::create_dir_all(frames)?;
+            }
+
+            let mut frame = 0;
+            let mut add_frame = |image: &Image| -> Result<()> {
+                if let Some(animation) = animation.as_mut() {
+                    animation.add_frame(image)?;
+                }
+                if let Some(frames) = &frames {
+                    let path = frames.join(format!("frame-{:05}.png", frame));
+                    image.write_png(BufWriter::new(File::create(path)?))?;
+                }
+                frame += 1;
+                Ok(())
+            };
+
+            add_frame(&first)?;
+            while battle.step()? {
+                if battle.core().steps_taken() % frame_interval == 0 {
+                    add_frame(&heatmap(battle.core())?)?;
+                }
+            }
+
+            let image = heatmap(battle.core())?;
+            if battle.core().steps_taken() % frame_interval != 0 {
+                add_frame(&image)?;
+            }
+            image.write_png(BufWriter::new(File::create(output)?))?;
+        }
+        Command::Watch {
+            speed,
+            core,
+            warrior,
+        } => {
+            let warriors = parse_warrior_files(&warrior, &core.parse_config())?;
+
+            let mut core = Core::new(core.config())?;
+            core.load_warriors(&warriors)?;
+
+            terminal::enable_raw_mode()?;
+            execute!(io::stdout(), EnterAlternateScreen, cursor::Hide)?;
+            let result = watch(&mut core, &warriors, speed.unwrap_or(16));
+            execute!(io::stdout(), cursor::Show, LeaveAlternateScreen)?;
+            terminal::disable_raw_mode()?;
+            result?;
+        }
     };
 
     Ok(())
 }
-fn parse_warrior(path: &Path) -> Result<Warrior> {
+/// A battle to render, either run in a new core or played back from a replay
+enum Battle<'a> {
+    Run(Core),
+    Replay(Player<'a>),
+}
+
+impl Battle<'_> {
+    fn core(&self) -> &Core {
+        match self {
+            Self::Run(core) => core,
+            Self::Replay(player) => player.core(),
+        }
+    }
+
+    /// Run a single cycle, returning whether the battle is still going
+    fn step(&mut self) -> Result<bool> {
+        match self {
+            Self::Run(core) => {
+                if core.is_finished() {
+                    return Ok(false);
+                }
+                core.step()?;
+                Ok(true)
+            }
+            Self::Replay(player) => Ok(player.step()?.is_some()),
+        }
+    }
+}
+
+/// Run a battle, drawing the core after every `speed` cycles until the user quits
+fn watch(core: &mut Core, warriors: &[Warrior], mut speed: usize) -> Result<()> {
+    const FRAME: Duration = Duration::from_millis(50);
+    const MAX_SPEED: usize = 1 << 16;
+
+    let mut visualiser = Visualiser::new(core, warriors);
+    let mut stdout = io::stdout();
+    let mut paused = false;
+
+    loop {
+        if !paused {
+            for _ in 0..speed {
+                if core.is_finished() {
+                    break;
+                }
+                core.step_with_observer(&mut visualiser)?;
+            }
+        }
+
+        let (width, height) = terminal::size()?;
+        let mut lines =
+            visualiser.render(core, width.into(), usize::from(height).saturating_sub(1));
+        let state = if core.is_finished() {
+            " (finished)"
+        } else if paused {
+            " (paused)"
+        } else {
+            ""
+        };
+        lines.push(format!(
+            "{} cycles/frame{}  [+/-] speed  [space] pause  [q] quit",
+            speed, state
+        ));
+
+        queue!(stdout, cursor::MoveTo(0, 0))?;
+        for (i, line) in lines.iter().enumerate() {
+            queue!(stdout, Clear(ClearType::UntilNewLine))?;
+            write!(stdout, "{}", line)?;
+            if i + 1 < lines.len() {
+                write!(stdout, "\r\n")?;
+            }
+        }
+        queue!(stdout, Clear(ClearType::FromCursorDown))?;
+        stdout.flush()?;
+
+        if event::poll(FRAME)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => break,
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break,
+                    KeyCode::Char('+') | KeyCode::Char('=') => speed = (speed * 2).min(MAX_SPEED),
+                    KeyCode::Char('-') => speed = (speed / 2).max(1),
+                    KeyCode::Char(' ') => paused = !paused,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_stats(
+    core: &Core,
+    names: &HashMap<WarriorID, Option<String>>,
+    results: &HashMap<WarriorID, BattleResult>,
+) {
+    let name = |id: WarriorID| names[&id].clone().unwrap_or_else(|| id.to_string());
+
+    for (id, stats) in core.warrior_stats().iter().enumerate() {
+        let outcome = match (&results[&id], stats.death_cycle) {
+            (BattleResult::Loss(err), Some(cycle)) => {
+                let killer = match stats.killed_by {
+                    Some(Killer::Warrior(killer)) if killer == id => "itself".to_owned(),
+                    Some(Killer::Warrior(killer)) => name(killer),
+                    Some(Killer::Unowned) => "empty core".to_owned(),
+                    None => "unknown".to_owned(),
+                };
+                format!("Loss at cycle {} ({}, killed by {})", cycle, err, killer)
+            }
+            (result, _) => result.to_string(),
+        };
+        println!(
+            "  {}: {}; executed {}, wrote {}, tasks {} peak / {} final",
+            name(id),
+            outcome,
+            stats.instructions_executed,
+            stats.cells_written,
+            stats.peak_tasks,
+            stats.final_tasks,
+        );
+    }
+}
+
+/// Parse every warrior in each of the input files, in order. Like pMARS,
+/// `WARRIORS` is the number of files.
+fn parse_warrior_files(paths: &[PathBuf], config: &ParseConfig) -> Result<Vec<Warrior>> {
+    let inputs = paths
+        .iter()
+        .map(|path| read_input(path))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Files may contain several warriors, which are all in the battle
+    let warrior_count: usize = inputs
+        .iter()
+        .map(|input| parser::count_warriors(input))
+        .sum();
+    let config = ParseConfig {
+        warriors: i32::try_from(warrior_count)?,
+        ..config.clone()
+    };
+
+    let mut warriors = Vec::new();
+    for (path, input) in paths.iter().zip(&inputs) {
+        warriors.extend(parse_warriors(path, input, &config)?);
+    }
+    Ok(warriors)
+}
+
+/// Read a warrior file, or stdin for "-"
+fn read_input(path: &Path) -> Result<String> {
     let mut input = String::new();
 
     if path == *IO_SENTINEL {
@@ -161,21 +642,54 @@ fn parse_warrior(path: &Path) -> Result<Warrior> {
     } else {
         input = fs::read_to_string(path)?;
     }
+    Ok(input)
+}
 
-    match parser::parse(input.as_str()) {
-        parser::Result::Ok(warrior, warnings) => {
+/// Parse the warriors in a file, each starting with a `;redcode` line
+fn parse_warriors(path: &Path, input: &str, config: &ParseConfig) -> Result<Vec<Warrior>> {
+    let print_warnings = |warnings: &[parser::Warning]| {
+        for warning in warnings {
+            print_diagnostic("warning", warning, warning.span(), path, input);
+        }
+    };
+
+    match parser::parse_all_with_config(input, config) {
+        parser::Result::Ok(warriors, warnings) => {
             print_warnings(&warnings);
-            Ok(warrior)
+            Ok(warriors)
         }
         parser::Result::Err(err, warnings) => {
             print_warnings(&warnings);
-            Err(anyhow!("parse failed: {}", err))
+            print_diagnostic("error", &err, err.span(), path, input);
+            Err(anyhow!("failed to parse {}", path.display()))
         }
     }
 }
 
-fn print_warnings(warnings: &[parser::Warning]) {
-    for warning in warnings.iter() {
-        eprintln!("Warning: {}", warning);
+/// Print a parser error or warning with the line of input it refers to, and
+/// a caret under the text in question, in the style of `rustc`
+fn print_diagnostic(
+    level: &str,
+    message: &dyn std::fmt::Display,
+    span: parser::Span,
+    path: &Path,
+    input: &str,
+) {
+    eprintln!("{}: {}", level, message);
+    eprintln!(" --> {}:{}", path.display(), span);
+
+    if let Some(source) = span.line.checked_sub(1).and_then(|i| input.lines().nth(i)) {
+        let number = span.line.to_string();
+        let gutter = " ".repeat(number.len());
+        let indent: String = source
+            .chars()
+            .take(span.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        eprintln!("{} |", gutter);
+        eprintln!("{} | {}", number, source);
+        eprintln!("{} | {}{}", gutter, indent, "^".repeat(span.len));
     }
+    eprintln!();
 }
@@ -66,19 +66,19 @@ fn apply_pointer(core: &mut Core, program_counter: Offset, field: &Field, eval_t
     let address_mode = field.address_mode;
     let pointer_location = program_counter + field.unwrap_value();
 
-    let pointed_to = core.get_offset(pointer_location);
+    let mut pointed_to = core.get_offset(pointer_location);
     let a_value = core.offset(pointed_to.a_field.unwrap_value());
     let b_value = core.offset(pointed_to.b_field.unwrap_value());
 
-    let mut_pointed_to = core.get_offset_mut(pointer_location);
-
     match (eval_time, address_mode) {
-        (EvalTime::Pre, PreDecIndirectA) => mut_pointed_to.a_field.set_value(a_value - 1),
-        (EvalTime::Pre, PreDecIndirectB) => mut_pointed_to.b_field.set_value(b_value - 1),
-        (EvalTime::Post, PostIncIndirectA) => mut_pointed_to.a_field.set_value(a_value + 1),
-        (EvalTime::Post, PostIncIndirectB) => mut_pointed_to.b_field.set_value(b_value + 1),
-        _ => {}
+        (EvalTime::Pre, PreDecIndirectA) => pointed_to.a_field.set_value(a_value - 1),
+        (EvalTime::Pre, PreDecIndirectB) => pointed_to.b_field.set_value(b_value - 1),
+        (EvalTime::Post, PostIncIndirectA) => pointed_to.a_field.set_value(a_value + 1),
+        (EvalTime::Post, PostIncIndirectB) => pointed_to.b_field.set_value(b_value + 1),
+        _ => return,
     }
+
+    core.set_offset(pointer_location, pointed_to);
 }
 
 #[cfg(test)]
@@ -96,23 +96,23 @@ mod tests {
     fn immediate_mode() {
         let core = build_core("dat #1, #2");
         let pc = core.offset(0);
-        let instruction = core.get_offset(pc).clone();
+        let instruction = core.get_offset(pc);
 
         assert_eq!(resolve_a_pointer(&core, pc), core.offset(0));
         assert_eq!(resolve_b_pointer(&core, pc), core.offset(0));
-        assert_eq!(core.get(0), &instruction);
+        assert_eq!(core.get(0), instruction);
     }
 
     #[test]
     fn direct_mode() {
         let core = build_core("dat $1, $2");
         let pc = core.offset(0);
-        let instruction = core.get_offset(pc).clone();
+        let instruction = core.get_offset(pc);
 
         assert_eq!(resolve_a_pointer(&core, pc), core.offset(1));
         assert_eq!(resolve_b_pointer(&core, pc), core.offset(2));
 
-        assert_eq!(core.get(0), &instruction);
+        assert_eq!(core.get(0), instruction);
     }
 
     #[test_case("*", 4, 7; "a")]
@@ -130,19 +130,19 @@ mod tests {
         ));
 
         let pc = core.offset(0);
-        let instruction = core.get_offset(pc).clone();
+        let instruction = core.get_offset(pc);
 
         assert_eq!(resolve_a_pointer(&core, pc), core.offset(expected_a));
         assert_eq!(resolve_b_pointer(&core, pc), core.offset(expected_b));
 
-        assert_eq!(core.get(0), &instruction);
+        assert_eq!(core.get(0), instruction);
         assert_eq!(
             core.get(1),
-            &Instruction::new(Opcode::Dat, Field::immediate(3), Field::immediate(4))
+            Instruction::new(Opcode::Dat, Field::immediate(3), Field::immediate(4))
         );
         assert_eq!(
             core.get(2),
-            &Instruction::new(Opcode::Dat, Field::immediate(5), Field::immediate(6))
+            Instruction::new(Opcode::Dat, Field::immediate(5), Field::immediate(6))
         );
     }
 
@@ -157,32 +157,32 @@ mod tests {
         );
 
         let pc = core.offset(0);
-        let instruction = core.get_offset(pc).clone();
+        let instruction = core.get_offset(pc);
 
         assert_eq!(resolve_a_pointer(&core, pc), core.offset(3));
         assert_eq!(resolve_b_pointer(&core, pc), core.offset(6));
 
-        assert_eq!(core.get(0), &instruction);
+        assert_eq!(core.get(0), instruction);
         assert_eq!(
             core.get(1),
-            &Instruction::new(Opcode::Dat, Field::immediate(3), Field::immediate(4))
+            Instruction::new(Opcode::Dat, Field::immediate(3), Field::immediate(4))
         );
         assert_eq!(
             core.get(2),
-            &Instruction::new(Opcode::Dat, Field::immediate(5), Field::immediate(6))
+            Instruction::new(Opcode::Dat, Field::immediate(5), Field::immediate(6))
         );
 
         apply_a_pointer(&mut core, pc, EvalTime::Pre);
         apply_b_pointer(&mut core, pc, EvalTime::Pre);
 
-        assert_eq!(core.get(0), &instruction);
+        assert_eq!(core.get(0), instruction);
         assert_eq!(
             core.get(1),
-            &Instruction::new(Opcode::Dat, Field::immediate(2), Field::immediate(4))
+            Instruction::new(Opcode::Dat, Field::immediate(2), Field::immediate(4))
         );
         assert_eq!(
             core.get(2),
-            &Instruction::new(Opcode::Dat, Field::immediate(4), Field::immediate(6))
+            Instruction::new(Opcode::Dat, Field::immediate(4), Field::immediate(6))
         );
     }
 
@@ -197,32 +197,32 @@ mod tests {
         );
 
         let pc = core.offset(0);
-        let instruction = core.get_offset(pc).clone();
+        let instruction = core.get_offset(pc);
 
         assert_eq!(resolve_a_pointer(&core, pc), core.offset(4));
         assert_eq!(resolve_b_pointer(&core, pc), core.offset(7));
 
-        assert_eq!(core.get(0), &instruction);
+        assert_eq!(core.get(0), instruction);
         assert_eq!(
             core.get(1),
-            &Instruction::new(Opcode::Dat, Field::immediate(3), Field::immediate(4))
+            Instruction::new(Opcode::Dat, Field::immediate(3), Field::immediate(4))
         );
         assert_eq!(
             core.get(2),
-            &Instruction::new(Opcode::Dat, Field::immediate(5), Field::immediate(6))
+            Instruction::new(Opcode::Dat, Field::immediate(5), Field::immediate(6))
         );
 
         apply_a_pointer(&mut core, pc, EvalTime::Pre);
         apply_b_pointer(&mut core, pc, EvalTime::Pre);
 
-        assert_eq!(core.get(0), &instruction);
+        assert_eq!(core.get(0), instruction);
         assert_eq!(
             core.get(1),
-            &Instruction::new(Opcode::Dat, Field::immediate(3), Field::immediate(3))
+            Instruction::new(Opcode::Dat, Field::immediate(3), Field::immediate(3))
         );
         assert_eq!(
             core.get(2),
-            &Instruction::new(Opcode::Dat, Field::immediate(5), Field::immediate(5))
+            Instruction::new(Opcode::Dat, Field::immediate(5), Field::immediate(5))
         );
     }
 
@@ -237,32 +237,32 @@ mod tests {
         );
 
         let pc = core.offset(0);
-        let instruction = core.get_offset(pc).clone();
+        let instruction = core.get_offset(pc);
 
         assert_eq!(resolve_a_pointer(&core, pc), core.offset(4));
         assert_eq!(resolve_b_pointer(&core, pc), core.offset(7));
 
-        assert_eq!(core.get(0), &instruction);
+        assert_eq!(core.get(0), instruction);
         assert_eq!(
             core.get(1),
-            &Instruction::new(Opcode::Dat, Field::immediate(3), Field::immediate(4))
+            Instruction::new(Opcode::Dat, Field::immediate(3), Field::immediate(4))
         );
         assert_eq!(
             core.get(2),
-            &Instruction::new(Opcode::Dat, Field::immediate(5), Field::immediate(6))
+            Instruction::new(Opcode::Dat, Field::immediate(5), Field::immediate(6))
         );
 
         apply_a_pointer(&mut core, pc, EvalTime::Post);
         apply_b_pointer(&mut core, pc, EvalTime::Post);
 
-        assert_eq!(core.get(0), &instruction);
+        assert_eq!(core.get(0), instruction);
         assert_eq!(
             core.get(1),
-            &Instruction::new(Opcode::Dat, Field::immediate(4), Field::immediate(4))
+            Instruction::new(Opcode::Dat, Field::immediate(4), Field::immediate(4))
         );
         assert_eq!(
             core.get(2),
-            &Instruction::new(Opcode::Dat, Field::immediate(6), Field::immediate(6))
+            Instruction::new(Opcode::Dat, Field::immediate(6), Field::immediate(6))
         );
     }
 
@@ -277,32 +277,32 @@ mod tests {
         );
 
         let pc = core.offset(0);
-        let instruction = core.get_offset(pc).clone();
+        let instruction = core.get_offset(pc);
 
         assert_eq!(resolve_a_pointer(&core, pc), core.offset(5));
         assert_eq!(resolve_b_pointer(&core, pc), core.offset(8));
 
-        assert_eq!(core.get(0), &instruction);
+        assert_eq!(core.get(0), instruction);
         assert_eq!(
             core.get(1),
-            &Instruction::new(Opcode::Dat, Field::immediate(3), Field::immediate(4))
+            Instruction::new(Opcode::Dat, Field::immediate(3), Field::immediate(4))
         );
         assert_eq!(
             core.get(2),
-            &Instruction::new(Opcode::Dat, Field::immediate(5), Field::immediate(6))
+            Instruction::new(Opcode::Dat, Field::immediate(5), Field::immediate(6))
         );
 
         apply_a_pointer(&mut core, pc, EvalTime::Post);
         apply_b_pointer(&mut core, pc, EvalTime::Post);
 
-        assert_eq!(core.get(0), &instruction);
+        assert_eq!(core.get(0), instruction);
         assert_eq!(
             core.get(1),
-            &Instruction::new(Opcode::Dat, Field::immediate(3), Field::immediate(5))
+            Instruction::new(Opcode::Dat, Field::immediate(3), Field::immediate(5))
         );
         assert_eq!(
             core.get(2),
-            &Instruction::new(Opcode::Dat, Field::immediate(5), Field::immediate(7))
+            Instruction::new(Opcode::Dat, Field::immediate(5), Field::immediate(7))
         );
     }
 }
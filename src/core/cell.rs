@@ -0,0 +1,254 @@
+//! A compact encoding of an [`Instruction`](Instruction), used to store the
+//! contents of the core.
+//!
+//! A decoded instruction takes around 60 bytes, since each field value may be
+//! an unresolved label. Once loaded into a core, every value is a resolved
+//! offset, so the whole instruction fits into 64 bits. This keeps a core with
+//! millions of cells within a few megabytes.
+
+use crate::load_file::{AddressMode, Field, Instruction, Modifier, Opcode, Value};
+
+const OPCODE_BITS: u32 = 5;
+const MODIFIER_BITS: u32 = 3;
+const MODE_BITS: u32 = 3;
+const VALUE_BITS: u32 = 25;
+
+const MODIFIER_SHIFT: u32 = OPCODE_BITS;
+const A_MODE_SHIFT: u32 = MODIFIER_SHIFT + MODIFIER_BITS;
+const B_MODE_SHIFT: u32 = A_MODE_SHIFT + MODE_BITS;
+const A_VALUE_SHIFT: u32 = B_MODE_SHIFT + MODE_BITS;
+const B_VALUE_SHIFT: u32 = A_VALUE_SHIFT + VALUE_BITS;
+
+/// The largest core size that can be represented, since every field value
+/// must fit in the bits available for it.
+pub const MAX_CORE_SIZE: i32 = 1 << VALUE_BITS;
+
+/// A single cell of the core, packed into a single integer:
+///
+/// | Bits    | Contents |
+/// |---------|----------|
+/// | 0..5    | opcode   |
+/// | 5..8    | modifier |
+/// | 8..11   | A mode   |
+/// | 11..14  | B mode   |
+/// | 14..39  | A value  |
+/// | 39..64  | B value  |
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Cell(u64);
+
+impl Cell {
+    /// Encode an instruction. Both field values must be literals in the range
+    /// `0..MAX_CORE_SIZE`, i.e. normalized to the core.
+    ///
+    /// # Panics
+    /// If a field value is an unresolved label, or out of range. Packing an
+    /// out of range value would corrupt the other fields of the cell.
+    pub fn encode(instruction: &Instruction) -> Self {
+        let a_value = instruction.a_field.unwrap_value();
+        let b_value = instruction.b_field.unwrap_value();
+        assert!(
+            (0..MAX_CORE_SIZE).contains(&a_value) && (0..MAX_CORE_SIZE).contains(&b_value),
+            "cannot encode unnormalized instruction {:?}",
+            instruction,
+        );
+
+        Self(
+            instruction.opcode as u64
+                | (instruction.modifier as u64) << MODIFIER_SHIFT
+                | (instruction.a_field.address_mode as u64) << A_MODE_SHIFT
+                | (instruction.b_field.address_mode as u64) << B_MODE_SHIFT
+                | (a_value as u64) << A_VALUE_SHIFT
+                | (b_value as u64) << B_VALUE_SHIFT,
+        )
+    }
+
+    /// Decode the instruction stored in this cell
+    pub fn decode(self) -> Instruction {
+        Instruction {
+            opcode: self.opcode(),
+            modifier: self.modifier(),
+            a_field: Field {
+                address_mode: self.a_mode(),
+                value: Value::Literal(self.a_value()),
+            },
+            b_field: Field {
+                address_mode: self.b_mode(),
+                value: Value::Literal(self.b_value()),
+            },
+        }
+    }
+
+    pub fn opcode(self) -> Opcode {
+        Opcode::iter_values().as_slice()[self.bits(0, OPCODE_BITS)]
+    }
+
+    pub fn modifier(self) -> Modifier {
+        Modifier::iter_values().as_slice()[self.bits(MODIFIER_SHIFT, MODIFIER_BITS)]
+    }
+
+    pub fn a_mode(self) -> AddressMode {
+        AddressMode::iter_values().as_slice()[self.bits(A_MODE_SHIFT, MODE_BITS)]
+    }
+
+    pub fn b_mode(self) -> AddressMode {
+        AddressMode::iter_values().as_slice()[self.bits(B_MODE_SHIFT, MODE_BITS)]
+    }
+
+    pub fn a_value(self) -> i32 {
+        self.bits(A_VALUE_SHIFT, VALUE_BITS) as i32
+    }
+
+    pub fn b_value(self) -> i32 {
+        self.bits(B_VALUE_SHIFT, VALUE_BITS) as i32
+    }
+
+    /// A copy of this cell with a different A value, which must be normalized
+    pub fn with_a_value(self, value: i32) -> Self {
+        self.with_bits(A_VALUE_SHIFT, VALUE_BITS, value as u64)
+    }
+
+    /// A copy of this cell with a different B value, which must be normalized
+    pub fn with_b_value(self, value: i32) -> Self {
+        self.with_bits(B_VALUE_SHIFT, VALUE_BITS, value as u64)
+    }
+
+    /// Whether both the A and B values of two cells are equal, regardless of
+    /// their opcode, modifier and address modes
+    pub fn values_equal(self, other: Self) -> bool {
+        (self.0 ^ other.0) >> A_VALUE_SHIFT == 0
+    }
+
+    /// The raw encoded value of the cell
+    pub fn to_bits(self) -> u64 {
+        self.0
+    }
+
+    fn bits(self, shift: u32, len: u32) -> usize {
+        ((self.0 >> shift) & ((1 << len) - 1)) as usize
+    }
+
+    /// # Panics
+    /// If the value doesn't fit in `len` bits, which would corrupt the other
+    /// fields of the cell.
+    fn with_bits(self, shift: u32, len: u32, value: u64) -> Self {
+        assert!(
+            value < 1 << len,
+            "value {} does not fit in {} bits",
+            value,
+            len
+        );
+        let mask = ((1 << len) - 1) << shift;
+        Self((self.0 & !mask) | value << shift)
+    }
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self::encode(&Instruction::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::iproduct;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn fits_in_64_bits() {
+        assert_eq!(B_VALUE_SHIFT + VALUE_BITS, u64::BITS);
+        assert!(Opcode::iter_values().len() <= 1 << OPCODE_BITS);
+        assert!(Modifier::iter_values().len() <= 1 << MODIFIER_BITS);
+        assert!(AddressMode::iter_values().len() <= 1 << MODE_BITS);
+        assert_eq!(std::mem::size_of::<Cell>(), 8);
+    }
+
+    #[test]
+    fn round_trip() {
+        for (&opcode, &modifier, &a_mode, &b_mode) in iproduct!(
+            Opcode::iter_values(),
+            Modifier::iter_values(),
+            AddressMode::iter_values(),
+            AddressMode::iter_values()
+        ) {
+            for &(a_value, b_value) in &[(0, 0), (MAX_CORE_SIZE - 1, 1), (7999, MAX_CORE_SIZE - 1)]
+            {
+                let instruction = Instruction {
+                    opcode,
+                    modifier,
+                    a_field: Field {
+                        address_mode: a_mode,
+                        value: Value::Literal(a_value),
+                    },
+                    b_field: Field {
+                        address_mode: b_mode,
+                        value: Value::Literal(b_value),
+                    },
+                };
+
+                assert_eq!(Cell::encode(&instruction).decode(), instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn with_values() {
+        let cell = Cell::encode(&Instruction::new(
+            Opcode::Mov,
+            Field::immediate(1),
+            Field::direct(2),
+        ));
+
+        let changed = cell.with_a_value(MAX_CORE_SIZE - 1).with_b_value(0);
+        assert_eq!(
+            changed.decode(),
+            Instruction::new(
+                Opcode::Mov,
+                Field::immediate(MAX_CORE_SIZE - 1),
+                Field::direct(0)
+            )
+        );
+        assert!(!changed.values_equal(cell));
+        assert!(changed.with_a_value(1).with_b_value(2).values_equal(cell));
+
+        let other = Cell::encode(&Instruction::new(
+            Opcode::Dat,
+            Field::direct(1),
+            Field::immediate(2),
+        ));
+        assert!(other.values_equal(cell));
+        assert_ne!(other, cell);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot encode unnormalized instruction")]
+    fn encode_negative_value() {
+        Cell::encode(&Instruction::new(
+            Opcode::Mov,
+            Field::direct(0),
+            Field::direct(-1),
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot encode unnormalized instruction")]
+    fn encode_value_too_large() {
+        Cell::encode(&Instruction::new(
+            Opcode::Mov,
+            Field::direct(MAX_CORE_SIZE),
+            Field::direct(1),
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit in")]
+    fn with_negative_value() {
+        let _ = Cell::default().with_b_value(-1);
+    }
+
+    #[test]
+    fn default_is_dat() {
+        assert_eq!(Cell::default().decode(), Instruction::default());
+    }
+}
@@ -0,0 +1,188 @@
+//! Detection of battles that can no longer change their outcome.
+//!
+//! Execution is translation invariant, since every address in Redcode is
+//! relative. If the core and process queue at some step are equal to an earlier
+//! state rotated by some distance, the battle will keep repeating that cycle
+//! forever. No warrior can be eliminated during a repeat (the queue contains the
+//! same warriors), so the result at the timeout is already known.
+//!
+//! Repeats are found using Brent's cycle detection algorithm: a snapshot is
+//! taken at steps that are powers of two, and each following step is compared
+//! against it. To keep the comparison cheap, the core maintains a fingerprint
+//! of its instructions which doesn't change when they are rotated, and the
+//! full comparison is only made when the fingerprints match.
+
+use crate::load_file::Offset;
+
+use crate::core::cell::Cell;
+use crate::core::process::Queue;
+use crate::core::WarriorID;
+
+/// Detects a repeated core state. Memory for the snapshot is reused across
+/// battles.
+#[derive(Debug, Default)]
+pub struct Detector {
+    /// The core at the time of the snapshot
+    instructions: Vec<Cell>,
+
+    /// The process queue at the time of the snapshot, from front to back
+    queue: Vec<(WarriorID, Offset)>,
+
+    /// The fingerprint of the core at the time of the snapshot
+    fingerprint: u64,
+
+    /// Whether a snapshot has been taken since the last reset
+    has_snapshot: bool,
+
+    /// The number of steps to compare against the snapshot before taking a new one
+    power: usize,
+
+    /// The number of steps since the snapshot was taken
+    distance: usize,
+}
+
+impl Detector {
+    /// Create a new detector, without any snapshot
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget the current snapshot, e.g. when starting a new battle
+    pub fn reset(&mut self) {
+        self.has_snapshot = false;
+    }
+
+    /// Check the current state of a core, returning true if it is a repeat
+    /// (up to a rotation) of a previous state. `fingerprint` must be the
+    /// [`fingerprint`] of `instructions`.
+    pub fn check(&mut self, fingerprint: u64, instructions: &[Cell], queue: &Queue) -> bool {
+        if !self.has_snapshot {
+            self.snapshot(fingerprint, instructions, queue);
+            self.has_snapshot = true;
+            self.power = 1;
+            return false;
+        }
+
+        self.distance += 1;
+        if fingerprint == self.fingerprint && self.matches(instructions, queue) {
+            return true;
+        }
+
+        if self.distance == self.power {
+            self.snapshot(fingerprint, instructions, queue);
+            self.power *= 2;
+        }
+
+        false
+    }
+
+    fn snapshot(&mut self, fingerprint: u64, instructions: &[Cell], queue: &Queue) {
+        self.fingerprint = fingerprint;
+        self.instructions.clear();
+        self.instructions.extend_from_slice(instructions);
+        self.queue.clear();
+        self.queue
+            .extend(queue.iter().map(|entry| (entry.id, entry.offset)));
+        self.distance = 0;
+    }
+
+    /// Whether the given state is the snapshot, rotated by some distance
+    fn matches(&self, instructions: &[Cell], queue: &Queue) -> bool {
+        if queue.len() != self.queue.len() || instructions.len() != self.instructions.len() {
+            return false;
+        }
+
+        let (snapshot_front, current_front) = match (self.queue.first(), queue.iter().next()) {
+            (Some(snapshot_front), Some(current_front)) => (snapshot_front, current_front),
+            _ => return false,
+        };
+        let rotation = current_front.offset - snapshot_front.1;
+
+        let queue_matches = self
+            .queue
+            .iter()
+            .zip(queue.iter())
+            .all(|(&(id, offset), entry)| id == entry.id && offset + rotation == entry.offset);
+        if !queue_matches {
+            return false;
+        }
+
+        // Start comparing from the front of the queue, since cells near the
+        // running processes are the most likely to differ
+        let len = self.instructions.len();
+        let start = snapshot_front.1.value() as usize;
+        let rotation = rotation.value() as usize;
+        (0..len).all(|i| {
+            let index = (start + i) % len;
+            self.instructions[index] == instructions[(index + rotation) % len]
+        })
+    }
+}
+
+/// Calculate the fingerprint of a core. This is the same for any rotation of
+/// the instructions.
+pub fn fingerprint(instructions: &[Cell]) -> u64 {
+    instructions
+        .iter()
+        .fold(0, |fingerprint, &cell| fingerprint.wrapping_add(hash(cell)))
+}
+
+/// Update the fingerprint of a core after replacing `old` with `new`.
+pub fn update_fingerprint(fingerprint: u64, old: Cell, new: Cell) -> u64 {
+    fingerprint.wrapping_sub(hash(old)).wrapping_add(hash(new))
+}
+
+/// A cheap hash of a single cell, using the finalizer from `SplitMix64` to
+/// spread the bits of the cell across the output
+fn hash(cell: Cell) -> u64 {
+    let mut value = cell.to_bits();
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::load_file::{Field, Instruction, Opcode};
+
+    #[test]
+    fn fingerprint_ignores_rotation() {
+        let instructions = vec![
+            Cell::encode(&Instruction::new(
+                Opcode::Mov,
+                Field::direct(0),
+                Field::direct(1),
+            )),
+            Cell::default(),
+            Cell::encode(&Instruction::new(
+                Opcode::Jmp,
+                Field::direct(1),
+                Field::immediate(0),
+            )),
+            Cell::default(),
+        ];
+        let mut rotated = instructions.clone();
+        rotated.rotate_left(3);
+
+        assert_eq!(fingerprint(&instructions), fingerprint(&rotated));
+    }
+
+    #[test]
+    fn update_matches_fingerprint() {
+        let mut instructions = vec![Cell::default(); 4];
+        let before = fingerprint(&instructions);
+
+        let new = Cell::encode(&Instruction::new(
+            Opcode::Mov,
+            Field::direct(0),
+            Field::direct(1),
+        ));
+        let updated = update_fingerprint(before, instructions[2], new);
+        instructions[2] = new;
+
+        assert_ne!(before, updated);
+        assert_eq!(fingerprint(&instructions), updated);
+    }
+}
@@ -0,0 +1,562 @@
+//! A specialised execution path, which dispatches on a pre-decoded key made
+//! from an instruction's opcode, modifier and address modes.
+//!
+//! This is similar in spirit to the dispatch used by fast MARS implementations
+//! like exMARS. Instead of matching on the opcode and then the modifier with
+//! closures (see [`opcode::execute`](super::opcode::execute)), the key selects
+//! an implementation that was monomorphised for that opcode and modifier. The
+//! address modes are decoded from the same key, but each operand is still
+//! evaluated by matching on its mode.
+//!
+//! Operands are read and written as packed [`Cell`]s, without decoding them to
+//! full [`Instruction`]s. In particular, comparing whole instructions for
+//! `CMP.I`, `SEQ.I` and `SNE.I` (used heavily by scanners) is a single
+//! integer comparison.
+//!
+//! This must behave identically to the generic path, including the statistics
+//! and observer events of each step, which is checked by the differential test
+//! at the bottom of this module.
+
+use crate::load_file::{AddressMode, Modifier, Offset, Opcode};
+
+use crate::core::cell::Cell;
+use crate::core::opcode::Executed;
+use crate::core::process;
+use crate::core::Core;
+
+const NUM_MODIFIERS: u16 = 7;
+const NUM_MODES: u16 = 8;
+
+const ADDRESS_MODES: [AddressMode; NUM_MODES as usize] = [
+    AddressMode::Immediate,
+    AddressMode::Direct,
+    AddressMode::IndirectA,
+    AddressMode::IndirectB,
+    AddressMode::PreDecIndirectA,
+    AddressMode::PreDecIndirectB,
+    AddressMode::PostIncIndirectA,
+    AddressMode::PostIncIndirectB,
+];
+
+/// The decoded opcode, modifier and address modes of an instruction, packed
+/// into a single integer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Key(u16);
+
+impl Key {
+    /// Decode the key for a given cell
+    pub fn decode(cell: Cell) -> Self {
+        let operation = cell.opcode() as u16 * NUM_MODIFIERS + cell.modifier() as u16;
+        let modes = cell.a_mode() as u16 * NUM_MODES + cell.b_mode() as u16;
+
+        Self(operation * NUM_MODES * NUM_MODES + modes)
+    }
+
+    /// The index of the opcode and modifier implementation in [`OPERATIONS`]
+    fn operation(self) -> usize {
+        (self.0 / (NUM_MODES * NUM_MODES)) as usize
+    }
+
+    fn a_mode(self) -> AddressMode {
+        ADDRESS_MODES[(self.0 / NUM_MODES % NUM_MODES) as usize]
+    }
+
+    fn b_mode(self) -> AddressMode {
+        ADDRESS_MODES[(self.0 % NUM_MODES) as usize]
+    }
+}
+
+/// The "registers" used while executing an instruction: the resolved pointers
+/// and a buffered copy of the cells they pointed to.
+struct Registers {
+    program_counter: Offset,
+    a_ptr: Offset,
+    b_ptr: Offset,
+    a_value: Cell,
+    b_value: Cell,
+}
+
+/// Execute the instruction at the given program counter, using its decoded key
+/// to pick the implementation.
+pub fn execute(core: &mut Core, program_counter: Offset) -> Result<Executed, process::Error> {
+    let key = Key::decode(core.cell(program_counter));
+
+    // NOTE: the order of evaluation is significant here, see modifier::Executor::new.
+    // The B field is read again after evaluating the A operand, since the
+    // A operand increment may have modified it.
+    let a_field = core.cell(program_counter).a_value();
+    let (a_ptr, a_value) = evaluate_operand(core, program_counter, key.a_mode(), a_field);
+
+    let b_field = core.cell(program_counter).b_value();
+    let (b_ptr, b_value) = evaluate_operand(core, program_counter, key.b_mode(), b_field);
+
+    let registers = Registers {
+        program_counter,
+        a_ptr,
+        b_ptr,
+        a_value,
+        b_value,
+    };
+
+    OPERATIONS[key.operation()](core, &registers)
+}
+
+/// Resolve an operand's pointer, applying any pre-decrement or post-increment,
+/// and buffer the cell it points to.
+fn evaluate_operand(
+    core: &mut Core,
+    program_counter: Offset,
+    address_mode: AddressMode,
+    field_value: i32,
+) -> (Offset, Cell) {
+    use AddressMode::{
+        Direct, Immediate, IndirectA, IndirectB, PostIncIndirectA, PostIncIndirectB,
+        PreDecIndirectA, PreDecIndirectB,
+    };
+
+    let pointer = program_counter + field_value;
+
+    let target = match address_mode {
+        Immediate => program_counter,
+        Direct => pointer,
+        IndirectA | PostIncIndirectA => pointer + core.cell(pointer).a_value(),
+        IndirectB | PostIncIndirectB => pointer + core.cell(pointer).b_value(),
+        PreDecIndirectA => {
+            let pointed_to = core.cell(pointer);
+            let decremented = core.offset(pointed_to.a_value() - 1);
+            core.set_cell(pointer, pointed_to.with_a_value(decremented.value()));
+            pointer + decremented
+        }
+        PreDecIndirectB => {
+            let pointed_to = core.cell(pointer);
+            let decremented = core.offset(pointed_to.b_value() - 1);
+            core.set_cell(pointer, pointed_to.with_b_value(decremented.value()));
+            pointer + decremented
+        }
+    };
+
+    let value = core.cell(target);
+    core.record_read(target);
+
+    match address_mode {
+        PostIncIndirectA => {
+            let pointed_to = core.cell(pointer);
+            let incremented = core.offset(pointed_to.a_value() + 1);
+            core.set_cell(pointer, pointed_to.with_a_value(incremented.value()));
+        }
+        PostIncIndirectB => {
+            let pointed_to = core.cell(pointer);
+            let incremented = core.offset(pointed_to.b_value() + 1);
+            core.set_cell(pointer, pointed_to.with_b_value(incremented.value()));
+        }
+        _ => {}
+    }
+
+    (target, value)
+}
+
+type Operation = fn(&mut Core, &Registers) -> Result<Executed, process::Error>;
+
+/// Build the table of operations, with one monomorphised entry per opcode and
+/// modifier, in the same order as [`Key::operation`].
+macro_rules! operations {
+    ($($opcode:ident),* $(,)?) => {
+        [$(
+            operation::<{ Opcode::$opcode as u8 }, { Modifier::A as u8 }>,
+            operation::<{ Opcode::$opcode as u8 }, { Modifier::B as u8 }>,
+            operation::<{ Opcode::$opcode as u8 }, { Modifier::AB as u8 }>,
+            operation::<{ Opcode::$opcode as u8 }, { Modifier::BA as u8 }>,
+            operation::<{ Opcode::$opcode as u8 }, { Modifier::F as u8 }>,
+            operation::<{ Opcode::$opcode as u8 }, { Modifier::X as u8 }>,
+            operation::<{ Opcode::$opcode as u8 }, { Modifier::I as u8 }>,
+        )*]
+    };
+}
+
+// NOTE: this must list opcodes in the same order as their declaration
+static OPERATIONS: [Operation; 19 * NUM_MODIFIERS as usize] = operations![
+    Add, Cmp, Dat, Div, Djn, Jmn, Jmp, Jmz, Ldp, Mod, Mov, Mul, Nop, Seq, Slt, Sne, Spl, Stp, Sub,
+];
+
+/// The pairs of (A, B) field values each modifier operates on, and whether the
+/// result is stored in the A or B field of the B target.
+#[derive(Copy, Clone)]
+enum Target {
+    A,
+    B,
+}
+
+struct Pair {
+    a: Offset,
+    b: Offset,
+    target: Target,
+}
+
+/// Get the field pairs that a given modifier operates on
+fn pairs<const MODIFIER: u8>(core: &Core, registers: &Registers) -> [Option<Pair>; 2] {
+    let offset = |value: i32| core.offset(value);
+
+    let a_a = offset(registers.a_value.a_value());
+    let a_b = offset(registers.a_value.b_value());
+    let b_a = offset(registers.b_value.a_value());
+    let b_b = offset(registers.b_value.b_value());
+
+    let pair = |a, b, target| Some(Pair { a, b, target });
+
+    if MODIFIER == Modifier::A as u8 {
+        [pair(a_a, b_a, Target::A), None]
+    } else if MODIFIER == Modifier::B as u8 {
+        [pair(a_b, b_b, Target::B), None]
+    } else if MODIFIER == Modifier::AB as u8 {
+        [pair(a_a, b_b, Target::B), None]
+    } else if MODIFIER == Modifier::BA as u8 {
+        [pair(a_b, b_a, Target::A), None]
+    } else if MODIFIER == Modifier::X as u8 {
+        [pair(a_b, b_a, Target::A), pair(a_a, b_b, Target::B)]
+    } else {
+        // F and I
+        [pair(a_a, b_a, Target::A), pair(a_b, b_b, Target::B)]
+    }
+}
+
+/// Write results to the fields of the B target. Like the generic path, the
+/// target is written once for the whole instruction, and only if there are
+/// any results.
+fn write(
+    core: &mut Core,
+    registers: &Registers,
+    results: impl IntoIterator<Item = (Target, Offset)>,
+) {
+    let mut b_target = None;
+    for (target, value) in results {
+        let cell = b_target.unwrap_or_else(|| core.cell(registers.b_ptr));
+        b_target = Some(match target {
+            Target::A => cell.with_a_value(value.value()),
+            Target::B => cell.with_b_value(value.value()),
+        });
+    }
+    if let Some(b_target) = b_target {
+        core.set_cell(registers.b_ptr, b_target);
+    }
+}
+
+/// Whether the instructions being compared are equal, as used by `CMP`, `SEQ`
+/// and `SNE`. With the `I` modifier the whole instruction is compared as well.
+fn compare<const MODIFIER: u8>(core: &Core, registers: &Registers) -> bool {
+    if MODIFIER == Modifier::I as u8 {
+        // The fields are compared against the buffered B operand, but the
+        // whole instruction against the current B target, which may differ
+        // if it was changed by evaluating the B operand
+        registers.a_value.values_equal(registers.b_value)
+            && registers.a_value == core.cell(registers.b_ptr)
+    } else {
+        pairs::<MODIFIER>(core, registers)
+            .iter()
+            .flatten()
+            .all(|pair| pair.a == pair.b)
+    }
+}
+
+/// The implementation of an opcode and modifier. The opcode and modifier are
+/// const generic parameters, so each branch is resolved at compile time.
+#[allow(clippy::too_many_lines)]
+fn operation<const OPCODE: u8, const MODIFIER: u8>(
+    core: &mut Core,
+    registers: &Registers,
+) -> Result<Executed, process::Error> {
+    const fn is(opcode: u8, other: Opcode) -> bool {
+        opcode == other as u8
+    }
+
+    let zero = core.offset(0);
+    let skip_one = core.offset(2);
+    let jump_offset = registers.a_ptr - registers.program_counter;
+
+    let mut program_counter_offset = None;
+
+    if is(OPCODE, Opcode::Dat) {
+        return Err(process::Error::ExecuteDat(registers.program_counter));
+    } else if is(OPCODE, Opcode::Mov) {
+        if MODIFIER == Modifier::I as u8 {
+            core.set_cell(registers.b_ptr, registers.a_value);
+        } else {
+            let pairs = pairs::<MODIFIER>(core, registers);
+            write(
+                core,
+                registers,
+                pairs
+                    .into_iter()
+                    .flatten()
+                    .map(|pair| (pair.target, pair.a)),
+            );
+        }
+    } else if is(OPCODE, Opcode::Nop) {
+        // Nothing to do
+    } else if is(OPCODE, Opcode::Add) || is(OPCODE, Opcode::Sub) || is(OPCODE, Opcode::Mul) {
+        let pairs = pairs::<MODIFIER>(core, registers);
+        let results = pairs.into_iter().flatten().map(|pair| {
+            let result = if is(OPCODE, Opcode::Add) {
+                pair.a + pair.b
+            } else if is(OPCODE, Opcode::Sub) {
+                pair.b - pair.a
+            } else {
+                pair.a * pair.b
+            };
+            (pair.target, result)
+        });
+        write(core, registers, results);
+    } else if is(OPCODE, Opcode::Div) || is(OPCODE, Opcode::Mod) {
+        let mut result = Ok(());
+        let pairs = pairs::<MODIFIER>(core, registers);
+        let results = pairs.into_iter().flatten().filter_map(|pair| {
+            if pair.b == zero {
+                result = Err(process::Error::DivideByZero);
+                None
+            } else if is(OPCODE, Opcode::Div) {
+                Some((pair.target, pair.a / pair.b))
+            } else {
+                Some((pair.target, pair.a % pair.b))
+            }
+        });
+        write(core, registers, results);
+        result?;
+    } else if is(OPCODE, Opcode::Cmp) || is(OPCODE, Opcode::Seq) {
+        if compare::<MODIFIER>(core, registers) {
+            program_counter_offset = Some(skip_one);
+        }
+    } else if is(OPCODE, Opcode::Sne) {
+        if !compare::<MODIFIER>(core, registers) {
+            program_counter_offset = Some(skip_one);
+        }
+    } else if is(OPCODE, Opcode::Slt) {
+        if pairs::<MODIFIER>(core, registers)
+            .iter()
+            .flatten()
+            .all(|pair| pair.a.value() < pair.b.value())
+        {
+            program_counter_offset = Some(skip_one);
+        }
+    } else if is(OPCODE, Opcode::Djn) {
+        let pairs = pairs::<MODIFIER>(core, registers);
+        let results = pairs.into_iter().flatten().map(|pair| {
+            let decremented = pair.b - 1_i32;
+            if decremented != zero {
+                program_counter_offset = Some(jump_offset);
+            }
+            (pair.target, decremented)
+        });
+        write(core, registers, results);
+    } else if is(OPCODE, Opcode::Jmn) {
+        if pairs::<MODIFIER>(core, registers)
+            .iter()
+            .flatten()
+            .any(|pair| pair.b != zero)
+        {
+            program_counter_offset = Some(jump_offset);
+        }
+    } else if is(OPCODE, Opcode::Jmz) {
+        if pairs::<MODIFIER>(core, registers)
+            .iter()
+            .flatten()
+            .any(|pair| pair.b == zero)
+        {
+            program_counter_offset = Some(jump_offset);
+        }
+    } else if is(OPCODE, Opcode::Jmp) || is(OPCODE, Opcode::Spl) {
+        program_counter_offset = Some(jump_offset);
+    } else {
+        // P-space opcodes
+        let opcode = if is(OPCODE, Opcode::Ldp) {
+            Opcode::Ldp
+        } else {
+            Opcode::Stp
+        };
+        return Err(process::Error::UnsupportedOpcode(opcode));
+    }
+
+    Ok(Executed {
+        program_counter_offset,
+        should_split: is(OPCODE, Opcode::Spl),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::iproduct;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    use crate::core::{CoreConfig, CoreObserver, Dispatch, Step, WarriorID};
+    use crate::load_file::{Field, Instruction, Program, Warrior};
+
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn decode_key() {
+        for (&opcode, &modifier, &a_mode, &b_mode) in iproduct!(
+            Opcode::iter_values(),
+            Modifier::iter_values(),
+            AddressMode::iter_values(),
+            AddressMode::iter_values()
+        ) {
+            let instruction = Instruction {
+                opcode,
+                modifier,
+                a_field: Field {
+                    address_mode: a_mode,
+                    value: 0.into(),
+                },
+                b_field: Field {
+                    address_mode: b_mode,
+                    value: 0.into(),
+                },
+            };
+            let key = Key::decode(Cell::encode(&instruction));
+
+            assert_eq!(key.operation(), opcode as usize * 7 + modifier as usize);
+            assert_eq!(key.a_mode(), a_mode);
+            assert_eq!(key.b_mode(), b_mode);
+        }
+    }
+
+    fn random_instruction(rng: &mut StdRng, core_size: i32) -> Instruction {
+        let opcodes: Vec<Opcode> = Opcode::iter_values().copied().collect();
+        let modifiers: Vec<Modifier> = Modifier::iter_values().copied().collect();
+        let modes: Vec<AddressMode> = AddressMode::iter_values().copied().collect();
+
+        let field = |rng: &mut StdRng| Field {
+            address_mode: modes[rng.gen_range(0..modes.len())],
+            value: rng.gen_range(0..core_size).into(),
+        };
+
+        Instruction {
+            opcode: opcodes[rng.gen_range(0..opcodes.len())],
+            modifier: modifiers[rng.gen_range(0..modifiers.len())],
+            a_field: field(rng),
+            b_field: field(rng),
+        }
+    }
+
+    /// Records every event reported by a step, to compare both dispatches
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl CoreObserver for Events {
+        fn instruction_executed(&mut self, step: &Step) {
+            self.0.push(format!("executed {:?}", step));
+        }
+
+        fn cell_read(&mut self, warrior: WarriorID, offset: Offset) {
+            self.0.push(format!("{} read {}", warrior, offset));
+        }
+
+        fn cell_written(
+            &mut self,
+            warrior: WarriorID,
+            offset: Offset,
+            old: &Instruction,
+            new: &Instruction,
+        ) {
+            self.0.push(format!(
+                "{} wrote {} at {}, was {}",
+                warrior, new, offset, old
+            ));
+        }
+
+        fn task_spawned(&mut self, warrior: WarriorID, thread: usize, offset: Offset) {
+            self.0
+                .push(format!("{} spawned {} at {}", warrior, thread, offset));
+        }
+
+        fn task_killed(&mut self, warrior: WarriorID, thread: usize, error: &process::Error) {
+            self.0
+                .push(format!("{} killed {}: {:?}", warrior, thread, error));
+        }
+
+        fn warrior_eliminated(&mut self, warrior: WarriorID) {
+            self.0.push(format!("{} eliminated", warrior));
+        }
+    }
+
+    #[test]
+    fn matches_generic_execution() {
+        const CORE_SIZE: i32 = 16;
+
+        let mut rng = StdRng::seed_from_u64(0x00C0_FFEE);
+
+        // Fill a core with the instructions, and load the one at the program
+        // counter as a warrior, so that its step is attributed to it
+        let build_core = |dispatch, instructions: &[Instruction], program_counter: i32| {
+            let mut core = Core::new(CoreConfig {
+                core_size: CORE_SIZE,
+                dispatch,
+                cell_stats: true,
+                ..CoreConfig::default()
+            })
+            .unwrap();
+            for (i, instruction) in instructions.iter().enumerate() {
+                core.set(i as i32, instruction.clone());
+            }
+            let warrior = Warrior {
+                program: Program {
+                    instructions: vec![instructions[program_counter as usize].clone()],
+                    origin: None,
+                },
+                ..Warrior::default()
+            };
+            core.load_warriors_at(&[warrior], &[program_counter])
+                .unwrap();
+            core
+        };
+
+        for _ in 0..10_000 {
+            let instructions: Vec<Instruction> = (0..CORE_SIZE)
+                .map(|_| random_instruction(&mut rng, CORE_SIZE))
+                .collect();
+            let program_counter = rng.gen_range(0..CORE_SIZE);
+
+            let mut generic = build_core(Dispatch::Generic, &instructions, program_counter);
+            let mut decoded = build_core(Dispatch::Decoded, &instructions, program_counter);
+
+            let mut expected_events = Events::default();
+            let mut actual_events = Events::default();
+            let expected = generic.step_with_observer(&mut expected_events);
+            let actual = decoded.step_with_observer(&mut actual_events);
+
+            let context = format!(
+                "executing {:?} at {} in core {:?}",
+                instructions[program_counter as usize], program_counter, instructions
+            );
+            assert_eq!(expected, actual, "different result {}", context);
+            assert_eq!(
+                generic.instructions, decoded.instructions,
+                "different core {}",
+                context
+            );
+            assert_eq!(
+                generic.warrior_stats(),
+                decoded.warrior_stats(),
+                "different warrior stats {}",
+                context
+            );
+            assert_eq!(
+                generic.owners, decoded.owners,
+                "different owners {}",
+                context
+            );
+            assert_eq!(
+                generic.cell_stats(),
+                decoded.cell_stats(),
+                "different cell stats {}",
+                context
+            );
+            assert_eq!(
+                expected_events.0, actual_events.0,
+                "different events {}",
+                context
+            );
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! A journal of the changes made by each step, so that steps can be undone
+//! with [`Core::step_back`](super::Core::step_back). This is only kept when
+//! [`CoreConfig::journal`](super::CoreConfig::journal) is set.
+
+use crate::core::cell::Cell;
+use crate::core::process;
+use crate::core::{WarriorID, WarriorStats};
+use crate::load_file::Offset;
+
+/// The changes made by every step since the start of the battle. The writes
+/// and reads of all steps are kept in flat lists to avoid allocating for
+/// each step, and popped into buffers which are reused for each step back.
+#[derive(Debug, Default)]
+pub(super) struct Journal {
+    steps: Vec<Entry>,
+    writes: Vec<Write>,
+    reads: Vec<Offset>,
+    popped_writes: Vec<Write>,
+    popped_reads: Vec<Offset>,
+}
+
+/// The changes made by a single step
+#[derive(Debug)]
+pub(super) struct Entry {
+    /// The task which executed, which was removed from the front of the queue
+    pub task: process::Entry,
+
+    /// The tasks added to the back of the queue
+    pub pushed: Pushed,
+
+    /// The statistics of the executing warrior before the step
+    pub warrior_stats: WarriorStats,
+
+    writes_start: usize,
+    reads_start: usize,
+}
+
+/// The tasks a step added to the back of the queue
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) enum Pushed {
+    /// The task died, so nothing was added
+    Nothing,
+    /// The task continued with the same thread ID
+    Task,
+    /// The task continued, then a new task was split off with a new thread ID
+    Split,
+}
+
+/// A write to the core, with what it overwrote
+#[derive(Copy, Clone, Debug)]
+pub(super) struct Write {
+    pub offset: Offset,
+    pub old: Cell,
+    /// The last writer of the cell before the write
+    pub old_writer: Option<WarriorID>,
+}
+
+impl Journal {
+    pub fn clear(&mut self) {
+        self.steps.clear();
+        self.writes.clear();
+        self.reads.clear();
+    }
+
+    /// Start an entry for a step. The entry must be [`push`](Self::push)ed
+    /// once the step is done.
+    pub fn begin(&self, task: process::Entry, warrior_stats: WarriorStats) -> Entry {
+        Entry {
+            task,
+            pushed: Pushed::Nothing,
+            warrior_stats,
+            writes_start: self.writes.len(),
+            reads_start: self.reads.len(),
+        }
+    }
+
+    pub fn push(&mut self, entry: Entry) {
+        self.steps.push(entry);
+    }
+
+    pub fn record_write(&mut self, write: Write) {
+        self.writes.push(write);
+    }
+
+    pub fn record_read(&mut self, offset: Offset) {
+        self.reads.push(offset);
+    }
+
+    /// Remove the last step from the journal, returning it with its writes
+    /// in the order they were made and its reads
+    pub fn pop(&mut self) -> Option<(Entry, &[Write], &[Offset])> {
+        let entry = self.steps.pop()?;
+
+        self.popped_writes.clear();
+        self.popped_writes
+            .extend(self.writes.drain(entry.writes_start..));
+        self.popped_reads.clear();
+        self.popped_reads
+            .extend(self.reads.drain(entry.reads_start..));
+
+        Some((entry, &self.popped_writes, &self.popped_reads))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::core::process::Entry;
+    use crate::core::tests::build_core_with;
+    use crate::core::{CellStats, Core, CoreConfig, Dispatch, WarriorID, WarriorStats};
+    use crate::load_file::Instruction;
+
+    /// Everything about a core which stepping back should restore
+    #[derive(Debug, PartialEq)]
+    struct Snapshot {
+        steps_taken: i32,
+        instructions: Vec<Instruction>,
+        queue: Vec<Entry>,
+        warrior_stats: Vec<WarriorStats>,
+        owners: Vec<Option<WarriorID>>,
+        cell_stats: Option<CellStats>,
+    }
+
+    fn snapshot(core: &Core) -> Snapshot {
+        Snapshot {
+            steps_taken: core.steps_taken(),
+            instructions: core.instructions().collect(),
+            queue: core.process_queue().iter().copied().collect(),
+            warrior_stats: core.warrior_stats(),
+            owners: (0..core.len())
+                .map(|index| core.last_writer(index))
+                .collect(),
+            cell_stats: core.cell_stats().cloned(),
+        }
+    }
+
+    fn build_core(dispatch: Dispatch, cell_stats: bool) -> Core {
+        let programs = [
+            // A bomber which splits, so the queue gets new thread IDs
+            "
+                  spl 0
+            loop  add #3, ptr
+                  mov bomb, @ptr
+                  djn loop, <ptr
+            ptr   dat 0, 10
+            bomb  dat 0, 0
+            ",
+            // An imp, which will eventually be bombed
+            "mov 0, 1",
+        ];
+
+        let (core, _) = build_core_with(
+            CoreConfig {
+                core_size: 64,
+                min_distance: 16,
+                max_warrior_length: 16,
+                journal: true,
+                cell_stats,
+                dispatch,
+                ..CoreConfig::default()
+            },
+            &programs,
+        );
+        core
+    }
+
+    #[test]
+    fn step_back_restores_state() {
+        for &dispatch in &[Dispatch::Decoded, Dispatch::Generic] {
+            for &cell_stats in &[false, true] {
+                let mut core = build_core(dispatch, cell_stats);
+
+                let mut snapshots = vec![snapshot(&core)];
+                for _ in 0..200 {
+                    core.step().expect("Failed to step");
+                    snapshots.push(snapshot(&core));
+                }
+
+                // Step all the way back, checking each state on the way
+                for expected in snapshots.iter().rev().skip(1) {
+                    assert!(core.step_back());
+                    assert_eq!(&snapshot(&core), expected, "{:?}", dispatch);
+                }
+                assert!(!core.step_back());
+
+                // Running forward again gives the same states, including
+                // thread IDs
+                for expected in snapshots.iter().skip(1) {
+                    core.step().expect("Failed to step");
+                    assert_eq!(&snapshot(&core), expected, "{:?}", dispatch);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn seek_to_cycle() {
+        let mut core = build_core(Dispatch::Decoded, false);
+        let start = snapshot(&core);
+
+        assert!(core.seek(50).expect("Failed to seek"));
+        assert_eq!(core.steps_taken(), 50);
+        let middle = snapshot(&core);
+
+        assert!(core.seek(120).expect("Failed to seek"));
+        assert_eq!(core.steps_taken(), 120);
+
+        assert!(core.seek(50).expect("Failed to seek"));
+        assert_eq!(snapshot(&core), middle);
+
+        assert!(core.seek(0).expect("Failed to seek"));
+        assert_eq!(snapshot(&core), start);
+    }
+
+    #[test]
+    fn set_clears_journal() {
+        let mut core = build_core(Dispatch::Decoded, false);
+        for _ in 0..10 {
+            core.step().expect("Failed to step");
+        }
+
+        // The imp was loaded here
+        assert_eq!(core.last_writer(32), Some(1));
+        core.set(32, Instruction::default());
+        assert_eq!(core.last_writer(32), None);
+        assert!(!core.step_back());
+
+        // Steps after the write can still be undone
+        let before = snapshot(&core);
+        core.step().expect("Failed to step");
+        assert!(core.step_back());
+        assert_eq!(snapshot(&core), before);
+        assert!(!core.step_back());
+    }
+
+    #[test]
+    fn step_back_without_journal() {
+        let mut core = crate::core::tests::build_core("jmp 0");
+        core.step().expect("Failed to step");
+
+        assert!(!core.step_back());
+        assert!(!core.seek(0).expect("Failed to seek"));
+        assert_eq!(core.steps_taken(), 1);
+    }
+}
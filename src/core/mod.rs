@@ -1,25 +1,37 @@
 //! A [`Core`](Core) is a block of "memory" in which Redcode programs reside.
 //! This is where all simulation of a Core Wars battle takes place.
 
-use rand::thread_rng;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 use log::trace;
+use std::fmt::{Debug, Display};
 use std::{collections::HashMap, convert::TryInto};
 use std::{collections::LinkedList, fmt};
-use std::{
-    fmt::{Debug, Display},
-    ops::{Index, Range},
-};
 
 use thiserror::Error as ThisError;
 
-use crate::load_file::{Instruction, Offset, Warrior};
+use crate::load_file::{Instruction, Offset, Opcode, Warrior};
+
+use cell::Cell;
+
+pub use cell::MAX_CORE_SIZE;
 
 mod address;
+mod cell;
+mod cycle;
+mod dispatch;
+mod journal;
 mod modifier;
+mod observer;
 mod opcode;
-mod process;
+pub mod process;
+mod stats;
+
+pub use observer::{CoreObserver, Tee};
+pub use stats::{CellStats, Killer, WarriorStats};
+
+use stats::Owners;
 
 /// An error occurred during loading or core creation
 #[derive(ThisError, Debug, PartialEq, Eq)]
@@ -33,25 +45,70 @@ pub enum Error {
     #[error("min distance between warriors is too large")]
     MinDistanceTooLarge,
 
-    /// The specified core size was larger than the allowed max
-    #[error("cannot create a core with size {0}; must be less than {}", u32::MAX)]
-    InvalidCoreSize(u32),
+    /// The specified core size was not positive, or larger than the allowed max
+    #[error(
+        "cannot create a core with size {0}; must be between 1 and {}",
+        MAX_CORE_SIZE
+    )]
+    InvalidCoreSize(i32),
+
+    /// A warrior's next task would execute an opcode which isn't supported
+    /// yet. The step is not run, so the core is left as it was.
+    #[error("warrior {0} executed {1}, which is not supported")]
+    UnsupportedOpcode(WarriorID, Opcode),
 
     #[error(transparent)]
     WarriorAlreadyLoaded(#[from] process::Error),
+
+    /// A warrior's task failed in a way that doesn't just kill the task.
+    /// The instruction may have changed the core already, so every later
+    /// step fails with the same error until the step is undone with
+    /// [`Core::step_back`] or the core is reset.
+    #[error("warrior {warrior} failed to execute an instruction")]
+    ExecutionFailed {
+        warrior: WarriorID,
+        #[source]
+        source: process::Error,
+    },
 }
 
 /// The full memory core at a given point in time
 pub struct Core {
     config: CoreConfig,
-    instructions: Vec<Instruction>,
+    instructions: Vec<Cell>,
     process_queue: process::Queue,
     steps_taken: i32,
-    log: LinkedList<Vec<Instruction>>,
+    log: LinkedList<Vec<Cell>>,
     num_warriors: usize,
+    cycle_detector: cycle::Detector,
+    /// A fingerprint of the core's instructions, maintained while detecting loops
+    fingerprint: Option<u64>,
+    /// Whether accesses to the core are being recorded for an observer
+    observing: bool,
+    /// The accesses to the core made by the current step, if observing
+    accesses: Vec<observer::Access>,
+    /// The random number generator used to place warriors
+    rng: StdRng,
+    /// The offset each warrior was loaded at, indexed by warrior ID
+    placements: Vec<Offset>,
+    /// The offsets chosen by [`load_warriors`](Self::load_warriors), kept to
+    /// reuse the allocation in later rounds
+    random_offsets: Vec<i32>,
+    /// The warrior which last wrote to each cell
+    owners: Owners,
+    /// Statistics about each cell, if enabled in the config
+    stats: Option<CellStats>,
+    /// The warrior executing the current step, used to attribute writes
+    current_warrior: Option<WarriorID>,
+    /// Statistics about each warrior, indexed by warrior ID
+    warrior_stats: Vec<WarriorStats>,
+    /// The changes made by each step, if enabled in the config
+    journal: Option<journal::Journal>,
+    /// The task which failed to execute, after which the battle can't continue
+    failure: Option<(WarriorID, process::Error)>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct CoreConfig {
     pub core_size: i32,
     pub max_cycles: i32,
@@ -60,6 +117,32 @@ pub struct CoreConfig {
     pub min_distance: i32,
     pub p_space: i32,
     pub log: bool,
+    pub dispatch: Dispatch,
+    /// End the battle early if the core repeats a previous state, since the
+    /// result can no longer change. This does not change the result, but
+    /// [`Core::steps_taken`] will be lower than for a full battle.
+    pub detect_loops: bool,
+    /// The seed used to randomly place warriors, so that battles can be
+    /// reproduced. A random seed is used if this is not set.
+    pub seed: Option<u64>,
+    /// Collect [`CellStats`] about how each cell is used, which can be read
+    /// with [`Core::cell_stats`].
+    pub cell_stats: bool,
+    /// Keep a journal of the changes made by each step, so the battle can be
+    /// rewound with [`Core::step_back`] and [`Core::seek`]. The journal grows
+    /// with every step, so this is best suited to debugging.
+    pub journal: bool,
+}
+
+/// The implementation used to execute each instruction
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Dispatch {
+    /// Dispatch to an implementation specialised for the pre-decoded opcode,
+    /// modifier and address modes of the instruction
+    #[default]
+    Decoded,
+    /// Match on the opcode and modifier of the instruction at each step
+    Generic,
 }
 
 impl Default for CoreConfig {
@@ -72,6 +155,11 @@ impl Default for CoreConfig {
             min_distance: 100,
             p_space: 500,
             log: false,
+            dispatch: Dispatch::default(),
+            detect_loops: false,
+            seed: None,
+            cell_stats: false,
+            journal: false,
         }
     }
 }
@@ -95,22 +183,149 @@ impl Display for BattleResult {
 
 pub type WarriorID = usize;
 
-enum StepResult {
-    Continue(WarriorID, Option<process::Error>),
-    Halt,
+/// The result of a single step of simulation, returned by [`Core::step`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// A task executed an instruction
+    Executed(Step),
+    /// There were no tasks left in the process queue to execute
+    Halted,
+}
+
+/// The details of an instruction executed by a task
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    /// The warrior the task belongs to
+    pub warrior: WarriorID,
+    /// The thread ID of the task within the warrior
+    pub thread: usize,
+    /// The offset of the executed instruction
+    pub program_counter: Offset,
+    /// The executed instruction, as it was before executing it
+    pub instruction: Instruction,
+    /// What happened to the task as a result
+    pub task: TaskOutcome,
+    /// Whether the task was the warrior's last, so the warrior has lost
+    pub eliminated: bool,
+}
+
+/// What happened to a task after it executed an instruction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TaskOutcome {
+    /// The task continued to the next instruction
+    Continued,
+    /// The task jumped (or skipped) to the given offset
+    Jumped(Offset),
+    /// The task continued to the next instruction, and a new task was
+    /// started at the given offset
+    Split(Offset),
+    /// The task was terminated
+    Died(process::Error),
 }
 
 impl Core {
     /// Create a new Core with the given number of possible instructions.
-    pub fn new(config: CoreConfig) -> Self {
-        Self {
-            instructions: vec![Instruction::default(); config.core_size as usize],
+    /// Returns an error if the core size is not between 1 and [`MAX_CORE_SIZE`].
+    pub fn new(config: CoreConfig) -> Result<Self, Error> {
+        if !(1..=MAX_CORE_SIZE).contains(&config.core_size) {
+            return Err(Error::InvalidCoreSize(config.core_size));
+        }
+
+        let rng = config
+            .seed
+            .map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
+
+        let stats = config
+            .cell_stats
+            .then(|| CellStats::new(config.core_size as usize));
+        let journal = config.journal.then(journal::Journal::default);
+        let owners = Owners::new(config.core_size as usize);
+
+        Ok(Self {
+            instructions: vec![Cell::default(); config.core_size as usize],
             config,
             process_queue: process::Queue::new(),
             steps_taken: 0,
             log: LinkedList::new(),
             num_warriors: 0,
+            cycle_detector: cycle::Detector::new(),
+            fingerprint: None,
+            observing: false,
+            accesses: Vec::new(),
+            rng,
+            placements: Vec::new(),
+            random_offsets: Vec::new(),
+            owners,
+            stats,
+            current_warrior: None,
+            warrior_stats: Vec::new(),
+            journal,
+            failure: None,
+        })
+    }
+
+    /// Reset the core to its initial state, so that it can be reused for
+    /// another battle. This does not allocate: the existing memory for the
+    /// instructions and process queue is reused.
+    pub fn reset(&mut self) {
+        self.instructions.fill(Cell::default());
+        self.process_queue.clear();
+        self.steps_taken = 0;
+        self.log.clear();
+        self.num_warriors = 0;
+        self.cycle_detector.reset();
+        self.fingerprint = None;
+        self.placements.clear();
+        self.owners.reset();
+        if let Some(stats) = self.stats.as_mut() {
+            stats.reset();
+        }
+        self.warrior_stats.clear();
+        if let Some(journal) = self.journal.as_mut() {
+            journal.clear();
         }
+        self.failure = None;
+    }
+
+    /// Get the configuration the core was created with
+    #[must_use]
+    pub fn config(&self) -> &CoreConfig {
+        &self.config
+    }
+
+    /// Get the offset each warrior was loaded at, indexed by warrior ID
+    #[must_use]
+    pub fn placements(&self) -> &[Offset] {
+        &self.placements
+    }
+
+    /// Get the statistics collected about each cell, if
+    /// [`CoreConfig::cell_stats`] is enabled
+    #[must_use]
+    pub fn cell_stats(&self) -> Option<&CellStats> {
+        self.stats.as_ref()
+    }
+
+    /// Get the warrior which last wrote to the instruction at a given index,
+    /// or was loaded into it. `None` if no warrior has touched the cell, or
+    /// it was last written with [`set`](Self::set).
+    #[must_use]
+    pub fn last_writer(&self, index: i32) -> Option<WarriorID> {
+        self.owners.get(self.offset(index))
+    }
+
+    /// Get statistics about each warrior in the current battle, indexed by
+    /// warrior ID
+    #[must_use]
+    pub fn warrior_stats(&self) -> Vec<WarriorStats> {
+        self.warrior_stats
+            .iter()
+            .enumerate()
+            .map(|(id, stats)| WarriorStats {
+                final_tasks: self.process_queue.thread_count(id),
+                ..stats.clone()
+            })
+            .collect()
     }
 
     #[must_use]
@@ -143,60 +358,142 @@ impl Core {
         self.instructions.is_empty()
     }
 
-    /// Get an instruction from a given index in the core
+    /// Get an instruction from a given index in the core. The core stores
+    /// encoded cells, so this decodes a copy of the instruction: write it
+    /// back with [`set`](Self::set), and use
+    /// [`instructions`](Self::instructions) to read a range of the core.
     #[must_use]
-    pub fn get(&self, index: i32) -> &Instruction {
+    pub fn get(&self, index: i32) -> Instruction {
         self.get_offset(self.offset(index))
     }
 
     /// Get an instruction from a given offset in the core
-    fn get_offset(&self, offset: Offset) -> &Instruction {
-        &self.instructions[offset.value() as usize]
+    fn get_offset(&self, offset: Offset) -> Instruction {
+        self.cell(offset).decode()
     }
 
-    /// Get a mutable instruction from a given index in the core
-    pub fn get_mut(&mut self, index: i32) -> &mut Instruction {
-        self.get_offset_mut(self.offset(index))
+    /// Get the encoded cell at a given offset in the core
+    fn cell(&self, offset: Offset) -> Cell {
+        self.instructions[offset.value() as usize]
     }
 
-    /// Get a mutable from a given offset in the core
-    fn get_offset_mut(&mut self, offset: Offset) -> &mut Instruction {
-        &mut self.instructions[offset.value() as usize]
+    /// Iterate over all instructions in the core, starting from index 0
+    pub fn instructions(&self) -> impl Iterator<Item = Instruction> + '_ {
+        self.instructions.iter().map(|cell| cell.decode())
     }
 
-    /// Write an instruction at a given index into the core
-    #[cfg(test)]
-    fn set(&mut self, index: i32, value: Instruction) {
-        self.set_offset(self.offset(index), value);
+    /// Write an instruction at a given index into the core. The field values
+    /// are normalized to the core size. No warrior owns the written cell, and
+    /// the journal is cleared, since stepping back past the write would no
+    /// longer restore the core as it was.
+    ///
+    /// # Panics
+    /// If a field value is an unresolved label.
+    pub fn set(&mut self, index: i32, instruction: Instruction) {
+        let instruction = self.normalize(instruction);
+        let offset = self.offset(index);
+        self.set_offset(offset, instruction);
+        self.owners.set(offset, None);
+        if let Some(journal) = self.journal.as_mut() {
+            journal.clear();
+        }
     }
 
-    /// Write an instruction at a given offset into the core
-    #[cfg(test)]
-    fn set_offset(&mut self, index: Offset, value: Instruction) {
-        self.instructions[index.value() as usize] = value;
+    /// Write an instruction at a given offset into the core. All writes during
+    /// simulation go through here or [`set_cell`](Self::set_cell), and `value`
+    /// must already be normalized.
+    fn set_offset(&mut self, offset: Offset, value: Instruction) {
+        self.set_cell(offset, Cell::encode(&value));
+    }
+
+    /// Write an encoded cell at a given offset into the core
+    fn set_cell(&mut self, offset: Offset, value: Cell) {
+        let cell = &mut self.instructions[offset.value() as usize];
+        if let Some(fingerprint) = self.fingerprint.as_mut() {
+            *fingerprint = cycle::update_fingerprint(*fingerprint, *cell, value);
+        }
+        if let Some(warrior) = self.current_warrior {
+            self.warrior_stats[warrior].cells_written += 1;
+            if let Some(journal) = self.journal.as_mut() {
+                journal.record_write(journal::Write {
+                    offset,
+                    old: *cell,
+                    old_writer: self.owners.get(offset),
+                });
+            }
+            self.owners.set(offset, Some(warrior));
+            if let Some(stats) = self.stats.as_mut() {
+                stats.record_write(offset);
+            }
+        }
+        if self.observing {
+            self.accesses.push(observer::Access::Write {
+                offset,
+                old: *cell,
+                new: value,
+            });
+        }
+        *cell = value;
+    }
+
+    /// Record that the current instruction read an operand at the given offset
+    fn record_read(&mut self, offset: Offset) {
+        if let Some(stats) = self.stats.as_mut() {
+            stats.record_read(offset);
+        }
+        if let Some(journal) = self.journal.as_mut() {
+            journal.record_read(offset);
+        }
+        if self.observing {
+            self.accesses.push(observer::Access::Read(offset));
+        }
     }
 
     /// Load a [`Warrior`](Warrior) into the core starting at the front (first instruction of the core).
-    /// Returns an error if the Warrior was too long to fit in the core, or had unresolved labels
+    /// Returns an error if the Warrior was too long to fit in the core, or had unresolved labels.
+    ///
+    /// To load warriors into a core that has already been used, call [`reset`](Self::reset) first.
     pub fn load_warriors(&mut self, warriors: &[Warrior]) -> Result<(), Error> {
-        self.num_warriors = warriors.len();
         let spacing = self.config.core_size / warriors.len() as i32;
         if spacing < self.config.min_distance {
             return Err(Error::MinDistanceTooLarge);
         }
-        let mut rng = thread_rng();
-        for (id, w) in warriors.iter().enumerate() {
-            let id = id as WarriorID;
-            let offset_value: i32 = if id == 0 {
+        let mut offsets = std::mem::take(&mut self.random_offsets);
+        offsets.clear();
+        offsets.extend((0..warriors.len() as i32).map(|id| {
+            if id == 0 {
                 0
             } else {
-                id as i32 * spacing
-                    + rng.gen_range(
+                id * spacing
+                    + self.rng.gen_range(
                         self.config.min_distance..spacing - self.config.max_warrior_length,
                     )
-            };
+            }
+        }));
+
+        let result = self.load_warriors_at(warriors, &offsets);
+        self.random_offsets = offsets;
+        result
+    }
+
+    /// Load each [`Warrior`](Warrior) into the core at the given offset, instead
+    /// of placing them randomly like [`load_warriors`](Self::load_warriors).
+    ///
+    /// # Panics
+    /// If the number of offsets doesn't match the number of warriors.
+    pub fn load_warriors_at(&mut self, warriors: &[Warrior], offsets: &[i32]) -> Result<(), Error> {
+        assert_eq!(
+            warriors.len(),
+            offsets.len(),
+            "each warrior needs exactly one offset"
+        );
+
+        self.num_warriors = warriors.len();
+        self.fingerprint = None;
+        for (id, (w, &offset_value)) in warriors.iter().zip(offsets).enumerate() {
             let offset = self.offset(offset_value);
             self.load_warrior(id, offset, w)?;
+            self.placements.push(offset);
         }
         Ok(())
     }
@@ -214,7 +511,10 @@ impl Core {
         // safe way of loading a resolved warrior perhaps
 
         for (i, instruction) in warrior.program.instructions.iter().enumerate() {
-            self.instructions[offset.value() as usize + i] = self.normalize(instruction.clone());
+            let instruction = self.normalize(instruction.clone());
+            self.set_offset(offset + i as i32, instruction);
+            // Loading makes the warrior the owner without counting as a write
+            self.owners.set(offset + i as i32, Some(id));
         }
 
         let origin: i32 = warrior
@@ -222,10 +522,14 @@ impl Core {
             .origin
             .unwrap_or(0)
             .try_into()
-            .expect(format!("Warrior {:?} has invalid origin", id).as_str());
+            .unwrap_or_else(|_| panic!("Warrior {:?} has invalid origin", id));
 
         self.process_queue
             .push(id, self.offset(offset.value() + origin), None);
+        self.warrior_stats.push(WarriorStats {
+            peak_tasks: 1,
+            ..WarriorStats::default()
+        });
 
         Ok(())
     }
@@ -249,15 +553,82 @@ impl Core {
         instruction
     }
 
-    // Run a single cycle of simulation.
-    fn step(&mut self) -> StepResult {
+    /// Get the process queue, to inspect which tasks will execute next
+    #[must_use]
+    pub fn process_queue(&self) -> &process::Queue {
+        &self.process_queue
+    }
+
+    /// Whether the battle is over when stepping manually, with the same rules
+    /// as [`run`](Self::run): the cycle limit was reached, every warrior was
+    /// eliminated, or only one warrior is left out of several. A battle is
+    /// also over if a step failed with [`Error::ExecutionFailed`].
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        let alive = (0..self.num_warriors)
+            .filter(|&id| self.process_queue.thread_count(id) > 0)
+            .count();
+
+        self.failure.is_some()
+            || self.steps_taken >= self.config.max_cycles
+            || alive == 0
+            || (self.num_warriors > 1 && alive <= 1)
+    }
+
+    /// Run a single step of simulation: the task at the front of the process
+    /// queue executes one instruction.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the instruction could not be executed, other than by killing
+    /// the task, e.g. a task executing `DAT`. See [`Error::UnsupportedOpcode`]
+    /// and [`Error::ExecutionFailed`] for the state the core is left in.
+    pub fn step(&mut self) -> Result<StepOutcome, Error> {
+        self.step_observed(None)
+    }
+
+    /// Run a single step of simulation like [`step`](Self::step), reporting
+    /// what happened to the given observer.
+    ///
+    /// # Errors
+    ///
+    /// See [`step`](Self::step).
+    pub fn step_with_observer(
+        &mut self,
+        observer: &mut dyn CoreObserver,
+    ) -> Result<StepOutcome, Error> {
+        self.step_observed(Some(observer))
+    }
+
+    fn step_observed(
+        &mut self,
+        mut observer: Option<&mut dyn CoreObserver>,
+    ) -> Result<StepOutcome, Error> {
+        if let Some((warrior, source)) = &self.failure {
+            return Err(Error::ExecutionFailed {
+                warrior: *warrior,
+                source: source.clone(),
+            });
+        }
+
+        // Check for opcodes which can't be executed before changing anything,
+        // so that the core is left as it was
+        if let Ok(entry) = self.process_queue.peek() {
+            let opcode = self.cell(entry.offset).opcode();
+            if matches!(opcode, Opcode::Ldp | Opcode::Stp) {
+                return Err(Error::UnsupportedOpcode(entry.id, opcode));
+            }
+        }
+
+        self.observing = observer.is_some();
         if self.config.log {
             self.log.push_back(self.instructions.to_vec());
         }
         let current_process = match self.process_queue.pop() {
             Ok(cp) => cp,
-            Err(_err) => return StepResult::Halt,
+            Err(_err) => return Ok(StepOutcome::Halted),
         };
+        let instruction = self.get_offset(current_process.offset);
 
         trace!(
             "Step{:>6} p:{:>2} t:{:>2} {:#06x} {:?}",
@@ -265,23 +636,67 @@ impl Core {
             current_process.id,
             current_process.thread,
             current_process.offset.value(),
-            self.get_offset(current_process.offset),
+            instruction,
         );
         self.steps_taken += 1;
 
-        let result = opcode::execute(self, current_process.offset);
+        let mut journal_entry = self.journal.as_ref().map(|journal| {
+            journal.begin(
+                current_process,
+                self.warrior_stats[current_process.id].clone(),
+            )
+        });
+
+        // Look up the author of the instruction before it executes, in case
+        // it overwrites itself
+        let author = self.owners.get(current_process.offset);
+        if let Some(stats) = self.stats.as_mut() {
+            stats.record_execution(current_process.offset);
+        }
+        self.warrior_stats[current_process.id].instructions_executed += 1;
+        self.current_warrior = Some(current_process.id);
+        let result = match self.config.dispatch {
+            Dispatch::Decoded => dispatch::execute(self, current_process.offset),
+            Dispatch::Generic => opcode::execute(self, current_process.offset),
+        };
+        self.current_warrior = None;
 
-        match result {
+        let mut eliminated = false;
+        let task = match result {
             Err(err) => match err {
                 process::Error::DivideByZero | process::Error::ExecuteDat(_) => {
-                    if self.process_queue.thread_count(current_process.id) < 1 {
-                        StepResult::Continue(current_process.id, Some(err))
-                    } else {
-                        // This is fine, the task terminated but the process is still alive
-                        StepResult::Continue(current_process.id, None)
+                    // If other tasks remain this is fine, the task terminated
+                    // but the process is still alive
+                    eliminated = self.process_queue.thread_count(current_process.id) < 1;
+                    if eliminated {
+                        let stats = &mut self.warrior_stats[current_process.id];
+                        stats.death_cycle = Some(self.steps_taken);
+                        stats.killed_by = Some(author.map_or(Killer::Unowned, Killer::Warrior));
+                    }
+
+                    if let Some(observer) = observer.as_deref_mut() {
+                        self.report_accesses(observer, current_process.id);
+                        observer.task_killed(current_process.id, current_process.thread, &err);
                     }
+
+                    TaskOutcome::Died(err)
+                }
+                source => {
+                    // The instruction may have changed the core already, so
+                    // finish recording the step and stop the battle here
+                    if let Some(observer) = observer.as_deref_mut() {
+                        self.report_accesses(observer, current_process.id);
+                    }
+                    if let (Some(journal), Some(entry)) = (self.journal.as_mut(), journal_entry) {
+                        journal.push(entry);
+                    }
+                    self.failure = Some((current_process.id, source.clone()));
+
+                    return Err(Error::ExecutionFailed {
+                        warrior: current_process.id,
+                        source,
+                    });
                 }
-                _ => panic!("Unexpected error {}", err),
             },
             Ok(result) => {
                 // In the special case of a split, enqueue PC+1 (with same thread id)
@@ -302,32 +717,211 @@ impl Core {
                     .program_counter_offset
                     .unwrap_or_else(|| self.offset(1));
 
-                self.process_queue.push(
-                    current_process.id,
-                    current_process.offset + offset,
-                    new_thread_id,
-                );
+                let target = current_process.offset + offset;
+                let thread = self
+                    .process_queue
+                    .push(current_process.id, target, new_thread_id);
 
-                StepResult::Continue(current_process.id, None)
+                if let Some(entry) = journal_entry.as_mut() {
+                    entry.pushed = if result.should_split {
+                        journal::Pushed::Split
+                    } else {
+                        journal::Pushed::Task
+                    };
+                }
+
+                if result.should_split {
+                    let tasks = self.process_queue.thread_count(current_process.id);
+                    let stats = &mut self.warrior_stats[current_process.id];
+                    stats.peak_tasks = stats.peak_tasks.max(tasks);
+                }
+
+                if let Some(observer) = observer.as_deref_mut() {
+                    self.report_accesses(observer, current_process.id);
+                    if result.should_split {
+                        observer.task_spawned(current_process.id, thread, target);
+                    }
+                }
+
+                if result.should_split {
+                    TaskOutcome::Split(target)
+                } else if result.program_counter_offset.is_some() {
+                    TaskOutcome::Jumped(target)
+                } else {
+                    TaskOutcome::Continued
+                }
+            }
+        };
+
+        if let (Some(journal), Some(entry)) = (self.journal.as_mut(), journal_entry) {
+            journal.push(entry);
+        }
+
+        let step = Step {
+            warrior: current_process.id,
+            thread: current_process.thread,
+            program_counter: current_process.offset,
+            instruction,
+            task,
+            eliminated,
+        };
+
+        if let Some(observer) = observer {
+            observer.instruction_executed(&step);
+            if eliminated {
+                observer.warrior_eliminated(step.warrior);
+            }
+        }
+
+        Ok(StepOutcome::Executed(step))
+    }
+
+    /// Undo the last step, restoring the core, process queue and statistics
+    /// to how they were before it. Returns `false` if there is no step to
+    /// undo, either because the battle is at its start or because
+    /// [`CoreConfig::journal`] is not enabled.
+    ///
+    /// Stepping forward again after stepping back gives the same steps,
+    /// including thread IDs.
+    pub fn step_back(&mut self) -> bool {
+        let (entry, writes, reads) = match self.journal.as_mut().and_then(journal::Journal::pop) {
+            Some(step) => step,
+            None => return false,
+        };
+        let task = entry.task;
+
+        match entry.pushed {
+            journal::Pushed::Nothing => {}
+            journal::Pushed::Task => self.process_queue.unpush(false),
+            journal::Pushed::Split => {
+                self.process_queue.unpush(true);
+                self.process_queue.unpush(false);
+            }
+        }
+        self.process_queue.unpop(task);
+
+        for write in writes.iter().rev() {
+            self.instructions[write.offset.value() as usize] = write.old;
+            self.owners.set(write.offset, write.old_writer);
+            if let Some(stats) = self.stats.as_mut() {
+                stats.undo_write(write.offset);
+            }
+        }
+        if let Some(stats) = self.stats.as_mut() {
+            for &offset in reads {
+                stats.undo_read(offset);
+            }
+            stats.undo_execution(task.offset);
+        }
+        self.warrior_stats[task.id] = entry.warrior_stats;
+        self.failure = None;
+
+        self.steps_taken -= 1;
+        if self.config.log {
+            self.log.pop_back();
+        }
+        // The fingerprint is recomputed when next needed, and states seen
+        // after this step may not be reached again
+        self.fingerprint = None;
+        self.cycle_detector.reset();
+
+        true
+    }
+
+    /// Step backwards or forwards until [`steps_taken`](Self::steps_taken)
+    /// is `cycle`, e.g. to rewind from a warrior's death to the instruction
+    /// that wrote the fatal `DAT`. Returns `false` if the cycle can't be
+    /// reached, because stepping back needs [`CoreConfig::journal`] or
+    /// because the process queue emptied first.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a step forwards fails, see [`step`](Self::step).
+    pub fn seek(&mut self, cycle: i32) -> Result<bool, Error> {
+        while self.steps_taken > cycle {
+            if !self.step_back() {
+                return Ok(false);
+            }
+        }
+        while self.steps_taken < cycle {
+            if self.step()? == StepOutcome::Halted {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Report the accesses recorded during a step to an observer
+    fn report_accesses(&mut self, observer: &mut dyn CoreObserver, warrior: WarriorID) {
+        for access in self.accesses.drain(..) {
+            match access {
+                observer::Access::Read(offset) => observer.cell_read(warrior, offset),
+                observer::Access::Write { offset, old, new } => {
+                    observer.cell_written(warrior, offset, &old.decode(), &new.decode());
+                }
             }
         }
     }
 
     /// Run a core to completion. Reports what happened to each warrior.
-    pub fn run(&mut self) -> HashMap<WarriorID, BattleResult> {
+    ///
+    /// # Errors
+    ///
+    /// Fails if any step fails, see [`step`](Self::step).
+    pub fn run(&mut self) -> Result<HashMap<WarriorID, BattleResult>, Error> {
+        self.run_observed(None)
+    }
+
+    /// Run a core to completion like [`run`](Self::run), reporting each step
+    /// to the given observer.
+    ///
+    /// # Errors
+    ///
+    /// See [`run`](Self::run).
+    pub fn run_with_observer(
+        &mut self,
+        observer: &mut dyn CoreObserver,
+    ) -> Result<HashMap<WarriorID, BattleResult>, Error> {
+        self.run_observed(Some(observer))
+    }
+
+    fn run_observed(
+        &mut self,
+        mut observer: Option<&mut dyn CoreObserver>,
+    ) -> Result<HashMap<WarriorID, BattleResult>, Error> {
         let mut results: HashMap<WarriorID, BattleResult> =
             HashMap::with_capacity(self.num_warriors);
 
         trace!("init:\n{:?}", self);
 
         while self.steps_taken < self.config.max_cycles {
-            match self.step() {
-                StepResult::Continue(id, err) => {
-                    if let Some(err) = err {
-                        results.insert(id, BattleResult::Loss(err));
-                    }
+            // Reborrow the observer for a single step
+            let step_observer = observer.as_mut().map(|observer| &mut **observer as _);
+            match self.step_observed(step_observer)? {
+                StepOutcome::Executed(Step {
+                    warrior,
+                    task: TaskOutcome::Died(err),
+                    eliminated: true,
+                    ..
+                }) => {
+                    results.insert(warrior, BattleResult::Loss(err));
+                }
+                StepOutcome::Executed(_) => {}
+                StepOutcome::Halted => break,
+            }
+
+            if self.config.detect_loops {
+                let fingerprint = *self
+                    .fingerprint
+                    .get_or_insert_with(|| cycle::fingerprint(&self.instructions));
+
+                if self
+                    .cycle_detector
+                    .check(fingerprint, &self.instructions, &self.process_queue)
+                {
+                    trace!("core state repeated after {} steps", self.steps_taken);
+                    break;
                 }
-                StepResult::Halt => break,
             }
 
             // If we have more that one warrior battling and a single survivor then stop
@@ -350,42 +944,38 @@ impl Core {
         if survivor_count > 1 {
             // Insert the winners, which all tied.
             for id in 0..self.num_warriors {
-                if results.get(&id).is_none() {
-                    results.insert(id, BattleResult::Tie);
-                }
+                results.entry(id).or_insert(BattleResult::Tie);
             }
         } else {
             // Insert the winner, which won.
             for id in 0..self.num_warriors {
-                if results.get(&id).is_none() {
-                    results.insert(id, BattleResult::Win);
-                }
+                results.entry(id).or_insert(BattleResult::Win);
             }
         }
         // Return results mapped by name instead of id
-        results
+        Ok(results)
     }
 }
 
 impl Default for Core {
     fn default() -> Self {
-        Self::new(CoreConfig::default())
+        Self::new(CoreConfig::default()).expect("default core size should be valid")
     }
 }
 
 impl Debug for Core {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Core: {:?}\n", self.config)?;
+        writeln!(f, "Core: {:?}", self.config)?;
         write!(f, "{:?}", Instructions(&self.instructions))
     }
 }
 
-struct Instructions<'a>(&'a [Instruction]);
+struct Instructions<'a>(&'a [Cell]);
 
 impl<'a> Debug for Instructions<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut run = 0;
-        let mut last = None;
+        let mut last: Option<&Cell> = None;
         for (idx, instruction) in self.0.iter().enumerate() {
             if let Some(last) = last {
                 if last == instruction {
@@ -393,36 +983,30 @@ impl<'a> Debug for Instructions<'a> {
                     continue;
                 } else if run > 0 {
                     if run >= 5 {
-                        write!(f, "Repeats {} more times\n", run)?;
+                        writeln!(f, "Repeats {} more times", run)?;
                     } else {
                         for i in 0..run {
-                            write!(f, "{:#06x} {:?}\n", idx - run + i, last)?;
+                            writeln!(f, "{:#06x} {:?}", idx - run + i, last.decode())?;
                         }
                     }
                     run = 0;
                 }
             }
-            write!(f, "{:#06x} {:?}\n", idx, instruction)?;
+            writeln!(f, "{:#06x} {:?}", idx, instruction.decode())?;
             last = Some(instruction);
         }
         if run > 0 {
-            write!(f, "Repeats {} more times\n", run)?;
+            writeln!(f, "Repeats {} more times", run)?;
         }
         Ok(())
     }
 }
 
-impl Index<Range<usize>> for Core {
-    type Output = [Instruction];
-
-    fn index(&self, index: Range<usize>) -> &Self::Output {
-        &self.instructions[index]
-    }
-}
-
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
+    use maplit::hashmap;
     use pretty_assertions::assert_eq;
+    use test_case::test_case;
 
     use crate::{
         load_file::{Field, Opcode, Program},
@@ -433,32 +1017,85 @@ mod tests {
 
     /// Create a core from a string. Public since it is used by submodules' tests as well
     pub fn build_core(program: &str) -> Core {
-        let warrior = parser::parse(program).expect("Failed to parse warrior");
-
-        let mut core = Core::new(CoreConfig {
-            max_cycles: 8000,
-            ..CoreConfig::default()
-        });
-        core.load_warriors(&vec![warrior])
-            .expect("Failed to load warrior");
+        let (core, _) = build_core_with(
+            CoreConfig {
+                max_cycles: 8000,
+                ..CoreConfig::default()
+            },
+            &[program],
+        );
         core
     }
 
+    /// Create a core with the given config, loading a warrior from each
+    /// program. The warriors are spaced evenly through the core, starting at
+    /// 0, and returned as well for observers which need them. Public since it
+    /// is used by other modules' tests as well
+    pub fn build_core_with(config: CoreConfig, programs: &[&str]) -> (Core, Vec<Warrior>) {
+        let warriors: Vec<Warrior> = programs
+            .iter()
+            .map(|program| parser::parse(program).expect("Failed to parse warrior"))
+            .collect();
+
+        let mut core = Core::new(config).expect("Failed to create core");
+        let spacing = core.len() / warriors.len() as i32;
+        let offsets: Vec<i32> = (0..warriors.len() as i32).map(|id| id * spacing).collect();
+        core.load_warriors_at(&warriors, &offsets)
+            .expect("Failed to load warriors");
+        (core, warriors)
+    }
+
     #[test]
     fn new_core() {
         let core = Core::new(CoreConfig {
             core_size: 128,
             ..CoreConfig::default()
-        });
+        })
+        .expect("Failed to create core");
         assert_eq!(core.len(), 128);
     }
 
+    #[test]
+    fn new_core_invalid_size() {
+        for &core_size in &[0, -8000, MAX_CORE_SIZE + 1] {
+            let err = Core::new(CoreConfig {
+                core_size,
+                ..CoreConfig::default()
+            })
+            .expect_err("Should have failed to create core");
+
+            assert_eq!(err, Error::InvalidCoreSize(core_size));
+        }
+    }
+
+    #[test]
+    fn new_core_max_size() {
+        let mut core = Core::new(CoreConfig {
+            core_size: MAX_CORE_SIZE,
+            ..CoreConfig::default()
+        })
+        .expect("Failed to create core");
+        assert_eq!(core.len(), MAX_CORE_SIZE);
+
+        let instruction = Instruction::new(Opcode::Mov, Field::direct(-1), Field::direct(1));
+        core.set(-1, instruction);
+        assert_eq!(
+            core.get(MAX_CORE_SIZE - 1),
+            Instruction::new(
+                Opcode::Mov,
+                Field::direct(MAX_CORE_SIZE - 1),
+                Field::direct(1)
+            )
+        );
+    }
+
     #[test]
     fn load_program() {
         let mut core = Core::new(CoreConfig {
             core_size: 128,
             ..CoreConfig::default()
-        });
+        })
+        .expect("Failed to create core");
 
         let warrior = parser::parse(
             "
@@ -469,15 +1106,15 @@ mod tests {
         )
         .expect("Failed to parse warrior");
 
-        core.load_warriors(&vec![warrior])
+        core.load_warriors(&[warrior])
             .expect("Failed to load warrior");
         let expected_core_size = 128_i32;
         assert_eq!(core.len(), expected_core_size);
 
-        let jmp_target = (expected_core_size - 1).try_into().unwrap();
+        let jmp_target = expected_core_size - 1;
 
         assert_eq!(
-            &core.instructions[..4],
+            &core.instructions().collect::<Vec<_>>()[..4],
             &[
                 Instruction::new(Opcode::Mov, Field::direct(1), Field::immediate(1)),
                 Instruction::new(
@@ -500,7 +1137,8 @@ mod tests {
         let mut core = Core::new(CoreConfig {
             core_size: 128,
             ..CoreConfig::default()
-        });
+        })
+        .expect("Failed to create core");
         let warrior = Warrior {
             program: Program {
                 instructions: vec![
@@ -512,23 +1150,297 @@ mod tests {
             ..Warrior::default()
         };
 
-        core.load_warriors(&vec![warrior])
+        core.load_warriors(&[warrior])
             .expect_err("Should have failed to load warrior: too long");
 
         assert_eq!(core.len(), 128);
     }
 
+    #[test_case(Dispatch::Decoded, "ldp #0, #1", Opcode::Ldp; "decoded ldp")]
+    #[test_case(Dispatch::Decoded, "stp #0, #1", Opcode::Stp; "decoded stp")]
+    #[test_case(Dispatch::Generic, "ldp #0, #1", Opcode::Ldp; "generic ldp")]
+    #[test_case(Dispatch::Generic, "stp #0, #1", Opcode::Stp; "generic stp")]
+    fn step_unsupported_opcode(dispatch: Dispatch, program: &str, opcode: Opcode) {
+        use pretty_assertions::assert_eq;
+
+        let mut core = Core::new(CoreConfig {
+            dispatch,
+            ..CoreConfig::default()
+        })
+        .expect("Failed to create core");
+        let warrior = parser::parse(program).expect("Failed to parse warrior");
+        core.load_warriors(&[warrior])
+            .expect("Failed to load warrior");
+
+        assert_eq!(core.step(), Err(Error::UnsupportedOpcode(0, opcode)));
+    }
+
+    /// Counts the accesses and steps reported to it
+    #[derive(Default)]
+    struct Events(usize);
+
+    impl CoreObserver for Events {
+        fn instruction_executed(&mut self, _step: &Step) {
+            self.0 += 1;
+        }
+
+        fn cell_read(&mut self, _warrior: WarriorID, _offset: Offset) {
+            self.0 += 1;
+        }
+
+        fn cell_written(
+            &mut self,
+            _warrior: WarriorID,
+            _offset: Offset,
+            _old: &Instruction,
+            _new: &Instruction,
+        ) {
+            self.0 += 1;
+        }
+    }
+
+    #[test]
+    fn step_after_unsupported_opcode() {
+        let (mut core, _) = build_core_with(
+            CoreConfig {
+                journal: true,
+                cell_stats: true,
+                ..CoreConfig::default()
+            },
+            &["ldp.a >1, }1\ndat 5, 6"],
+        );
+        let queue = core.process_queue().iter().copied().collect::<Vec<_>>();
+        let stats = core.warrior_stats();
+
+        // The step is never run, so stepping again fails the same way
+        for _ in 0..2 {
+            let mut observer = Events::default();
+            assert_eq!(
+                core.step_with_observer(&mut observer),
+                Err(Error::UnsupportedOpcode(0, Opcode::Ldp))
+            );
+            assert_eq!(observer.0, 0);
+        }
+
+        assert_eq!(core.steps_taken(), 0);
+        assert_eq!(
+            core.process_queue().iter().copied().collect::<Vec<_>>(),
+            queue
+        );
+        assert_eq!(core.warrior_stats(), stats);
+        assert_eq!(core.get(1).a_field.unwrap_value(), 5);
+        assert_eq!(core.cell_stats().unwrap().executions(core.offset(0)), 0);
+        assert!(!core.step_back());
+    }
+
+    #[test]
+    fn step_after_execution_failed() {
+        let (mut core, _) = build_core_with(
+            CoreConfig {
+                journal: true,
+                ..CoreConfig::default()
+            },
+            &["mov 0, 1", "jmp 0"],
+        );
+        core.step().expect("Failed to step");
+
+        // No instruction fails like this yet, so fail the next step directly
+        let failed = Err(Error::ExecutionFailed {
+            warrior: 0,
+            source: process::Error::NoRemainingProcesses,
+        });
+        core.failure = Some((0, process::Error::NoRemainingProcesses));
+        assert!(core.is_finished());
+        assert_eq!(core.step(), failed);
+        assert_eq!(core.step(), failed);
+        assert_eq!(core.steps_taken(), 1);
+
+        assert!(core.step_back());
+        assert!(!core.is_finished());
+        core.step().expect("Failed to step");
+    }
+
+    #[test]
+    fn reset_and_reload() {
+        let mut core = build_core("mov $0, $1");
+        let warrior = parser::parse("mov $0, $1").expect("Failed to parse warrior");
+
+        for _ in 0..10 {
+            core.step().expect("Failed to step");
+        }
+        assert_eq!(core.steps_taken(), 10);
+        assert_ne!(core.get(5), Instruction::default());
+
+        let capacity = core.instructions.capacity();
+        core.reset();
+
+        assert_eq!(core.steps_taken(), 0);
+        assert_eq!(core.instructions.capacity(), capacity);
+        assert!(core.instructions().all(|i| i == Instruction::default()));
+        assert!(core.process_queue.peek().is_err());
+
+        core.load_warriors(&[warrior])
+            .expect("Failed to reload warrior");
+        assert_eq!(core.program_counter().value(), 0);
+        assert_eq!(core.get(1), Instruction::default());
+        core.step().expect("Failed to step");
+        assert_eq!(core.program_counter().value(), 1);
+        assert_eq!(core.get(1), core.get(0));
+    }
+
+    fn build_battle(programs: &[&str], detect_loops: bool) -> Core {
+        let warriors: Vec<Warrior> = programs
+            .iter()
+            .map(|program| parser::parse(program).expect("Failed to parse warrior"))
+            .collect();
+
+        let mut core = Core::new(CoreConfig {
+            core_size: 800,
+            detect_loops,
+            ..CoreConfig::default()
+        })
+        .expect("Failed to create core");
+        core.load_warriors(&warriors)
+            .expect("Failed to load warriors");
+        core
+    }
+
+    #[test]
+    fn detect_loops_between_imps() {
+        let imps = ["mov 0, 1", "mov 0, 1"];
+        let expected = hashmap! {
+            0 => BattleResult::Tie,
+            1 => BattleResult::Tie,
+        };
+
+        let mut core = build_battle(&imps, false);
+        assert_eq!(core.run().expect("Failed to run"), expected);
+        assert_eq!(core.steps_taken(), core.config.max_cycles);
+
+        let mut core = build_battle(&imps, true);
+        assert_eq!(core.run().expect("Failed to run"), expected);
+        assert!(core.steps_taken() < core.config.max_cycles);
+    }
+
+    #[test]
+    fn detect_loops_keeps_result() {
+        // The second warrior counts down and eventually executes a DAT, so
+        // no state repeats before it dies
+        let mut core = build_battle(&["jmp 0", "djn 0, #500"], true);
+
+        let results = core.run().expect("Failed to run");
+
+        assert_eq!(results[&0], BattleResult::Win);
+        assert!(matches!(results[&1], BattleResult::Loss(_)));
+    }
+
+    fn executed_step(core: &mut Core) -> Step {
+        match core.step().expect("Failed to step") {
+            StepOutcome::Executed(step) => step,
+            StepOutcome::Halted => panic!("Core halted unexpectedly"),
+        }
+    }
+
+    #[test]
+    fn step_outcomes() {
+        let mut core = build_core(
+            "
+            spl 2
+            jmp 0
+            seq 0, 1
+            nop 0, 0
+            ",
+        );
+        let offset = |value| Offset::new(value, core.len());
+        let (zero, one, two, three, four) = (offset(0), offset(1), offset(2), offset(3), offset(4));
+
+        assert_eq!(
+            executed_step(&mut core),
+            Step {
+                warrior: 0,
+                thread: 0,
+                program_counter: zero,
+                instruction: Instruction::new(Opcode::Spl, Field::direct(2), Field::direct(0)),
+                task: TaskOutcome::Split(two),
+                eliminated: false,
+            }
+        );
+
+        let queue: Vec<_> = core
+            .process_queue()
+            .iter()
+            .map(|entry| (entry.thread, entry.offset))
+            .collect();
+        assert_eq!(queue, vec![(0, one), (1, two)]);
+
+        let step = executed_step(&mut core);
+        assert_eq!((step.thread, step.task), (0, TaskOutcome::Jumped(one)));
+
+        let step = executed_step(&mut core);
+        assert_eq!(step.instruction.opcode, Opcode::Seq);
+        assert_eq!((step.thread, step.task), (1, TaskOutcome::Continued));
+
+        executed_step(&mut core);
+
+        let step = executed_step(&mut core);
+        assert_eq!(step.program_counter, three);
+        assert_eq!(step.task, TaskOutcome::Continued);
+
+        executed_step(&mut core);
+
+        let step = executed_step(&mut core);
+        assert_eq!(
+            (step.thread, step.task, step.eliminated),
+            (
+                1,
+                TaskOutcome::Died(process::Error::ExecuteDat(four)),
+                false
+            )
+        );
+        assert_eq!(core.process_queue().len(), 1);
+    }
+
+    #[test]
+    fn step_eliminates_warrior() {
+        let mut core = build_core("dat 0, 0");
+
+        let step = executed_step(&mut core);
+        assert_eq!(
+            step.task,
+            TaskOutcome::Died(process::Error::ExecuteDat(core.offset(0)))
+        );
+        assert!(step.eliminated);
+        assert!(core.process_queue().is_empty());
+
+        assert_eq!(core.step().expect("Failed to step"), StepOutcome::Halted);
+    }
+
+    #[test]
+    fn finished_after_elimination_or_max_cycles() {
+        let mut core = build_core("dat 0, 0");
+        assert!(!core.is_finished());
+        core.step().expect("Failed to step");
+        assert!(core.is_finished());
+
+        let mut core = build_core("jmp 0");
+        for _ in 0..core.config().max_cycles {
+            assert!(!core.is_finished());
+            core.step().expect("Failed to step");
+        }
+        assert!(core.is_finished());
+    }
+
     #[test]
     fn wrap_program_counter_on_overflow() {
         let mut core = build_core("mov $0, $1");
 
         for i in 0..core.len() {
             assert_eq!(core.program_counter().value(), i);
-            core.step();
+            core.step().expect("Failed to step");
         }
 
         assert_eq!(core.program_counter().value(), 0);
-        core.step();
+        core.step().expect("Failed to step");
         assert_eq!(core.program_counter().value(), 1);
     }
 }
@@ -26,13 +26,15 @@ impl<'a> Executor<'a> {
         // operations must use the buffered A operand, in case the B pointer evaluation
         // modifies memory
         address::apply_a_pointer(core, program_counter, address::EvalTime::Pre);
-        let a_value = core.get_offset(a_ptr).clone();
+        let a_value = core.get_offset(a_ptr);
+        core.record_read(a_ptr);
         address::apply_a_pointer(core, program_counter, address::EvalTime::Post);
 
         let b_ptr = address::resolve_b_pointer(core, program_counter);
 
         address::apply_b_pointer(core, program_counter, address::EvalTime::Pre);
-        let b_value = core.get_offset(b_ptr).clone();
+        let b_value = core.get_offset(b_ptr);
+        core.record_read(b_ptr);
         address::apply_b_pointer(core, program_counter, address::EvalTime::Post);
 
         Self {
@@ -71,7 +73,7 @@ impl<'a> Executor<'a> {
         InstructionOp: FnMut(Instruction, Instruction) -> Option<Instruction>,
         OptionalInstructionOp: Into<Option<InstructionOp>>,
     {
-        let instruction = self.core.get_offset(self.program_counter).clone();
+        let instruction = self.core.get_offset(self.program_counter);
 
         let a_value_a_offset = self.core.offset(self.a_value.a_field.unwrap_value());
         let a_value_b_offset = self.core.offset(self.a_value.b_field.unwrap_value());
@@ -79,7 +81,16 @@ impl<'a> Executor<'a> {
         let b_value_a_offset = self.core.offset(self.b_value.a_field.unwrap_value());
         let b_value_b_offset = self.core.offset(self.b_value.b_field.unwrap_value());
 
-        let b_target = self.core.get_offset_mut(self.b_ptr);
+        let mut b_target = self.core.get_offset(self.b_ptr);
+
+        // Only write the B target back if an operation produced a result, so
+        // that e.g. comparisons don't count as writes
+        let mut written = false;
+        let mut field_op = |a, b| {
+            let result = field_op(a, b);
+            written |= result.is_some();
+            result
+        };
 
         match instruction.modifier {
             Modifier::A => {
@@ -113,8 +124,11 @@ impl<'a> Executor<'a> {
                 if instruction.modifier == Modifier::I {
                     if let Some(mut instruction_op) = instruction_op.into() {
                         if let Some(res) = instruction_op(self.a_value, b_target.clone()) {
+                            written = true;
                             b_target.opcode = res.opcode;
                             b_target.modifier = res.modifier;
+                            b_target.a_field.address_mode = res.a_field.address_mode;
+                            b_target.b_field.address_mode = res.b_field.address_mode;
                         }
                     }
                 }
@@ -128,6 +142,10 @@ impl<'a> Executor<'a> {
                 }
             }
         }
+
+        if written {
+            self.core.set_offset(self.b_ptr, b_target);
+        }
     }
 }
 
@@ -171,7 +189,7 @@ mod tests {
 
         assert_eq!(
             core.get(2),
-            &Instruction {
+            Instruction {
                 opcode: Opcode::Sub,
                 modifier: Modifier::X,
                 a_field: Field::direct(expected_a),
@@ -215,7 +233,7 @@ mod tests {
 
         assert_eq!(
             core.get(2),
-            &Instruction {
+            Instruction {
                 opcode: Opcode::Nop,
                 modifier: Modifier::AB,
                 a_field: Field::direct(35),
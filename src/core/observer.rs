@@ -0,0 +1,298 @@
+//! Hooks for observing a simulation as it runs, e.g. to collect statistics,
+//! visualise the core or record a replay.
+
+use crate::load_file::{Instruction, Offset};
+
+use crate::core::cell::Cell;
+use crate::core::process;
+use crate::core::{Step, WarriorID};
+
+/// Callbacks made by [`Core::run_with_observer`](super::Core::run_with_observer)
+/// and [`Core::step_with_observer`](super::Core::step_with_observer). Every
+/// method has an empty default implementation, so an observer only needs to
+/// implement the events it is interested in.
+///
+/// For each step, the events are reported in this order:
+/// 1. [`cell_read`](Self::cell_read) and [`cell_written`](Self::cell_written),
+///    in the order the instruction accessed the core. Every instruction
+///    reads exactly two cells, its A operand and then its B operand, so the
+///    reads of a step are its A and B pointers.
+/// 2. [`task_spawned`](Self::task_spawned) or [`task_killed`](Self::task_killed)
+/// 3. [`instruction_executed`](Self::instruction_executed)
+/// 4. [`warrior_eliminated`](Self::warrior_eliminated)
+pub trait CoreObserver {
+    /// A task executed an instruction
+    fn instruction_executed(&mut self, _step: &Step) {}
+
+    /// A warrior read a cell as the A or B operand of an instruction
+    fn cell_read(&mut self, _warrior: WarriorID, _offset: Offset) {}
+
+    /// A warrior wrote to a cell, either as the result of an instruction or
+    /// by incrementing or decrementing an indirect pointer
+    fn cell_written(
+        &mut self,
+        _warrior: WarriorID,
+        _offset: Offset,
+        _old: &Instruction,
+        _new: &Instruction,
+    ) {
+    }
+
+    /// A warrior started a new task with `SPL`
+    fn task_spawned(&mut self, _warrior: WarriorID, _thread: usize, _offset: Offset) {}
+
+    /// A task of a warrior was terminated
+    fn task_killed(&mut self, _warrior: WarriorID, _thread: usize, _error: &process::Error) {}
+
+    /// A warrior's last task was terminated, so it has lost
+    fn warrior_eliminated(&mut self, _warrior: WarriorID) {}
+}
+
+/// Reports every event to both of its observers, e.g. to record a replay
+/// and a trace of the same battle
+pub struct Tee<'a>(pub &'a mut dyn CoreObserver, pub &'a mut dyn CoreObserver);
+
+impl CoreObserver for Tee<'_> {
+    fn instruction_executed(&mut self, step: &Step) {
+        self.0.instruction_executed(step);
+        self.1.instruction_executed(step);
+    }
+
+    fn cell_read(&mut self, warrior: WarriorID, offset: Offset) {
+        self.0.cell_read(warrior, offset);
+        self.1.cell_read(warrior, offset);
+    }
+
+    fn cell_written(
+        &mut self,
+        warrior: WarriorID,
+        offset: Offset,
+        old: &Instruction,
+        new: &Instruction,
+    ) {
+        self.0.cell_written(warrior, offset, old, new);
+        self.1.cell_written(warrior, offset, old, new);
+    }
+
+    fn task_spawned(&mut self, warrior: WarriorID, thread: usize, offset: Offset) {
+        self.0.task_spawned(warrior, thread, offset);
+        self.1.task_spawned(warrior, thread, offset);
+    }
+
+    fn task_killed(&mut self, warrior: WarriorID, thread: usize, error: &process::Error) {
+        self.0.task_killed(warrior, thread, error);
+        self.1.task_killed(warrior, thread, error);
+    }
+
+    fn warrior_eliminated(&mut self, warrior: WarriorID) {
+        self.0.warrior_eliminated(warrior);
+        self.1.warrior_eliminated(warrior);
+    }
+}
+
+/// An access to the core made while executing an instruction. These are
+/// buffered during a step and reported to the observer afterwards.
+#[derive(Clone, Debug)]
+pub(super) enum Access {
+    Read(Offset),
+    Write {
+        offset: Offset,
+        old: Cell,
+        new: Cell,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use test_case::test_case;
+
+    use super::*;
+
+    use crate::core::tests::{build_core, build_core_with};
+    use crate::core::{BattleResult, CoreConfig, Dispatch, StepOutcome, TaskOutcome};
+    use crate::load_file::{Field, Opcode};
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Event {
+        Executed(i32),
+        Read(i32),
+        Written(i32, Instruction),
+        Spawned(usize, i32),
+        Killed(usize, process::Error),
+        Eliminated(WarriorID),
+    }
+
+    #[derive(Default)]
+    struct Recorder(Vec<Event>);
+
+    impl CoreObserver for Recorder {
+        fn instruction_executed(&mut self, step: &Step) {
+            self.0.push(Event::Executed(step.program_counter.value()));
+        }
+
+        fn cell_read(&mut self, _warrior: WarriorID, offset: Offset) {
+            self.0.push(Event::Read(offset.value()));
+        }
+
+        fn cell_written(
+            &mut self,
+            _warrior: WarriorID,
+            offset: Offset,
+            _old: &Instruction,
+            new: &Instruction,
+        ) {
+            self.0.push(Event::Written(offset.value(), new.clone()));
+        }
+
+        fn task_spawned(&mut self, _warrior: WarriorID, thread: usize, offset: Offset) {
+            self.0.push(Event::Spawned(thread, offset.value()));
+        }
+
+        fn task_killed(&mut self, _warrior: WarriorID, thread: usize, error: &process::Error) {
+            self.0.push(Event::Killed(thread, error.clone()));
+        }
+
+        fn warrior_eliminated(&mut self, warrior: WarriorID) {
+            self.0.push(Event::Eliminated(warrior));
+        }
+    }
+
+    #[test]
+    fn observe_events() {
+        for &dispatch in &[Dispatch::Decoded, Dispatch::Generic] {
+            let (mut core, _) = build_core_with(
+                CoreConfig {
+                    dispatch,
+                    ..CoreConfig::default()
+                },
+                &["
+                spl 2
+                dat 0, 0
+                mov 0, 2
+                "],
+            );
+
+            let mut recorder = Recorder::default();
+            let results = core
+                .run_with_observer(&mut recorder)
+                .expect("Failed to run");
+            assert!(matches!(
+                results[&0],
+                BattleResult::Loss(process::Error::ExecuteDat(_))
+            ));
+
+            let offset = |value| Offset::new(value, core.len());
+            let mov = Instruction::new(Opcode::Mov, Field::direct(0), Field::direct(2));
+
+            assert_eq!(
+                recorder.0,
+                vec![
+                    Event::Read(2),
+                    Event::Read(0),
+                    Event::Spawned(1, 2),
+                    Event::Executed(0),
+                    Event::Read(1),
+                    Event::Read(1),
+                    Event::Killed(0, process::Error::ExecuteDat(offset(1))),
+                    Event::Executed(1),
+                    Event::Read(2),
+                    Event::Read(4),
+                    Event::Written(4, mov),
+                    Event::Executed(2),
+                    Event::Read(3),
+                    Event::Read(3),
+                    Event::Killed(1, process::Error::ExecuteDat(offset(3))),
+                    Event::Executed(3),
+                    Event::Eliminated(0),
+                ],
+                "events differ with {:?} dispatch",
+                dispatch,
+            );
+        }
+    }
+
+    #[test]
+    fn step_reports_task_outcome() {
+        let mut core = build_core("spl 0");
+        let mut recorder = Recorder::default();
+
+        let outcome = core
+            .step_with_observer(&mut recorder)
+            .expect("Failed to step");
+
+        assert!(matches!(
+            outcome,
+            StepOutcome::Executed(Step {
+                task: TaskOutcome::Split(_),
+                ..
+            })
+        ));
+        assert_eq!(
+            recorder.0,
+            vec![
+                Event::Read(0),
+                Event::Read(0),
+                Event::Spawned(1, 0),
+                Event::Executed(0),
+            ]
+        );
+    }
+
+    #[test_case("cmp.i 0, 2"; "cmp i")]
+    #[test_case("seq.f 0, 2"; "seq f")]
+    #[test_case("sne.x 0, 2"; "sne x")]
+    #[test_case("slt.ab 0, 2"; "slt ab")]
+    #[test_case("jmz.f 0, 2"; "jmz f")]
+    #[test_case("jmn.b 0, 2"; "jmn b")]
+    fn comparisons_do_not_write(instruction: &str) {
+        use pretty_assertions::assert_eq;
+
+        let program = format!(
+            "
+            {}
+            jmp -1
+            dat 0, 0
+            ",
+            instruction
+        );
+
+        let run = |dispatch| {
+            let (mut core, _) = build_core_with(
+                CoreConfig {
+                    core_size: 16,
+                    max_cycles: 10,
+                    min_distance: 4,
+                    max_warrior_length: 4,
+                    dispatch,
+                    cell_stats: true,
+                    ..CoreConfig::default()
+                },
+                &[&program],
+            );
+            let mut recorder = Recorder::default();
+            core.run_with_observer(&mut recorder)
+                .expect("Failed to run");
+            (core, recorder.0)
+        };
+
+        let (decoded, decoded_events) = run(Dispatch::Decoded);
+        let (generic, generic_events) = run(Dispatch::Generic);
+
+        assert_eq!(decoded.warrior_stats()[0].cells_written, 0);
+        assert!(!decoded_events
+            .iter()
+            .any(|event| matches!(event, Event::Written(..))));
+
+        assert_eq!(decoded.warrior_stats(), generic.warrior_stats());
+        assert_eq!(decoded_events, generic_events);
+        for index in 0..decoded.len() {
+            let offset = decoded.offset(index);
+            assert_eq!(decoded.last_writer(index), generic.last_writer(index));
+            assert_eq!(
+                decoded.cell_stats().unwrap().writes(offset),
+                generic.cell_stats().unwrap().writes(offset)
+            );
+        }
+    }
+}
@@ -8,7 +8,7 @@ use crate::core::modifier;
 use crate::core::process;
 use crate::core::Core;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct Executed {
     pub program_counter_offset: Option<Offset>,
     pub should_split: bool,
@@ -18,7 +18,7 @@ pub struct Executed {
 // TODO
 #[allow(clippy::too_many_lines)]
 pub fn execute(core: &mut Core, program_counter: Offset) -> Result<Executed, process::Error> {
-    let instruction = core.get_offset(program_counter).clone();
+    let instruction = core.get_offset(program_counter);
     let opcode = instruction.opcode;
 
     // These are basically just useful constants that some opcodes need to use
@@ -146,7 +146,7 @@ pub fn execute(core: &mut Core, program_counter: Offset) -> Result<Executed, pro
         }
 
         // P-space opcodes
-        Opcode::Ldp | Opcode::Stp => unimplemented!(),
+        Opcode::Ldp | Opcode::Stp => return Err(process::Error::UnsupportedOpcode(opcode)),
     }
 
     Ok(Executed {
@@ -189,7 +189,7 @@ mod tests {
 
             assert_eq!(err, Error::ExecuteDat(pc));
             assert_eq!(
-                &core.instructions[1..=2],
+                &core.instructions().collect::<Vec<_>>()[1..=2],
                 &[
                     Instruction::new(Opcode::Dat, Field::direct(0), Field::direct(1)),
                     Instruction::new(Opcode::Dat, Field::direct(0), Field::direct(1)),
@@ -211,7 +211,7 @@ mod tests {
             assert!(result.program_counter_offset.is_none());
 
             assert_eq!(
-                &core.instructions[..4],
+                &core.instructions().collect::<Vec<_>>()[..4],
                 &vec![
                     instruction.clone(),
                     instruction,
@@ -221,6 +221,20 @@ mod tests {
             );
         }
 
+        #[test]
+        fn execute_mov_copies_address_modes() {
+            let mut core = build_core(
+                "
+                mov.i $1, $2
+                dat   #1, <2
+                ",
+            );
+            let pc = core.offset(0);
+            execute(&mut core, pc).expect("Failed to execute");
+
+            assert_eq!(core.get(2), core.get(1));
+        }
+
         #[test]
         fn execute_nop() {
             let mut core = build_core("nop #0, #0");
@@ -257,7 +271,7 @@ mod tests {
             assert!(result.program_counter_offset.is_none());
 
             assert_eq!(
-                *core.get(2),
+                core.get(2),
                 Instruction::new(
                     Opcode::Dat,
                     Field::immediate(expected_result),
@@ -283,7 +297,7 @@ mod tests {
             // The a-operand should be from before the predecrement, but the
             // b-operand should be from after it, resulting in (1+1=2, 1+0=1)
             assert_eq!(
-                *core.get(1),
+                core.get(1),
                 Instruction::new(Opcode::Dat, Field::direct(2), Field::direct(1))
             );
         }
@@ -310,7 +324,7 @@ mod tests {
             assert!(result.program_counter_offset.is_none());
 
             assert_eq!(
-                *core.get(2),
+                core.get(2),
                 Instruction::new(Opcode::Dat, Field::immediate(2), Field::immediate(3)),
             );
         }
@@ -345,7 +359,7 @@ mod tests {
             let err = execute(&mut core, pc).unwrap_err();
 
             assert_eq!(err, Error::DivideByZero);
-            assert_eq!(core.get(2), result);
+            assert_eq!(&core.get(2), result);
         }
 
         #[test]
@@ -362,7 +376,7 @@ mod tests {
             assert!(result.program_counter_offset.is_none());
 
             assert_eq!(
-                *core.get(2),
+                core.get(2),
                 Instruction::new(Opcode::Dat, Field::immediate(0), Field::immediate(1)),
             );
         }
@@ -397,7 +411,7 @@ mod tests {
             let err = execute(&mut core, pc).unwrap_err();
 
             assert_eq!(err, Error::DivideByZero);
-            assert_eq!(core.get(2), result);
+            assert_eq!(&core.get(2), result);
         }
     }
 
@@ -529,7 +543,7 @@ mod tests {
 
             assert_eq!(result.program_counter_offset, None);
             assert_eq!(
-                &core.instructions[1..4],
+                &core.instructions().collect::<Vec<_>>()[1..4],
                 &vec![
                     Instruction {
                         opcode: Opcode::Djn,
@@ -558,7 +572,7 @@ mod tests {
 
             assert_eq!(result.program_counter_offset, Some(core.offset(2)));
             assert_eq!(
-                &core.instructions[1..4],
+                &core.instructions().collect::<Vec<_>>()[1..4],
                 &vec![
                     Instruction {
                         opcode: Opcode::Djn,
@@ -617,7 +631,7 @@ mod tests {
 
             assert_eq!(result.program_counter_offset, Some(core.offset(3)));
             assert_eq!(
-                &core.instructions[1..5],
+                &core.instructions().collect::<Vec<_>>()[1..5],
                 &vec![
                     Instruction::new(Opcode::Jmp, Field::direct(3), Field::immediate(0)),
                     Instruction::default(),
@@ -641,7 +655,7 @@ mod tests {
             assert_eq!(result.program_counter_offset, Some(core.offset(3)));
             assert!(result.should_split);
             assert_eq!(
-                &core.instructions[1..5],
+                &core.instructions().collect::<Vec<_>>()[1..5],
                 &vec![
                     Instruction::new(Opcode::Spl, Field::direct(3), Field::immediate(0)),
                     Instruction::default(),
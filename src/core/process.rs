@@ -1,15 +1,16 @@
 /// Container for managing the process queue of warriors. A given core has
 /// a single queue, but the queue itself may have numerous "threads" of execution
 /// and determines what process is scheduled when.
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::VecDeque;
 
 use thiserror::Error as ThisError;
 
 use crate::core::WarriorID;
 
 use crate::core::Offset;
+use crate::load_file::Opcode;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct Entry {
     pub id: WarriorID,
     pub thread: usize,
@@ -18,28 +19,33 @@ pub struct Entry {
 
 /// A representation of the process queue. This is effectively a simple FIFO queue.
 // TODO enforce size limits based on MAXPROCESSES
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Queue {
     /// The actual offsets enqueued to be executed
     queue: VecDeque<Entry>,
 
-    /// A map of process names to the number of tasks each has in the queue.
+    /// The number of tasks each process has in the queue, indexed by warrior ID.
     /// This is updated whenever instructions are added to/removed from the queue,
     /// and can be used to determine whether a process is alive or not.
-    processes: BTreeMap<WarriorID, usize>,
+    processes: Vec<usize>,
 
-    /// An increasing counter per process to give unique thread ids
-    next_thread_id: BTreeMap<WarriorID, usize>,
+    /// An increasing counter per process to give unique thread ids, indexed by
+    /// warrior ID.
+    next_thread_id: Vec<usize>,
 }
 
 impl Queue {
     /// Create an empty queue
     pub fn new() -> Self {
-        Self {
-            queue: VecDeque::new(),
-            processes: BTreeMap::new(),
-            next_thread_id: BTreeMap::new(),
-        }
+        Self::default()
+    }
+
+    /// Remove all entries and processes from the queue. The allocated capacity
+    /// is kept so the queue can be reused for another battle.
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.processes.clear();
+        self.next_thread_id.clear();
     }
 
     /// Get the next offset for execution, removing it from the queue.
@@ -47,10 +53,8 @@ impl Queue {
         self.queue
             .pop_front()
             .map_or(Err(Error::NoRemainingProcesses), |entry| {
-                let decremented = self.processes[&entry.id].saturating_sub(1);
-                self.processes
-                    .entry(entry.id)
-                    .and_modify(|count| *count = decremented);
+                let count = &mut self.processes[entry.id];
+                *count = count.saturating_sub(1);
 
                 Ok(entry)
             })
@@ -59,15 +63,20 @@ impl Queue {
     /// Get the next offset for execution without modifying the queue.
     // TODO: this should probably just return Option<&ProcessEntry>
     pub fn peek(&self) -> Result<&Entry, Error> {
-        self.queue.get(0).ok_or(Error::NoRemainingProcesses)
+        self.queue.front().ok_or(Error::NoRemainingProcesses)
     }
 
     /// Add an entry to the process queue. If specified, it will use the given thread ID,
     /// otherwise a new thread ID will be created based on the current number of
-    /// threads active for this process name.
-    pub fn push(&mut self, warrior_id: WarriorID, offset: Offset, thread: Option<usize>) {
+    /// threads active for this process name. Returns the thread ID of the entry.
+    pub fn push(&mut self, warrior_id: WarriorID, offset: Offset, thread: Option<usize>) -> usize {
+        if warrior_id >= self.processes.len() {
+            self.processes.resize(warrior_id + 1, 0);
+            self.next_thread_id.resize(warrior_id + 1, 0);
+        }
+
         let thread_id = thread.unwrap_or_else(|| {
-            let entry = self.next_thread_id.entry(warrior_id).or_insert(0);
+            let entry = &mut self.next_thread_id[warrior_id];
             let id = *entry;
             *entry += 1;
             id
@@ -79,18 +88,54 @@ impl Queue {
             offset,
         });
 
-        *self.processes.entry(warrior_id).or_insert(0) += 1;
+        self.processes[warrior_id] += 1;
+
+        thread_id
+    }
+
+    /// Put an entry back at the front of the queue, undoing a [`pop`](Self::pop)
+    pub(super) fn unpop(&mut self, entry: Entry) {
+        self.processes[entry.id] += 1;
+        self.queue.push_front(entry);
+    }
+
+    /// Remove the entry at the back of the queue, undoing a [`push`](Self::push).
+    /// `new_thread` must be set if the push created a new thread ID, so that
+    /// the ID is given out again by the next push.
+    pub(super) fn unpush(&mut self, new_thread: bool) {
+        if let Some(entry) = self.queue.pop_back() {
+            self.processes[entry.id] -= 1;
+            if new_thread {
+                self.next_thread_id[entry.id] -= 1;
+            }
+        }
+    }
+
+    /// Iterate over the entries in the queue, from the next to be executed
+    /// to the last.
+    pub fn iter(&self) -> impl Iterator<Item = &Entry> {
+        self.queue.iter()
+    }
+
+    /// The total number of entries in the queue, across all processes
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Whether there are no entries left in the queue
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
     }
 
     /// Check the status of a process in the queue. Panics if the process was
     /// never added to the queue.
     pub fn thread_count(&self, warrrior_id: WarriorID) -> usize {
-        self.processes[&warrrior_id]
+        self.processes[warrrior_id]
     }
 }
 
 /// An process-related error occurred
-#[derive(ThisError, Debug, Eq, PartialEq)]
+#[derive(ThisError, Clone, Debug, Eq, PartialEq)]
 pub enum Error {
     /// All processes terminated
     #[error("no process running to execute")]
@@ -103,6 +148,10 @@ pub enum Error {
     /// The warrior attempted to execute a division by zero
     #[error("division by 0")]
     DivideByZero,
+
+    /// The warrior attempted to execute an opcode which isn't supported yet
+    #[error("{0} is not supported")]
+    UnsupportedOpcode(Opcode),
 }
 
 #[cfg(test)]
@@ -210,4 +259,48 @@ mod tests {
         );
         assert!(queue.thread_count(1) > 0);
     }
+
+    #[test]
+    fn undo_pop_and_push() {
+        let mut queue = Queue::new();
+        let starting_offset = Offset::new(10, 8000);
+
+        queue.push(0, starting_offset, None);
+        let entry = queue.pop().unwrap();
+        queue.push(0, starting_offset + 1, Some(entry.thread));
+        queue.push(0, starting_offset + 2, None);
+
+        queue.unpush(true);
+        queue.unpush(false);
+        queue.unpop(entry);
+        assert_eq!(queue.iter().collect::<Vec<_>>(), vec![&entry]);
+        assert_eq!(queue.thread_count(0), 1);
+
+        // The new thread ID is reused
+        assert_eq!(queue.push(0, starting_offset, None), 1);
+    }
+
+    #[test]
+    fn clear_queue() {
+        let mut queue = Queue::new();
+        let starting_offset = Offset::new(10, 8000);
+
+        queue.push(0, starting_offset, None);
+        queue.push(1, starting_offset, None);
+        queue.clear();
+
+        assert_eq!(queue.peek().unwrap_err(), Error::NoRemainingProcesses);
+
+        // Thread IDs start over after clearing
+        queue.push(0, starting_offset, None);
+        assert_eq!(
+            queue.peek().unwrap(),
+            &Entry {
+                id: 0,
+                thread: 0,
+                offset: starting_offset
+            }
+        );
+        assert_eq!(queue.thread_count(0), 1);
+    }
 }
@@ -0,0 +1,329 @@
+//! Statistics about a battle: [`WarriorStats`] for each warrior, the
+//! [`Owners`] of each cell, and optional [`CellStats`] about how each cell of
+//! the core was used, e.g. for visualisation or studying bombing and scanning
+//! patterns. Cell statistics are only collected when
+//! [`CoreConfig::cell_stats`](super::CoreConfig::cell_stats) is set, since
+//! they slow down the simulation.
+
+use std::fmt;
+
+use crate::core::WarriorID;
+use crate::load_file::Offset;
+
+/// Statistics about a warrior in the current battle, from
+/// [`Core::warrior_stats`](super::Core::warrior_stats)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WarriorStats {
+    /// The number of cycles run when the warrior's last task died, or `None`
+    /// if the warrior is still alive
+    pub death_cycle: Option<i32>,
+
+    /// The most tasks the warrior had at once
+    pub peak_tasks: usize,
+
+    /// The number of tasks the warrior has now
+    pub final_tasks: usize,
+
+    /// The number of instructions the warrior's tasks executed
+    pub instructions_executed: u32,
+
+    /// The number of times the warrior's instructions wrote to a cell,
+    /// including increments and decrements of indirect pointers
+    pub cells_written: u32,
+
+    /// Who wrote the instruction that killed the warrior's last task, if it
+    /// was eliminated
+    pub killed_by: Option<Killer>,
+}
+
+/// The author of the instruction which killed a warrior
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Killer {
+    /// The instruction was written by a warrior, or loaded as part of it. If
+    /// this is the warrior that died, it destroyed itself.
+    Warrior(WarriorID),
+
+    /// No warrior wrote the instruction, so the warrior ran into empty core
+    Unowned,
+}
+
+impl fmt::Display for Killer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Warrior(warrior) => write!(f, "warrior {}", warrior),
+            Self::Unowned => f.write_str("empty core"),
+        }
+    }
+}
+
+/// The warrior which last wrote to each cell of the core, or was loaded into
+/// it. Unlike [`CellStats`] this is always kept, to find
+/// [`WarriorStats::killed_by`], so owners are packed into 32 bits each.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(super) struct Owners(Vec<u32>);
+
+impl Owners {
+    const UNOWNED: u32 = u32::MAX;
+
+    pub fn new(core_size: usize) -> Self {
+        Self(vec![Self::UNOWNED; core_size])
+    }
+
+    pub fn reset(&mut self) {
+        self.0.fill(Self::UNOWNED);
+    }
+
+    /// The owner of a cell, or `None` if no warrior has touched it
+    pub fn get(&self, offset: Offset) -> Option<WarriorID> {
+        match self.0[offset.value() as usize] {
+            Self::UNOWNED => None,
+            owner => Some(owner as WarriorID),
+        }
+    }
+
+    pub fn set(&mut self, offset: Offset, owner: Option<WarriorID>) {
+        self.0[offset.value() as usize] = owner.map_or(Self::UNOWNED, |owner| {
+            u32::try_from(owner).expect("warrior ID does not fit in 32 bits")
+        });
+    }
+}
+
+/// Side tables with an entry for each cell of the core. Counts only include
+/// accesses made by instructions, not loading warriors or [`Core::set`](super::Core::set).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CellStats {
+    writes: Vec<u32>,
+    executions: Vec<u32>,
+    reads: Vec<u32>,
+}
+
+impl CellStats {
+    pub(super) fn new(core_size: usize) -> Self {
+        Self {
+            writes: vec![0; core_size],
+            executions: vec![0; core_size],
+            reads: vec![0; core_size],
+        }
+    }
+
+    pub(super) fn reset(&mut self) {
+        self.writes.fill(0);
+        self.executions.fill(0);
+        self.reads.fill(0);
+    }
+
+    pub(super) fn record_write(&mut self, offset: Offset) {
+        self.writes[offset.value() as usize] += 1;
+    }
+
+    pub(super) fn record_execution(&mut self, offset: Offset) {
+        self.executions[offset.value() as usize] += 1;
+    }
+
+    pub(super) fn record_read(&mut self, offset: Offset) {
+        self.reads[offset.value() as usize] += 1;
+    }
+
+    pub(super) fn undo_write(&mut self, offset: Offset) {
+        self.writes[offset.value() as usize] -= 1;
+    }
+
+    pub(super) fn undo_execution(&mut self, offset: Offset) {
+        self.executions[offset.value() as usize] -= 1;
+    }
+
+    pub(super) fn undo_read(&mut self, offset: Offset) {
+        self.reads[offset.value() as usize] -= 1;
+    }
+
+    /// The number of times a cell was written to, including increments and
+    /// decrements of indirect pointers
+    #[must_use]
+    pub fn writes(&self, offset: Offset) -> u32 {
+        self.writes[offset.value() as usize]
+    }
+
+    /// The number of times a task executed the instruction in a cell
+    #[must_use]
+    pub fn executions(&self, offset: Offset) -> u32 {
+        self.executions[offset.value() as usize]
+    }
+
+    /// The number of times a cell was read as the A or B operand of an
+    /// instruction
+    #[must_use]
+    pub fn reads(&self, offset: Offset) -> u32 {
+        self.reads[offset.value() as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    use crate::core::tests::build_core_with;
+    use crate::core::{Core, CoreConfig, Dispatch};
+
+    fn run_with_stats(programs: &[&str], dispatch: Dispatch) -> Core {
+        run(programs, dispatch, true)
+    }
+
+    fn run(programs: &[&str], dispatch: Dispatch, cell_stats: bool) -> Core {
+        let (mut core, _) = build_core_with(
+            CoreConfig {
+                core_size: 32,
+                max_cycles: 10,
+                min_distance: 8,
+                max_warrior_length: 8,
+                dispatch,
+                cell_stats,
+                ..CoreConfig::default()
+            },
+            programs,
+        );
+        core.run().expect("Failed to run");
+        core
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let core = Core::default();
+        assert!(core.cell_stats().is_none());
+    }
+
+    #[test]
+    fn count_accesses() {
+        for &dispatch in &[Dispatch::Decoded, Dispatch::Generic] {
+            // Bomb backwards through the core, decrementing the pointer first
+            let core = run_with_stats(
+                &[
+                    "
+                          mov bomb, <ptr
+                          jmp -1
+                    ptr   dat 0, -2
+                    bomb  dat 0, 0
+                    ",
+                    "jmp 0",
+                ],
+                dispatch,
+            );
+            let stats = core.cell_stats().expect("Stats were not collected");
+            let offset = |value| core.offset(value);
+
+            // Each warrior executes 5 of the 10 cycles
+            assert_eq!(stats.executions(offset(0)), 3, "{:?}", dispatch);
+            assert_eq!(stats.executions(offset(1)), 2, "{:?}", dispatch);
+            assert_eq!(stats.executions(offset(16)), 5, "{:?}", dispatch);
+            assert_eq!(stats.executions(offset(2)), 0, "{:?}", dispatch);
+
+            // Each MOV reads the bomb and decrements the pointer...
+            assert_eq!(stats.reads(offset(3)), 3, "{:?}", dispatch);
+            assert_eq!(stats.writes(offset(2)), 3, "{:?}", dispatch);
+            assert_eq!(core.last_writer(2), Some(0), "{:?}", dispatch);
+
+            // ...then drops the bomb below the warrior
+            for bomb in 29..32 {
+                assert_eq!(stats.reads(offset(bomb)), 1, "{:?}", dispatch);
+                assert_eq!(stats.writes(offset(bomb)), 1, "{:?}", dispatch);
+                assert_eq!(core.last_writer(bomb), Some(0), "{:?}", dispatch);
+            }
+
+            // Loading a warrior sets the owner without counting as a write
+            assert_eq!(core.last_writer(16), Some(1), "{:?}", dispatch);
+            assert_eq!(stats.writes(offset(16)), 0, "{:?}", dispatch);
+            assert_eq!(core.last_writer(8), None, "{:?}", dispatch);
+        }
+    }
+
+    #[test]
+    fn reset_stats() {
+        let mut core = run_with_stats(&["jmp 0"], Dispatch::Decoded);
+        assert_eq!(core.cell_stats().unwrap().executions(core.offset(0)), 10);
+
+        core.reset();
+        let stats = core.cell_stats().expect("Stats were not collected");
+        assert_eq!(stats.executions(core.offset(0)), 0);
+        assert_eq!(core.last_writer(0), None);
+    }
+
+    #[test]
+    fn warrior_killed_by_bomb() {
+        for &cell_stats in &[false, true] {
+            let core = run(
+                &[
+                    "
+                    mov 2, 16
+                    jmp -1
+                    dat 0, 0
+                    ",
+                    "jmp 0",
+                ],
+                Dispatch::Decoded,
+                cell_stats,
+            );
+
+            assert_eq!(
+                core.warrior_stats(),
+                vec![
+                    WarriorStats {
+                        death_cycle: None,
+                        peak_tasks: 1,
+                        final_tasks: 1,
+                        instructions_executed: 1,
+                        cells_written: 1,
+                        killed_by: None,
+                    },
+                    WarriorStats {
+                        death_cycle: Some(2),
+                        peak_tasks: 1,
+                        final_tasks: 0,
+                        instructions_executed: 1,
+                        cells_written: 0,
+                        killed_by: Some(Killer::Warrior(0)),
+                    },
+                ],
+                "cell_stats: {}",
+                cell_stats
+            );
+        }
+    }
+
+    #[test]
+    fn warrior_killed_by_itself() {
+        let core = run_with_stats(
+            &["
+            spl 1
+            dat 0, 0
+            "],
+            Dispatch::Decoded,
+        );
+
+        assert_eq!(
+            core.warrior_stats(),
+            vec![WarriorStats {
+                death_cycle: Some(3),
+                peak_tasks: 2,
+                final_tasks: 0,
+                instructions_executed: 3,
+                cells_written: 0,
+                killed_by: Some(Killer::Warrior(0)),
+            }]
+        );
+    }
+
+    #[test]
+    fn warrior_killed_by_empty_core() {
+        let core = run_with_stats(&["nop 0, 0"], Dispatch::Generic);
+        let stats = &core.warrior_stats()[0];
+        assert_eq!(stats.death_cycle, Some(2));
+        assert_eq!(stats.killed_by, Some(Killer::Unowned));
+
+        // The author of the instruction is known without cell stats too
+        let core = run(&["nop 0, 0"], Dispatch::Generic, false);
+        let stats = &core.warrior_stats()[0];
+        assert_eq!(stats.death_cycle, Some(2));
+        assert_eq!(stats.killed_by, Some(Killer::Unowned));
+    }
+}
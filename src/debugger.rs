@@ -0,0 +1,736 @@
+//! An interactive debugger for stepping through a battle, in the style of the
+//! pMARS `cdb` debugger.
+//!
+//! A [`Debugger`] reads commands line by line and writes their output, so the
+//! same session can be driven from a terminal or from a test:
+//!
+//! | Command                       | Effect                                                 |
+//! |-------------------------------|--------------------------------------------------------|
+//! | `step [n]`, `s`               | Execute `n` cycles (default 1), printing each one      |
+//! | `continue`, `c`               | Execute cycles until a breakpoint or the end of battle |
+//! | `skip [n]`                    | Execute `n` cycles silently, ignoring breakpoints      |
+//! | `back [n]`                    | Undo `n` cycles (default 1), if the core keeps a journal |
+//! | `list [address] [n]`, `l`     | List `n` cells (default 11) around an address (default the PC) |
+//! | `queue`, `t`                  | Show the task queue, from the next task to execute     |
+//! | `edit <address> <instruction>`, `e` | Write an instruction into the core               |
+//! | `break [address or opcode]`, `b` | Add a breakpoint, or list breakpoints without an argument |
+//! | `delete <n>`, `d`             | Delete the breakpoint with the given number           |
+//! | `dump`                        | Print the whole core                                   |
+//! | `help`, `h`                   | Print a summary of commands                            |
+//! | `quit`, `q`                   | Leave the debugger                                     |
+//!
+//! An empty line repeats the previous command. Addresses are absolute, and
+//! may be given in decimal or as hex with a `0x` prefix.
+//!
+//! Stepping back needs a core created with [`CoreConfig::journal`](crate::core::CoreConfig::journal)
+//! set. Editing the core with `edit` clears the journal, so the battle can't
+//! be stepped back past an edit.
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+use thiserror::Error as ThisError;
+
+use crate::core::{self, Core, StepOutcome, TaskOutcome};
+use crate::load_file::{Instruction, Offset, Opcode, Warrior};
+use crate::parser;
+
+/// The number of cells shown by `list` when no count is given
+const DEFAULT_LIST_LENGTH: i32 = 11;
+
+const HELP: &str = "\
+step [n]                     execute n cycles (default 1), printing each one
+continue                     execute cycles until a breakpoint or the end of battle
+skip [n]                     execute n cycles silently, ignoring breakpoints
+back [n]                     undo n cycles (default 1)
+list [address] [n]           list n cells around an address (default the PC)
+queue                        show the task queue
+edit <address> <instruction> write an instruction into the core
+break [address or opcode]    add a breakpoint, or list breakpoints
+delete <n>                   delete a breakpoint
+dump                         print the whole core
+help                         print this summary
+quit                         leave the debugger
+";
+
+/// An error in a debugger command
+#[derive(ThisError, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    /// The command was not recognised
+    #[error("unknown command {0:?}; type \"help\" for a list of commands")]
+    UnknownCommand(String),
+
+    /// A command was missing a required argument
+    #[error("{0} requires an argument")]
+    MissingArgument(&'static str),
+
+    /// An argument to a command could not be parsed
+    #[error("invalid argument {0:?}")]
+    InvalidArgument(String),
+
+    /// The instruction given to `edit` could not be parsed
+    #[error("invalid instruction: {0}")]
+    InvalidInstruction(String),
+
+    /// There was no breakpoint with the given number
+    #[error("no breakpoint {0}")]
+    NoSuchBreakpoint(usize),
+
+    /// The core was not created with a journal, so it can't step back
+    #[error("cannot step back without a journal of the battle")]
+    JournalDisabled,
+
+    #[error(transparent)]
+    Core(#[from] core::Error),
+}
+
+/// A condition which stops `continue` before the next task executes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Stop when the next task will execute the cell at this address
+    Address(i32),
+
+    /// Stop when the next task will execute an instruction with this opcode
+    Opcode(Opcode),
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Address(address) => write!(f, "address {:#06x}", address),
+            Self::Opcode(opcode) => write!(f, "opcode {}", opcode),
+        }
+    }
+}
+
+/// A command given to the debugger
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Step(usize),
+    Continue,
+    Skip(usize),
+    Back(usize),
+    List { address: Option<i32>, count: i32 },
+    Queue,
+    Edit(i32, Instruction),
+    Break(Option<Breakpoint>),
+    Delete(usize),
+    Dump,
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim();
+        let (name, rest) = line
+            .split_once(char::is_whitespace)
+            .map_or((line, ""), |(name, rest)| (name, rest.trim()));
+        let mut args = rest.split_whitespace();
+
+        let command = match name {
+            "step" | "s" => Self::Step(parse_optional(args.next())?.unwrap_or(1)),
+            "continue" | "c" => Self::Continue,
+            "skip" => Self::Skip(parse_optional(args.next())?.unwrap_or(1)),
+            "back" => Self::Back(parse_optional(args.next())?.unwrap_or(1)),
+            "list" | "l" => Self::List {
+                address: args.next().map(parse_address).transpose()?,
+                count: parse_optional(args.next())?.unwrap_or(DEFAULT_LIST_LENGTH),
+            },
+            "queue" | "t" => Self::Queue,
+            "edit" | "e" => {
+                let (address, instruction) = rest
+                    .split_once(char::is_whitespace)
+                    .ok_or(Error::MissingArgument("edit"))?;
+                Self::Edit(parse_address(address)?, parse_instruction(instruction)?)
+            }
+            "break" | "b" => Self::Break(args.next().map(parse_breakpoint).transpose()?),
+            "delete" | "d" => {
+                Self::Delete(parse_optional(args.next())?.ok_or(Error::MissingArgument("delete"))?)
+            }
+            "dump" => Self::Dump,
+            "help" | "h" | "?" => Self::Help,
+            "quit" | "q" | "exit" => Self::Quit,
+            _ => return Err(Error::UnknownCommand(name.to_owned())),
+        };
+
+        match args.next() {
+            Some(arg) if !matches!(command, Self::Edit(..)) => {
+                Err(Error::InvalidArgument(arg.to_owned()))
+            }
+            _ => Ok(command),
+        }
+    }
+}
+
+fn parse_optional<T: FromStr>(arg: Option<&str>) -> Result<Option<T>, Error> {
+    arg.map(|arg| {
+        arg.parse()
+            .map_err(|_| Error::InvalidArgument(arg.to_owned()))
+    })
+    .transpose()
+}
+
+fn parse_address(arg: &str) -> Result<i32, Error> {
+    let parsed = match arg.strip_prefix("0x") {
+        Some(hex) => i32::from_str_radix(hex, 16),
+        None => arg.parse(),
+    };
+    parsed.map_err(|_| Error::InvalidArgument(arg.to_owned()))
+}
+
+fn parse_breakpoint(arg: &str) -> Result<Breakpoint, Error> {
+    parse_address(arg).map(Breakpoint::Address).or_else(|_| {
+        Opcode::from_str(&arg.to_uppercase())
+            .map(Breakpoint::Opcode)
+            .map_err(|_| Error::InvalidArgument(arg.to_owned()))
+    })
+}
+
+fn parse_instruction(text: &str) -> Result<Instruction, Error> {
+    match parser::parse(text) {
+        parser::Result::Ok(warrior, _) => warrior
+            .program
+            .instructions
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::InvalidInstruction(text.to_owned())),
+        parser::Result::Err(err, _) => Err(Error::InvalidInstruction(err.to_string())),
+    }
+}
+
+/// An interactive debugging session for a core with warriors loaded into it
+pub struct Debugger {
+    core: Core,
+    names: Vec<String>,
+    breakpoints: Vec<Breakpoint>,
+}
+
+impl Debugger {
+    /// Debug a core, which `warriors` have already been loaded into
+    #[must_use]
+    pub fn new(core: Core, warriors: &[Warrior]) -> Self {
+        let names = warriors
+            .iter()
+            .enumerate()
+            .map(|(id, warrior)| {
+                warrior
+                    .metadata
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| id.to_string())
+            })
+            .collect();
+
+        Self {
+            core,
+            names,
+            breakpoints: Vec::new(),
+        }
+    }
+
+    /// Get the core being debugged
+    #[must_use]
+    pub fn core(&self) -> &Core {
+        &self.core
+    }
+
+    /// Get the current breakpoints, in the order they were added
+    #[must_use]
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Read commands from `input` until it ends or a `quit` command, writing
+    /// a prompt before each command. Errors in commands are reported to
+    /// `output` and do not end the session.
+    pub fn run(&mut self, input: impl BufRead, mut output: impl Write) -> Result<(), Error> {
+        let mut lines = input.lines();
+        let mut previous: Option<Command> = None;
+
+        loop {
+            write!(output, "(cdb) ")?;
+            output.flush()?;
+
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => break,
+            };
+
+            let command = if line.trim().is_empty() {
+                match previous.take() {
+                    Some(command) => command,
+                    None => continue,
+                }
+            } else {
+                match line.parse() {
+                    Ok(command) => command,
+                    Err(err) => {
+                        writeln!(output, "{}", err)?;
+                        continue;
+                    }
+                }
+            };
+
+            if command == Command::Quit {
+                break;
+            }
+            if let Err(err) = self.execute(&command, &mut output) {
+                writeln!(output, "{}", err)?;
+            }
+            previous = Some(command);
+        }
+
+        Ok(())
+    }
+
+    /// Execute a single command, writing its output
+    pub fn execute(&mut self, command: &Command, output: &mut dyn Write) -> Result<(), Error> {
+        match command {
+            Command::Step(count) => {
+                for _ in 0..*count {
+                    if !self.step(output, true)? {
+                        break;
+                    }
+                }
+            }
+            Command::Continue => {
+                while self.step(output, false)? {
+                    if let Some(breakpoint) = self.breakpoint_hit() {
+                        writeln!(output, "Stopped at {}", breakpoint)?;
+                        self.list(None, 1, output)?;
+                        break;
+                    }
+                }
+            }
+            Command::Skip(count) => {
+                for _ in 0..*count {
+                    if !self.step(output, false)? {
+                        break;
+                    }
+                }
+            }
+            Command::Back(count) => {
+                if !self.core.config().journal {
+                    return Err(Error::JournalDisabled);
+                }
+                for _ in 0..*count {
+                    if !self.core.step_back() {
+                        writeln!(output, "No earlier steps to go back to")?;
+                        break;
+                    }
+                }
+                self.list(None, 1, output)?;
+            }
+            Command::List { address, count } => self.list(*address, *count, output)?,
+            Command::Queue => {
+                for (position, entry) in self.core.process_queue().iter().enumerate() {
+                    writeln!(
+                        output,
+                        "{:>4} {:<20} thread {:<4} {:#06x} {}",
+                        position,
+                        self.names[entry.id],
+                        entry.thread,
+                        entry.offset.value(),
+                        self.core.get(entry.offset.value()),
+                    )?;
+                }
+            }
+            Command::Edit(address, instruction) => {
+                self.core.set(*address, instruction.clone());
+                self.list(Some(*address), 1, output)?;
+            }
+            Command::Break(None) => {
+                for (number, breakpoint) in self.breakpoints.iter().enumerate() {
+                    writeln!(output, "{:>4} {}", number, breakpoint)?;
+                }
+            }
+            Command::Break(Some(breakpoint)) => {
+                let breakpoint = match breakpoint {
+                    Breakpoint::Address(address) => {
+                        Breakpoint::Address(self.offset(*address).value())
+                    }
+                    Breakpoint::Opcode(_) => breakpoint.clone(),
+                };
+                writeln!(
+                    output,
+                    "Breakpoint {} at {}",
+                    self.breakpoints.len(),
+                    breakpoint
+                )?;
+                self.breakpoints.push(breakpoint);
+            }
+            Command::Delete(number) => {
+                if *number >= self.breakpoints.len() {
+                    return Err(Error::NoSuchBreakpoint(*number));
+                }
+                self.breakpoints.remove(*number);
+            }
+            Command::Dump => write!(output, "{:?}", self.core)?,
+            Command::Help => write!(output, "{}", HELP)?,
+            Command::Quit => {}
+        }
+
+        Ok(())
+    }
+
+    /// Execute a single cycle, unless the battle is over. Returns whether a
+    /// cycle was executed.
+    fn step(&mut self, output: &mut dyn Write, print: bool) -> Result<bool, Error> {
+        if self.core.is_finished() {
+            writeln!(
+                output,
+                "The battle is over after {} cycles",
+                self.core.steps_taken()
+            )?;
+            return Ok(false);
+        }
+
+        let cycle = self.core.steps_taken();
+        let step = match self.core.step()? {
+            StepOutcome::Executed(step) => step,
+            StepOutcome::Halted => return Ok(false),
+        };
+
+        if print {
+            write!(
+                output,
+                "{:>6} {:<20} {:#06x} {}",
+                cycle,
+                self.names[step.warrior],
+                step.program_counter.value(),
+                step.instruction,
+            )?;
+            if let TaskOutcome::Died(err) = &step.task {
+                write!(output, "  ; task {} died: {}", step.thread, err)?;
+            }
+            writeln!(output)?;
+        }
+        if step.eliminated {
+            writeln!(output, "{} was eliminated", self.names[step.warrior])?;
+        }
+
+        Ok(true)
+    }
+
+    /// Find a breakpoint matching the next task to be executed
+    fn breakpoint_hit(&self) -> Option<&Breakpoint> {
+        let next = self.core.process_queue().peek().ok()?.offset;
+        let instruction = self.core.get(next.value());
+
+        self.breakpoints.iter().find(|breakpoint| match breakpoint {
+            Breakpoint::Address(address) => *address == next.value(),
+            Breakpoint::Opcode(opcode) => *opcode == instruction.opcode,
+        })
+    }
+
+    /// List `count` cells centred on `address`, or the next task's PC
+    fn list(&self, address: Option<i32>, count: i32, output: &mut dyn Write) -> Result<(), Error> {
+        let next = self
+            .core
+            .process_queue()
+            .peek()
+            .ok()
+            .map(|entry| entry.offset);
+        let centre = match (address, next) {
+            (Some(address), _) => address,
+            (None, Some(next)) => next.value(),
+            (None, None) => 0,
+        };
+
+        for i in 0..count {
+            let offset = self.offset(centre - count / 2 + i);
+            let marker = if Some(offset) == next { '>' } else { ' ' };
+            let breakpoint = if self
+                .breakpoints
+                .contains(&Breakpoint::Address(offset.value()))
+            {
+                '*'
+            } else {
+                ' '
+            };
+            writeln!(
+                output,
+                "{}{} {:#06x} {}",
+                marker,
+                breakpoint,
+                offset.value(),
+                self.core.get(offset.value()),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn offset(&self, address: i32) -> Offset {
+        Offset::new(address, self.core.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    use crate::core::tests::build_core_with;
+    use crate::core::CoreConfig;
+    use crate::load_file::Field;
+
+    fn build_debugger(program: &str) -> Debugger {
+        let (core, warriors) = build_core_with(
+            CoreConfig {
+                core_size: 16,
+                max_cycles: 100,
+                min_distance: 4,
+                max_warrior_length: 4,
+                journal: true,
+                ..CoreConfig::default()
+            },
+            &[program],
+        );
+        Debugger::new(core, &warriors)
+    }
+
+    fn run_session(debugger: &mut Debugger, input: &str) -> String {
+        let mut output = Vec::new();
+        debugger
+            .run(input.as_bytes(), &mut output)
+            .expect("Debugger session failed");
+        String::from_utf8(output).expect("Output was not UTF-8")
+    }
+
+    #[test]
+    fn parse_commands() {
+        let cases = [
+            ("s", Command::Step(1)),
+            ("step 5", Command::Step(5)),
+            ("c", Command::Continue),
+            ("skip 100", Command::Skip(100)),
+            ("back", Command::Back(1)),
+            (
+                "l",
+                Command::List {
+                    address: None,
+                    count: DEFAULT_LIST_LENGTH,
+                },
+            ),
+            (
+                "list 0x10 3",
+                Command::List {
+                    address: Some(16),
+                    count: 3,
+                },
+            ),
+            ("queue", Command::Queue),
+            (
+                "edit 3 mov 0, 1",
+                Command::Edit(
+                    3,
+                    Instruction::new(Opcode::Mov, Field::direct(0), Field::direct(1)),
+                ),
+            ),
+            ("b", Command::Break(None)),
+            ("b -2", Command::Break(Some(Breakpoint::Address(-2)))),
+            (
+                "break djn",
+                Command::Break(Some(Breakpoint::Opcode(Opcode::Djn))),
+            ),
+            ("d 0", Command::Delete(0)),
+            ("dump", Command::Dump),
+            ("q", Command::Quit),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(input.parse::<Command>().unwrap(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn parse_invalid_commands() {
+        assert!(matches!(
+            "jump".parse::<Command>(),
+            Err(Error::UnknownCommand(_))
+        ));
+        assert!(matches!(
+            "step x".parse::<Command>(),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            "step 1 2".parse::<Command>(),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            "break foo".parse::<Command>(),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            "delete".parse::<Command>(),
+            Err(Error::MissingArgument(_))
+        ));
+        assert!(matches!(
+            "edit 3".parse::<Command>(),
+            Err(Error::MissingArgument(_))
+        ));
+    }
+
+    #[test]
+    fn step_and_repeat() {
+        let mut debugger = build_debugger(
+            "
+            add #1, 3
+            jmp -1
+            ",
+        );
+
+        let output = run_session(&mut debugger, "step\n\nstep 2\n");
+
+        assert_eq!(debugger.core().steps_taken(), 4);
+        assert_eq!(
+            output.lines().collect::<Vec<_>>(),
+            vec![
+                "(cdb)      0 0                    0x0000 ADD.AB  #1,     $3",
+                "(cdb)      1 0                    0x0001 JMP.B   $15,    $0",
+                "(cdb)      2 0                    0x0000 ADD.AB  #1,     $3",
+                "     3 0                    0x0001 JMP.B   $15,    $0",
+                "(cdb) ",
+            ]
+        );
+    }
+
+    #[test]
+    fn continue_to_breakpoints() {
+        let mut debugger = build_debugger(
+            "
+            add #1, 3
+            nop 0, 0
+            jmp -2
+            ",
+        );
+
+        run_session(&mut debugger, "break nop\nb 2\nc\n");
+        assert_eq!(debugger.core().steps_taken(), 1);
+
+        run_session(&mut debugger, "c\n");
+        assert_eq!(debugger.core().steps_taken(), 2);
+
+        run_session(&mut debugger, "delete 0\nc\n");
+        assert_eq!(debugger.core().steps_taken(), 5);
+        assert_eq!(debugger.breakpoints(), &[Breakpoint::Address(2)]);
+    }
+
+    #[test]
+    fn continue_to_end_of_battle() {
+        let mut debugger = build_debugger("jmp 0");
+
+        let output = run_session(&mut debugger, "c\nskip 10\n");
+
+        assert_eq!(debugger.core().steps_taken(), 100);
+        assert!(output.contains("The battle is over after 100 cycles"));
+    }
+
+    #[test]
+    fn step_until_eliminated() {
+        let mut debugger = build_debugger(
+            "
+            nop 0, 0
+            dat 0, 0
+            ",
+        );
+
+        let output = run_session(&mut debugger, "step 3\n");
+
+        assert_eq!(debugger.core().steps_taken(), 2);
+        assert!(output.contains("task 0 died: reached a DAT at offset 1"));
+        assert!(output.contains("0 was eliminated"));
+        assert!(output.contains("The battle is over after 2 cycles"));
+    }
+
+    #[test]
+    fn skip_ignores_breakpoints() {
+        let mut debugger = build_debugger("jmp 0");
+
+        let output = run_session(&mut debugger, "break 0\nskip 10\n");
+
+        assert_eq!(debugger.core().steps_taken(), 10);
+        assert_eq!(output, "(cdb) Breakpoint 0 at address 0x0000\n(cdb) (cdb) ");
+    }
+
+    #[test]
+    fn step_back() {
+        let mut debugger = build_debugger(
+            "
+            mov 2, 3
+            jmp -1
+            dat 0, 0
+            ",
+        );
+
+        let output = run_session(&mut debugger, "skip 3\nback 2\nback 5\n");
+
+        assert_eq!(debugger.core().steps_taken(), 0);
+        assert_eq!(debugger.core().get(3), Instruction::default());
+        assert_eq!(
+            output.lines().collect::<Vec<_>>(),
+            vec![
+                "(cdb) (cdb) >  0x0001 JMP.B   $15,    $0",
+                "(cdb) No earlier steps to go back to",
+                ">  0x0000 MOV.I   $2,     $3",
+                "(cdb) ",
+            ]
+        );
+
+        let core = Core::new(CoreConfig::default()).expect("Failed to create core");
+        let mut debugger = Debugger::new(core, &[]);
+        let output = run_session(&mut debugger, "back\n");
+        assert!(output.contains("cannot step back without a journal"));
+    }
+
+    #[test]
+    fn list_and_edit() {
+        let mut debugger = build_debugger(
+            "
+            spl 2
+            jmp 0
+            ",
+        );
+
+        let output = run_session(&mut debugger, "s\nb 1\nedit -1 mov 0, 1\nlist 1 5\nqueue\n");
+
+        assert_eq!(
+            debugger.core().get(15),
+            Instruction::new(Opcode::Mov, Field::direct(0), Field::direct(1))
+        );
+        assert_eq!(
+            output.lines().skip(3).collect::<Vec<_>>(),
+            vec![
+                "(cdb)    0x000f MOV.I   $0,     $1",
+                "   0x0000 SPL.B   $2,     $0",
+                ">* 0x0001 JMP.B   $0,     $0",
+                "   0x0002 DAT.F   $0,     $0",
+                "   0x0003 DAT.F   $0,     $0",
+                "(cdb)    0 0                    thread 0    0x0001 JMP.B   $0,     $0",
+                "   1 0                    thread 1    0x0002 DAT.F   $0,     $0",
+                "(cdb) ",
+            ]
+        );
+    }
+
+    #[test]
+    fn report_errors_and_continue() {
+        let mut debugger = build_debugger("jmp 0");
+
+        let output = run_session(&mut debugger, "jump\ndelete 0\ns\nquit\ns\n");
+
+        assert_eq!(debugger.core().steps_taken(), 1);
+        assert!(output.contains("unknown command \"jump\""));
+        assert!(output.contains("no breakpoint 0"));
+    }
+}
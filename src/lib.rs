@@ -5,5 +5,12 @@
 #[macro_use]
 mod util;
 pub mod core;
+pub mod debugger;
 pub mod load_file;
 pub mod parser;
+#[cfg(feature = "render")]
+pub mod render;
+pub mod replay;
+#[cfg(feature = "trace")]
+pub mod trace;
+pub mod visualiser;
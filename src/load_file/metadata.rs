@@ -1,13 +1,18 @@
 //! Metadata about a Redcode program. Most of this is not used for execution,
 //! with some exceptions, namely `;redcode` and `;assertion`
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 
+/// Directives kept in [`Metadata::other`] without opting in, which are used
+/// by hills, e.g. `;kill` to remove a warrior
+pub const KNOWN_DIRECTIVES: &[&str] = &["contact", "kill", "url"];
+
 /// Metadata about a Redcode program that is stored in the comments.
 #[derive(Debug, Default, PartialEq, Eq)]
 pub struct Metadata {
-    /// The Redcode standard for this warrior (e.g. "94").
-    // TODO #38 handle directives like `redcode-94` etc.
+    /// The Redcode standard for this warrior, e.g. "94" for `;redcode-94`, or
+    /// empty for a plain `;redcode`.
     pub redcode: Option<String>,
 
     /// The name of this warrior.
@@ -22,66 +27,232 @@ pub struct Metadata {
     /// The version of this warrior.
     pub version: Option<String>,
 
-    /// A description of the warrior's strategy
-    // TODO #38 handle multiline strategies
+    /// A description of the warrior's strategy. Each `;strategy` line is
+    /// appended, separated by newlines.
     pub strategy: Option<String>,
 
     /// An assertion for this warrior to ensure compilation.
     pub assertion: Option<String>,
+
+    /// Any other directives, like `;kill`, keyed by the lowercase directive,
+    /// with the value of each line in order. To avoid treating ordinary
+    /// comments as directives, only the [`KNOWN_DIRECTIVES`] and those opted
+    /// in to are kept, and only on lines without code, with no whitespace
+    /// between the `;` and the directive.
+    pub other: BTreeMap<String, Vec<String>>,
 }
 
 impl Metadata {
     /// Parse warrior metadata out of a line. Any comments will be removed and
     /// the resulting string returned, with whitespace trimmed.
     pub fn parse_line(&mut self, line: &str) -> String {
-        let split_line: Vec<&str> = line.splitn(2, ';').map(str::trim).collect();
-
-        if split_line.len() > 1 {
-            let split_comment: Vec<&str> = split_line[1].splitn(2, char::is_whitespace).collect();
-            let value = Some(
-                split_comment
-                    .get(1)
-                    .map_or_else(String::new, |s| s.trim().to_owned()),
-            );
-
-            let directive = split_comment[0].to_lowercase();
-            match directive.as_ref() {
-                "redcode" => self.redcode = value,
-                "name" => self.name = value,
-                "author" => self.author = value,
-                "date" => self.date = value,
-                "version" => self.version = value,
-                "strategy" => self.strategy = value,
-                "assert" => self.assertion = value,
-                _ => (),
+        self.parse_line_with_directives(line, &BTreeSet::new())
+    }
+
+    /// Parse warrior metadata out of a line like [`parse_line`](Self::parse_line),
+    /// also keeping the lowercase `directives` in [`other`](Self::other)
+    pub fn parse_line_with_directives(
+        &mut self,
+        line: &str,
+        directives: &BTreeSet<String>,
+    ) -> String {
+        let (code, comment) = line.split_once(';').unwrap_or((line, ""));
+
+        let mut split_comment = comment.trim().splitn(2, char::is_whitespace);
+        let directive = split_comment.next().unwrap_or_default().to_lowercase();
+        let value = split_comment.next().unwrap_or_default().trim().to_owned();
+
+        match directive.as_ref() {
+            "" => (),
+            "redcode" => self.redcode = Some(value),
+            // Variants like `;redcode-94` or `;redcode-x`
+            variant if variant.starts_with("redcode-") => {
+                self.redcode = Some(variant["redcode-".len()..].to_owned());
             }
+            "name" => self.name = Some(value),
+            "author" => self.author = Some(value),
+            "date" => self.date = Some(value),
+            "version" => self.version = Some(value),
+            "strategy" => match &mut self.strategy {
+                Some(strategy) => {
+                    strategy.push('\n');
+                    strategy.push_str(&value);
+                }
+                None => self.strategy = Some(value),
+            },
+            "assert" => self.assertion = Some(value),
+            directive
+                if code.trim().is_empty()
+                    && !comment.starts_with(char::is_whitespace)
+                    && (KNOWN_DIRECTIVES.contains(&directive)
+                        || directives.contains(directive)) =>
+            {
+                self.other
+                    .entry(directive.to_owned())
+                    .or_default()
+                    .push(value);
+            }
+            _ => (),
         }
 
-        split_line[0].trim().to_string()
+        code.trim().to_string()
     }
 }
 
 impl fmt::Display for Metadata {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        for (field, name) in &[
-            (&self.redcode, "redcode"),
-            (&self.name, "name"),
-            (&self.author, "author"),
-            (&self.version, "version"),
-            (&self.date, "date"),
-            (&self.strategy, "strategy"),
-            (&self.assertion, "assert"),
-        ] {
-            if let Some(value) = field.as_deref() {
-                if value.is_empty() {
-                    writeln!(formatter, ";{}", name)?;
-                } else {
-                    writeln!(formatter, ";{} {}", name, value)?;
-                }
+        match self.redcode.as_deref() {
+            Some("") => writeln!(formatter, ";redcode")?,
+            Some(variant) => writeln!(formatter, ";redcode-{}", variant)?,
+            None => {}
+        }
+
+        let fields = [
+            ("name", &self.name),
+            ("author", &self.author),
+            ("version", &self.version),
+            ("date", &self.date),
+            ("strategy", &self.strategy),
+            ("assert", &self.assertion),
+        ];
+        let fields = fields
+            .iter()
+            .filter_map(|(name, field)| Some((*name, field.as_deref()?.split('\n'))))
+            .flat_map(|(name, lines)| lines.map(move |line| (name, line)))
+            .chain(self.other.iter().flat_map(|(name, values)| {
+                values
+                    .iter()
+                    .map(move |value| (name.as_str(), value.as_str()))
+            }));
+
+        // Multi-line strategies are written as one directive per line
+        for (name, line) in fields {
+            if line.is_empty() {
+                writeln!(formatter, ";{}", name)?;
+            } else {
+                writeln!(formatter, ";{} {}", name, line)?;
             }
         }
         Ok(())
     }
 }
 
-// TODO as part of #38 test parse_line
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(";redcode", ""; "plain")]
+    #[test_case(";redcode-94", "94"; "94")]
+    #[test_case(";Redcode-X verbose", "x"; "variant with text")]
+    #[test_case("; redcode 94", "94"; "separate version")]
+    fn parse_redcode(line: &str, expected: &str) {
+        let mut metadata = Metadata::default();
+
+        assert_eq!(metadata.parse_line(line), "");
+        assert_eq!(metadata.redcode.as_deref(), Some(expected));
+    }
+
+    #[test]
+    fn round_trip_redcode() {
+        let mut metadata = Metadata::default();
+        metadata.parse_line(";redcode-94");
+        metadata.parse_line(";name Imp");
+
+        assert_eq!(metadata.to_string(), ";redcode-94\n;name Imp\n");
+    }
+
+    #[test]
+    fn multiline_strategy() {
+        let mut metadata = Metadata::default();
+        metadata.parse_line(";strategy Quick-scan -> incendiary bomber.");
+        metadata.parse_line(";strategy");
+        metadata.parse_line("  ;strategy  Bombs with spl 0 first.");
+
+        assert_eq!(
+            metadata.strategy.as_deref(),
+            Some("Quick-scan -> incendiary bomber.\n\nBombs with spl 0 first.")
+        );
+    }
+
+    #[test_case(";kill Phosphorus", Some("Phosphorus"); "kill")]
+    #[test_case(";KILL", Some(""); "without value")]
+    #[test_case("  ;kill Imp", Some("Imp"); "indented")]
+    #[test_case("; kill the imp", None; "comment with space")]
+    #[test_case("mov 0, 1 ;kill counter", None; "after code")]
+    #[test_case(";kill: the imp", None; "not a word")]
+    #[test_case(";spl 0, kill", None; "commented out code")]
+    fn parse_kill(line: &str, expected: Option<&str>) {
+        let mut metadata = Metadata::default();
+        metadata.parse_line(line);
+
+        let expected: BTreeMap<_, _> = expected
+            .map(|value| ("kill".to_owned(), vec![value.to_owned()]))
+            .into_iter()
+            .collect();
+        assert_eq!(metadata.other, expected);
+    }
+
+    #[test]
+    fn ignore_unknown_directives() {
+        let mut metadata = Metadata::default();
+        metadata.parse_line(";and loop forever");
+        metadata.parse_line(";THE CORE CLEAR");
+
+        assert_eq!(metadata.other, BTreeMap::new());
+    }
+
+    #[test]
+    fn opt_in_directives() {
+        let directives = ["hill".to_owned()].into_iter().collect();
+        let mut metadata = Metadata::default();
+        metadata.parse_line_with_directives(";hill 94nop", &directives);
+        metadata.parse_line_with_directives(";mini HSA", &directives);
+
+        assert_eq!(
+            metadata.other,
+            [("hill".to_owned(), vec!["94nop".to_owned()])]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn repeated_directives() {
+        let mut metadata = Metadata::default();
+        metadata.parse_line(";kill A");
+        metadata.parse_line(";kill B");
+
+        assert_eq!(metadata.other["kill"], vec!["A", "B"]);
+        assert_eq!(metadata.to_string(), ";kill A\n;kill B\n");
+    }
+
+    #[test]
+    fn round_trip_all() {
+        let input = ";redcode-94
+;name Thermite 1.0
+;author Robert Macrae
+;strategy Quick-scan -> incendiary bomber.
+;strategy
+;strategy Bombs with spl 0 first.
+;assert CORESIZE == 8000
+;contact someone@example.com
+;kill Phosphorus
+;kill Thermite
+;url http://example.com
+";
+        let mut metadata = Metadata::default();
+        for line in input.lines() {
+            metadata.parse_line(line);
+        }
+
+        assert_eq!(metadata.to_string(), input);
+
+        let mut reparsed = Metadata::default();
+        for line in metadata.to_string().lines() {
+            reparsed.parse_line(line);
+        }
+        assert_eq!(reparsed, metadata);
+    }
+}
@@ -1,35 +1,16 @@
 use fmt::Debug;
-use std::{
-    convert::TryInto,
-    fmt::{self, Display},
-};
-
-use lazy_static::lazy_static;
-use maplit::hashmap;
+use std::fmt::{self, Display};
 
 mod metadata;
 mod offset;
 mod program;
 mod types;
 
-pub use metadata::Metadata;
+pub use metadata::{Metadata, KNOWN_DIRECTIVES};
 pub use offset::Offset;
 pub use program::{Instructions, LabelMap, Program};
 pub use types::{AddressMode, Modifier, Opcode, PseudoOpcode, Value};
 
-lazy_static! {
-    // TODO: handle command-line constant redefinition and things like
-    // CURLINE, VERSION, WARRIORS, PSPACESIZE
-    pub static ref DEFAULT_CONSTANTS: LabelMap = hashmap! {
-        "CORESIZE".into() => 8000,
-        "MAXPROCESSES".into() => 8000,
-        "MAXCYCLES".into() => 80_000,
-        "MAXLENGTH".into() => 100,
-        "MINDISTANCE".into() => 100,
-        "ROUNDS".into() => 1,
-    };
-}
-
 /// The main public struct used to represent a Redcode warrior
 #[derive(Debug, Default)]
 pub struct Warrior {
@@ -109,12 +90,7 @@ impl Field {
     }
 
     pub fn set_value(&mut self, offset: Offset) {
-        self.value = Value::Literal(
-            offset
-                .value()
-                .try_into()
-                .expect("Offset should always be convertible to i32"),
-        );
+        self.value = Value::Literal(offset.value());
     }
 }
 
@@ -1,10 +1,11 @@
 use std::fmt;
 
 enum_string! {
-    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    #[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
     pub enum Opcode {
         Add => "ADD",
         Cmp => "CMP",
+        #[default]
         Dat => "DAT",
         Div => "DIV",
         Djn => "DJN",
@@ -25,12 +26,6 @@ enum_string! {
     }
 }
 
-impl Default for Opcode {
-    fn default() -> Self {
-        Self::Dat
-    }
-}
-
 enum_string! {
     #[derive(Copy, Clone, Debug, PartialEq, Eq)]
     pub enum PseudoOpcode {
@@ -43,24 +38,19 @@ enum_string! {
 
 enum_string! {
     #[allow(clippy::upper_case_acronyms)]
-    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    #[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
     pub enum Modifier {
         A   => "A",
         B   => "B",
         AB  => "AB",
         BA  => "BA",
+        #[default]
         F   => "F",
         X   => "X",
         I   => "I",
     }
 }
 
-impl Default for Modifier {
-    fn default() -> Self {
-        Self::F
-    }
-}
-
 impl Modifier {
     #[must_use]
     pub fn default_88_to_94(opcode: Opcode, a_mode: AddressMode, b_mode: AddressMode) -> Self {
@@ -92,9 +82,10 @@ impl Modifier {
 }
 
 enum_string! {
-    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    #[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
     pub enum AddressMode {
         Immediate           => "#",
+        #[default]
         Direct              => "$",
         IndirectA           => "*",
         IndirectB           => "@",
@@ -105,12 +96,6 @@ enum_string! {
     }
 }
 
-impl Default for AddressMode {
-    fn default() -> Self {
-        Self::Direct
-    }
-}
-
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Value {
     Label(String),
@@ -0,0 +1,90 @@
+//! Settings for the environment a warrior is parsed for.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::core::CoreConfig;
+
+/// The value of `VERSION`. pMARS defines this as its version times 100, e.g.
+/// 94 for 0.9.4, which is the version whose Redcode extensions are supported.
+pub const VERSION: i32 = 94;
+
+/// The environment a warrior is parsed for. These values are available to
+/// the warrior as predefined labels, e.g. `CORESIZE`, so that it can adapt
+/// to the core or refuse to assemble with an `;assert`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseConfig {
+    /// The value of `CORESIZE`
+    pub core_size: i32,
+    /// The value of `MAXCYCLES`
+    pub max_cycles: i32,
+    /// The value of `MAXPROCESSES`
+    pub max_processes: i32,
+    /// The value of `MAXLENGTH`
+    pub max_warrior_length: i32,
+    /// The value of `MINDISTANCE`
+    pub min_distance: i32,
+    /// The value of `ROUNDS`
+    pub rounds: i32,
+    /// The value of `PSPACESIZE`
+    pub p_space_size: i32,
+    /// The value of `WARRIORS`, the number of warriors in the battle
+    pub warriors: i32,
+    /// Extra labels to define, e.g. from the command line. These take
+    /// precedence over any definitions of the same label in the warrior. The
+    /// predefined labels like `CORESIZE` are set by the fields above instead,
+    /// so defining one here is an [`Error::PredefinedLabel`](super::Error::PredefinedLabel).
+    pub labels: BTreeMap<String, i32>,
+    /// Lowercase directives to keep in [`Metadata::other`](crate::load_file::Metadata::other),
+    /// in addition to the [`KNOWN_DIRECTIVES`](crate::load_file::KNOWN_DIRECTIVES)
+    pub directives: BTreeSet<String>,
+}
+
+impl ParseConfig {
+    /// The predefined labels and their values
+    pub(super) fn constants(&self) -> Vec<(&'static str, i32)> {
+        vec![
+            ("CORESIZE", self.core_size),
+            ("MAXCYCLES", self.max_cycles),
+            ("MAXPROCESSES", self.max_processes),
+            ("MAXLENGTH", self.max_warrior_length),
+            ("MINDISTANCE", self.min_distance),
+            ("ROUNDS", self.rounds),
+            ("PSPACESIZE", self.p_space_size),
+            ("WARRIORS", self.warriors),
+            ("VERSION", VERSION),
+        ]
+    }
+
+    /// The first of `labels` which is a predefined label, if any
+    pub(super) fn redefined_constant(&self) -> Option<&str> {
+        let constants = self.constants();
+        self.labels.keys().map(String::as_str).find(|&label| {
+            label == "CURLINE" || constants.iter().any(|&(constant, _)| constant == label)
+        })
+    }
+}
+
+impl Default for ParseConfig {
+    fn default() -> Self {
+        Self::from(&CoreConfig::default())
+    }
+}
+
+/// Parse warriors for a core with the given config, for a single round with a
+/// single warrior
+impl From<&CoreConfig> for ParseConfig {
+    fn from(config: &CoreConfig) -> Self {
+        Self {
+            core_size: config.core_size,
+            max_cycles: config.max_cycles,
+            max_processes: config.max_processes,
+            max_warrior_length: config.max_warrior_length,
+            min_distance: config.min_distance,
+            rounds: 1,
+            p_space_size: config.p_space,
+            warriors: 1,
+            labels: BTreeMap::new(),
+            directives: BTreeSet::new(),
+        }
+    }
+}
@@ -6,7 +6,7 @@ use thiserror::Error as ThisError;
 
 use crate::load_file::Opcode;
 
-// TODO: use pest spans for error reporting? Or at least line number
+use super::Span;
 
 /// An error that occurred while parsing a warrior.
 #[derive(ThisError, Debug, PartialEq, Eq)]
@@ -14,19 +14,89 @@ use crate::load_file::Opcode;
 pub enum Error {
     /// The warrior contained a reference to a label that doesn't exist.
     #[error("no such label {label:?}")]
-    LabelNotFound { label: String, line: Option<usize> },
+    LabelNotFound { label: String, span: Span },
 
     /// An invalid warrior origin (not a positive integer) was specified.
     #[error("invalid origin specified")]
-    InvalidOrigin(#[from] TryFromIntError),
+    InvalidOrigin { source: TryFromIntError, span: Span },
 
     /// The input string was ill-formed Redcode syntax.
     #[error("invalid syntax")]
-    InvalidSyntax(#[from] super::grammar::SyntaxError),
+    InvalidSyntax {
+        source: Box<super::grammar::SyntaxError>,
+        span: Span,
+    },
 
     /// The given opcode was not given enough arguments.
     #[error("expected additional arguments for {opcode} opcode")]
-    InvalidArguments { opcode: Opcode },
+    InvalidArguments { opcode: Opcode, span: Span },
+
+    /// An `;assert` directive evaluated to zero, so the warrior refuses to
+    /// assemble for the current environment.
+    #[error("assertion {assertion:?} failed")]
+    AssertionFailed { assertion: String, span: Span },
+
+    /// An expression divided by zero, or took a remainder modulo zero.
+    #[error("division by zero in {expression:?}")]
+    DivideByZero { expression: String, span: Span },
+
+    /// A number or the result of an expression didn't fit in 32 bits.
+    #[error("{expression:?} is out of range for a 32-bit integer")]
+    Overflow { expression: String, span: Span },
+
+    /// `ORG` was used without an expression for the origin.
+    #[error("ORG must be given an argument")]
+    MissingOrigin { span: Span },
+
+    /// A line continuing an `EQU` appeared without an `EQU` to continue.
+    #[error("EQU continuation without a label to define")]
+    EquWithoutLabel { span: Span },
+
+    /// A `FOR` block was given a negative count, or one larger than
+    /// `MAXLENGTH`, since the copies couldn't fit in the warrior.
+    #[error("FOR count must be between 0 and MAXLENGTH ({max}), found {count}")]
+    InvalidForCount { count: i32, max: i32, span: Span },
+
+    /// A `FOR` block was never closed with `ROF`.
+    #[error("FOR without a matching ROF")]
+    UnclosedFor { span: Span },
+
+    /// `ROF` was used outside of a `FOR` block.
+    #[error("ROF without a matching FOR")]
+    UnmatchedRof { span: Span },
+
+    /// [`ParseConfig::labels`](super::ParseConfig::labels) defined one of
+    /// the predefined labels like `CORESIZE`. The span is empty, since the
+    /// label doesn't come from the input.
+    #[error("{label} is predefined and cannot be given as an extra label")]
+    PredefinedLabel { label: String, span: Span },
+
+    /// A line was left after expansion which is not an instruction.
+    #[error("expected an instruction, found {statement:?}")]
+    UnexpectedStatement { statement: String, span: Span },
+}
+
+impl Error {
+    /// Where in the input the error occurred
+    #[must_use]
+    pub fn span(&self) -> Span {
+        match self {
+            Self::LabelNotFound { span, .. }
+            | Self::InvalidOrigin { span, .. }
+            | Self::InvalidSyntax { span, .. }
+            | Self::InvalidArguments { span, .. }
+            | Self::AssertionFailed { span, .. }
+            | Self::DivideByZero { span, .. }
+            | Self::Overflow { span, .. }
+            | Self::MissingOrigin { span }
+            | Self::EquWithoutLabel { span }
+            | Self::InvalidForCount { span, .. }
+            | Self::UnclosedFor { span }
+            | Self::UnmatchedRof { span }
+            | Self::PredefinedLabel { span, .. }
+            | Self::UnexpectedStatement { span, .. } => *span,
+        }
+    }
 }
 
 /// A warning that occurred while parsing a warrior.
@@ -35,13 +105,35 @@ pub enum Error {
 pub enum Warning {
     /// Attempt to define the warrior origin more than once.
     #[error("origin already defined as {old:?}, new definition {new:?} will be ignored")]
-    OriginRedefinition { old: String, new: String },
+    OriginRedefinition {
+        old: String,
+        new: String,
+        span: Span,
+    },
 
     /// Empty EQU substitution.
-    #[error("right-hand side of substitution for label {0:?} is empty")]
-    EmptySubstitution(String),
+    #[error("right-hand side of substitution for label {label:?} is empty")]
+    EmptySubstitution { label: String, span: Span },
 
     /// Offset label declaration with no instruction.
-    #[error("no instruction offset for label {0:?}, it will not b")]
-    EmptyOffset(String),
+    #[error("no instruction offset for label {label:?}, it will not be used")]
+    EmptyOffset { label: String, span: Span },
+
+    /// A `FOR` block with a count of 0, e.g. a false condition, so its body
+    /// is dropped.
+    #[error("FOR count is 0, so {lines} line(s) will not be assembled")]
+    DroppedFor { lines: usize, span: Span },
+}
+
+impl Warning {
+    /// Where in the input the warning occurred
+    #[must_use]
+    pub fn span(&self) -> Span {
+        match self {
+            Self::OriginRedefinition { span, .. }
+            | Self::EmptySubstitution { span, .. }
+            | Self::EmptyOffset { span, .. }
+            | Self::DroppedFor { span, .. } => *span,
+        }
+    }
 }
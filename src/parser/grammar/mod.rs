@@ -1,12 +1,10 @@
 //! Definition and tests for the grammar that defines a valid line of Redcode.
 //! Provides helper function to tokenize strings into span-like tokens.
 
-use pest::error::ErrorVariant::CustomError;
+use pest::error::{ErrorVariant::CustomError, InputLocation};
 use pest::Parser as _;
 use pest_derive::Parser;
 
-use super::error::Error;
-
 pub type Pair<'a> = pest::iterators::Pair<'a, Rule>;
 pub type Pairs<'a> = pest::iterators::Pairs<'a, Rule>;
 pub type SyntaxError = pest::error::Error<Rule>;
@@ -17,7 +15,7 @@ pub struct Grammar;
 
 /// Parse an input line and flatten it to only include the terminal token pairs,
 /// i.e. pairs without any inner token pairs.
-pub fn tokenize(line: &str) -> Vec<Pair> {
+pub fn tokenize(line: &str) -> Vec<Pair<'_>> {
     parse_line(line)
         .map(|pairs| {
             pairs
@@ -29,26 +27,40 @@ pub fn tokenize(line: &str) -> Vec<Pair> {
         .unwrap_or_default()
 }
 
-/// Parse a single line of input according to the grammar.
-pub fn parse_line(line: &str) -> Result<Pairs, Error> {
+/// Parse a single line of input according to the grammar. The error is boxed
+/// since it is large, and rarely constructed.
+pub fn parse_line(line: &str) -> Result<Pairs<'_>, Box<SyntaxError>> {
     Ok(Grammar::parse(Rule::Line, line)?)
 }
 
 /// Parse a single expression as a string.
-pub fn parse_expression(line: &str) -> Result<Pair, Error> {
+pub fn parse_expression(line: &str) -> Result<Pair<'_>, Box<SyntaxError>> {
     let mut pairs = Grammar::parse(Rule::Expression, line)?;
 
     pairs
         .find(|pair| pair.as_rule() == Rule::Expression)
         .ok_or_else(|| {
-            SyntaxError::new_from_span(
+            Box::new(SyntaxError::new_from_span(
                 CustomError {
                     message: "Invalid expression".into(),
                 },
                 pest::Span::new(line, 0, line.len()).unwrap(),
-            )
-            .into()
+            ))
+        })
+}
+
+/// Find the token in `line` where a syntax error occurred, i.e. the text from
+/// the error's position up to the next whitespace or comma.
+pub fn error_token<'a>(err: &SyntaxError, line: &'a str) -> &'a str {
+    let start = match err.location {
+        InputLocation::Pos(start) | InputLocation::Span((start, _)) => start,
+    };
+    line.get(start..)
+        .and_then(|rest| {
+            rest.split(|c: char| c.is_whitespace() || c == ',')
+                .find(|token| !token.is_empty())
         })
+        .unwrap_or_default()
 }
 
 #[cfg(any(test, doctest))] // cfg(doctest) so we run the helper's doctest
@@ -290,7 +302,10 @@ mod test {
     #[test]
     fn parse_label() {
         match_parse!(label_input, LabelDeclaration {
-            "some_label" | "some_label2" | "DJNOFF" => [Label(0, label_input.len())],
+            "some_label" | "some_label2" | "DJNOFF" | "boot&i" | "a&b&c" => [
+                Label(0, label_input.len())
+            ],
+            "a&&b" => [Label(0, 1)],
             "a: " => [Label(0, 1)],
             " a " => [Label(1, 2)],
             "a :" => [Label(0, 1)],
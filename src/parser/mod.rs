@@ -5,38 +5,239 @@
 // TODO(#43)
 #![allow(clippy::missing_panics_doc)]
 
+pub use config::{ParseConfig, VERSION};
 pub use error::{Error, Warning};
 pub use result::Result;
+pub use span::Span;
 
+mod config;
 mod error;
 mod grammar;
 mod phase;
 mod result;
+mod span;
 
 use std::convert::TryFrom;
+use std::ops::Range;
 
 use crate::load_file::Warrior;
 
-use phase::{CommentsRemoved, Evaluated, Expanded, Output, Phase, Raw};
+use phase::{CommentsRemoved, Evaluated, Expanded, Failure, Output, Phase, Raw};
 
 /// Parse a given input string into a [`Result`](Result). If successful the
 /// `Result` will contain a `Warrior`, otherwise it will contain an error. In
 /// either case, one or more [`Warning`](error::Warning)s may be generated with
-/// the `Warrior`.
+/// the `Warrior`. Like pMARS, anything before the first `;redcode` line is
+/// ignored, and only the first warrior is parsed if there are several.
 pub fn parse(input: &str) -> Result<Warrior> {
-    parse_impl(input).into()
+    parse_with_config(input, &ParseConfig::default())
 }
 
-fn parse_impl(input: &str) -> std::result::Result<Warrior, Error> {
-    let raw = Phase::<Raw>::from(input);
+/// Parse a given input string like [`parse`](parse), for the environment
+/// described by `config`. This sets the values of predefined labels like
+/// `CORESIZE`, which are also used to check `;assert` directives. Defining a
+/// predefined label in [`ParseConfig::labels`] is an error.
+pub fn parse_with_config(input: &str, config: &ParseConfig) -> Result<Warrior> {
+    let warrior = phase::split_warriors(input).swap_remove(0);
 
-    let cleaned = Phase::<CommentsRemoved>::from(raw);
+    match parse_impl(input, warrior, config) {
+        Ok(output) => Result::Ok(output.state.warrior, output.warnings),
+        Err(Failure { error, warnings }) => Result::Err(error, warnings),
+    }
+}
+
+/// Count the warriors in the input, each starting at a `;redcode` line, as
+/// they would be parsed by [`parse_all`](parse_all). This is useful to set
+/// [`ParseConfig::warriors`] when the warriors come from several inputs.
+#[must_use]
+pub fn count_warriors(input: &str) -> usize {
+    phase::split_warriors(input).len()
+}
+
+/// Parse every warrior in the input, each starting at a `;redcode` line, like
+/// a file of several warriors submitted to a hill. The warnings for all the
+/// warriors are returned together.
+pub fn parse_all(input: &str) -> Result<Vec<Warrior>> {
+    parse_all_with_config(input, &ParseConfig::default())
+}
+
+/// Parse every warrior in the input like [`parse_all`](parse_all), for the
+/// environment described by `config`, which is checked as for
+/// [`parse_with_config`](parse_with_config).
+pub fn parse_all_with_config(input: &str, config: &ParseConfig) -> Result<Vec<Warrior>> {
+    let mut warriors = Vec::new();
+    let mut all_warnings = Vec::new();
 
-    let expanded = Phase::<Expanded>::from(cleaned);
+    for warrior in phase::split_warriors(input) {
+        match parse_impl(input, warrior, config) {
+            Ok(output) => {
+                warriors.push(output.state.warrior);
+                all_warnings.extend(output.warnings);
+            }
+            Err(Failure { error, warnings }) => {
+                all_warnings.extend(warnings);
+                return Result::Err(error, all_warnings);
+            }
+        }
+    }
+
+    Result::Ok(warriors, all_warnings)
+}
+
+fn parse_impl(
+    input: &str,
+    warrior: Range<usize>,
+    config: &ParseConfig,
+) -> std::result::Result<Phase<Output>, Failure> {
+    if let Some(label) = config.redefined_constant() {
+        return Err(Failure {
+            error: Error::PredefinedLabel {
+                label: label.to_owned(),
+                span: Span::default(),
+            },
+            warnings: Vec::new(),
+        });
+    }
+
+    let raw = Phase::<Raw>::new(input, warrior, config);
+
+    let cleaned = Phase::<CommentsRemoved>::try_from(raw)?;
+
+    let expanded = Phase::<Expanded>::try_from(cleaned)?;
 
     let evaluated = Phase::<Evaluated>::try_from(expanded)?;
 
-    let output = Phase::<Output>::from(evaluated);
+    Ok(Phase::<Output>::from(evaluated))
+}
+
+#[cfg(test)]
+mod test {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test]
+    fn parse_multiple_warriors() {
+        let input = "junk before the first warrior
+;redcode-94
+;name First
+mov 0, 1
+;redcode-94
+;name Second
+  jmp  missing
+";
+
+        assert_eq!(count_warriors(input), 2);
+        assert_eq!(count_warriors("mov 0, 1"), 1);
+
+        let first = parse(input).expect("Should parse the first warrior");
+        assert_eq!(first.metadata.name.as_deref(), Some("First"));
+        assert_eq!(first.metadata.redcode.as_deref(), Some("94"));
+        assert_eq!(first.len(), 1);
+
+        match parse_all(input) {
+            Result::Err(Error::LabelNotFound { label, span }, _) => {
+                assert_eq!(label, "missing");
+                assert_eq!((span.line, span.column), (7, 8));
+            }
+            other => panic!("Expected the second warrior to fail, got {:?}", other),
+        }
+
+        let warriors =
+            parse_all(&input.replace("missing", "-1")).expect("Should parse both warriors");
+        let names: Vec<_> = warriors
+            .iter()
+            .map(|warrior| warrior.metadata.name.as_deref())
+            .collect();
+        assert_eq!(names, vec![Some("First"), Some("Second")]);
+    }
+
+    #[test]
+    fn assert_predefined_labels() {
+        let input = ";redcode-94
+;assert WARRIORS == 2 && PSPACESIZE == CORESIZE / 16 && VERSION >= 80
+dat 0, 0
+";
+        let config = ParseConfig {
+            warriors: 2,
+            ..ParseConfig::default()
+        };
+
+        assert!(matches!(
+            parse(input),
+            Result::Err(Error::AssertionFailed { .. }, _)
+        ));
+        parse_with_config(input, &config).expect("Assertion should pass");
+    }
+
+    #[test_case("CORESIZE"; "core size")]
+    #[test_case("WARRIORS"; "warriors")]
+    #[test_case("CURLINE"; "current line")]
+    fn reject_predefined_labels(label: &str) {
+        use pretty_assertions::assert_eq;
+
+        let config = ParseConfig {
+            labels: [(label.to_owned(), 800), ("step".to_owned(), 4)]
+                .into_iter()
+                .collect(),
+            ..ParseConfig::default()
+        };
+
+        match parse_with_config("dat 0, 0", &config) {
+            Result::Err(Error::PredefinedLabel { label: found, .. }, _) => assert_eq!(found, label),
+            other => panic!("Expected {} to be rejected, got {:?}", label, other),
+        }
+        match parse_all_with_config("dat 0, 0", &config) {
+            Result::Err(Error::PredefinedLabel { label: found, .. }, _) => assert_eq!(found, label),
+            other => panic!("Expected {} to be rejected, got {:?}", label, other),
+        }
+    }
+
+    #[test_case("mov 0, 1/0", 1; "divide in field")]
+    #[test_case("mov 0, 5%0", 1; "modulo in field")]
+    #[test_case("x equ 1/0\nmov 0, x", 2; "divide in equ")]
+    #[test_case(";assert 1/0\ndat 0, 0", 1; "divide in assertion")]
+    #[test_case("for 2 % (1 - 1)\ndat 0, 0\nrof", 1; "modulo in for count")]
+    fn divide_by_zero(input: &str, line: usize) {
+        match parse(input) {
+            Result::Err(Error::DivideByZero { span, .. }, _) => assert_eq!(span.line, line),
+            other => panic!("Expected division by zero, got {:?}", other),
+        }
+    }
+
+    #[test_case("mov 0, 99999999999", 1; "large number")]
+    #[test_case("mov 0, 2147483647*2", 1; "product in field")]
+    #[test_case("for 2147483647*2\ndat 0, 0\nrof", 1; "product in for count")]
+    fn overflow(input: &str, line: usize) {
+        match parse(input) {
+            Result::Err(Error::Overflow { span, .. }, _) => assert_eq!(span.line, line),
+            other => panic!("Expected overflow, got {:?}", other),
+        }
+    }
+
+    #[test_case("a equ 5\nb equ a*2\n;assert b == 10", true; "nested equ")]
+    #[test_case("a equ 5\nb equ a*2\n;assert b == 11", false; "nested equ fails")]
+    #[test_case(";assert CURLINE == 0", true; "curline")]
+    #[test_case("x equ CURLINE + 1\n;assert x == 1", true; "curline in equ")]
+    fn assert_substitutes_labels(input: &str, passes: bool) {
+        let input = format!("{}\ndat 0, 0\nmov 0, 1", input);
+
+        match parse(&input) {
+            Result::Ok(..) => assert!(passes, "Assertion should fail"),
+            Result::Err(Error::AssertionFailed { .. }, _) => {
+                assert!(!passes, "Assertion should pass")
+            }
+            other => panic!("Unexpected parse result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn assert_recursive_equ() {
+        let input = "a equ b\nb equ a\n;assert a\ndat 0, 0";
 
-    Ok(output.state.warrior)
+        assert!(matches!(
+            parse(input),
+            Result::Err(Error::LabelNotFound { .. }, _)
+        ));
+    }
 }
@@ -2,11 +2,16 @@
 //! Any comments like `;redcode` and `;author` will be parsed and stored in
 //! [`Metadata`]. This phase also finds the origin and end of the program.
 
-use super::CommentsRemoved;
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use super::{Assertion, CommentsRemoved};
 
 use crate::load_file::Metadata;
 
+use crate::parser::error::{Error, Warning};
 use crate::parser::grammar;
+use crate::parser::span::SourceMap;
 
 enum OriginInLine {
     NewOrigin(String),
@@ -15,90 +20,170 @@ enum OriginInLine {
     NotFound,
 }
 
-/// Parse a raw String input and return the output sans comments, with metadata.
-pub fn extract_from_string(input: &str) -> CommentsRemoved {
+/// Find the lines of each warrior in the input. Like pMARS, each warrior starts
+/// at a line beginning with `;redcode`, and anything before the first one is
+/// ignored. Input without any `;redcode` is a single warrior.
+pub fn split_warriors(input: &str) -> Vec<Range<usize>> {
+    let mut starts: Vec<usize> = input
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            let line = line.trim_start().to_lowercase();
+            line.strip_prefix(";redcode").is_some_and(|rest| {
+                rest.is_empty() || rest.starts_with(|c: char| c == '-' || c.is_whitespace())
+            })
+        })
+        .map(|(number, _)| number)
+        .collect();
+
+    let line_count = input.lines().count();
+
+    if starts.is_empty() {
+        starts.push(0);
+    }
+
+    starts
+        .iter()
+        .zip(starts.iter().skip(1).chain(Some(&line_count)))
+        .map(|(&start, &end)| start..end)
+        .collect()
+}
+
+/// Parse the lines of a raw String input in the range `warrior`, and return
+/// the output sans comments, with metadata. The input line of each output line
+/// is added to `source_map`.
+pub fn extract_from_string(
+    input: &str,
+    warrior: Range<usize>,
+    directives: &BTreeSet<String>,
+    source_map: &mut SourceMap,
+    warnings: &mut Vec<Warning>,
+) -> Result<CommentsRemoved, Error> {
     let mut metadata = Metadata::default();
     let mut origin: Option<String> = None;
 
-    // Returns whether a new origin was set
-    let mut set_origin = |new_origin: String| {
+    let mut set_origin = |new_origin: String, line: usize, source_map: &mut SourceMap| {
         if let Some(old_origin) = origin.as_ref() {
-            // TODO (#25) proper warnings instead of just eprintln
-            eprintln!(
-                "Warning: ORG already defined as {:?}, new definition {:?} will be ignored",
-                new_origin, old_origin
-            );
+            if *old_origin == new_origin {
+                return;
+            }
+            warnings.push(Warning::OriginRedefinition {
+                span: source_map.input_span(line, &new_origin),
+                old: old_origin.clone(),
+                new: new_origin,
+            });
         } else {
             origin = Some(new_origin);
+            source_map.set_origin(line);
         }
     };
 
     let mut lines: Vec<String> = Vec::new();
+    let mut assertions = Vec::new();
+
+    for (number, line) in join_continued_lines(input) {
+        if !warrior.contains(&number) {
+            continue;
+        }
+
+        // Keep every assertion, even though the metadata only has the last
+        let previous_assertion = metadata.assertion.take();
+        let trimmed_line = metadata.parse_line_with_directives(&line, directives);
+        match &metadata.assertion {
+            Some(expression) => assertions.push(Assertion {
+                line: number,
+                expression: expression.clone(),
+            }),
+            None => metadata.assertion = previous_assertion,
+        }
 
-    for line in input.lines() {
-        let trimmed_line = metadata.parse_line(line);
         if trimmed_line.is_empty() {
             continue;
         }
 
-        if let Ok(found_origin) = find_origin_in_line(&trimmed_line) {
+        if let Some(found_origin) = find_origin_in_line(&trimmed_line) {
             match found_origin {
                 OriginInLine::NewOrigin(new_origin) => {
-                    set_origin(new_origin);
+                    set_origin(new_origin, number, source_map);
                 }
                 OriginInLine::EndWithNewOrigin(new_origin) => {
-                    set_origin(new_origin);
+                    set_origin(new_origin, number, source_map);
                     break;
                 }
                 OriginInLine::End => break,
-                OriginInLine::NotFound => lines.push(trimmed_line),
+                OriginInLine::NotFound => {
+                    lines.push(trimmed_line);
+                    source_map.push(number);
+                }
             }
         } else {
-            // TODO (#25) return error
+            let opcode = trimmed_line.split_whitespace().next().unwrap_or_default();
+            return Err(Error::MissingOrigin {
+                span: source_map.input_span(number, opcode),
+            });
         }
     }
 
-    CommentsRemoved {
+    Ok(CommentsRemoved {
         lines,
         metadata,
         origin,
+        assertions,
+    })
+}
+
+/// Join each line ending with `\` to the next line, like pMARS does, even
+/// within a comment. Each line is returned with the number of the first input
+/// line it came from.
+fn join_continued_lines(input: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    let mut continued = false;
+
+    for (number, line) in input.lines().enumerate() {
+        match lines.last_mut() {
+            Some((_, last)) if continued => last.push_str(line),
+            _ => lines.push((number, line.to_owned())),
+        }
+
+        if let Some((_, last)) = lines.last_mut() {
+            continued = last.trim_end().ends_with('\\');
+            if continued {
+                last.truncate(last.trim_end().len() - 1);
+            }
+        }
     }
+
+    lines
 }
 
-/// Find and return the origin defined in the given line.
-fn find_origin_in_line(line: &str) -> Result<OriginInLine, ()> {
+/// Find and return the origin defined in the given line, or `None` if the line
+/// is an `ORG` without an argument.
+fn find_origin_in_line(line: &str) -> Option<OriginInLine> {
     use OriginInLine::{End, EndWithNewOrigin, NewOrigin, NotFound};
 
     let tokenized = grammar::tokenize(line);
 
     if tokenized.is_empty() {
-        return Ok(NotFound);
+        return Some(NotFound);
     }
 
     match &tokenized[0].as_rule() {
-        grammar::Rule::Opcode => {
+        // On its own, e.g. `END`, an opcode is parsed as a label
+        grammar::Rule::Opcode | grammar::Rule::Label => {
             let remainder = tokenized
                 .get(1)
                 .map(|s| &line[s.as_span().start()..])
                 .filter(|s| !s.is_empty());
 
             match tokenized[0].as_str().to_uppercase().as_str() {
-                "ORG" => {
-                    if let Some(remainder) = remainder {
-                        Ok(NewOrigin(remainder.to_owned()))
-                    } else {
-                        // TODO (#25) proper error handling, probably in the return type
-                        eprintln!("Error: ORG must be given an argument!");
-                        Err(())
-                    }
+                "ORG" => remainder.map(|remainder| NewOrigin(remainder.to_owned())),
+                "END" => {
+                    Some(remainder.map_or(End, |remainder| EndWithNewOrigin(remainder.to_owned())))
                 }
-                "END" => remainder.map_or(Ok(End), |remainder| {
-                    Ok(EndWithNewOrigin(remainder.to_owned()))
-                }),
-                _ => Ok(NotFound),
+                _ => Some(NotFound),
             }
         }
-        _ => Ok(NotFound),
+        _ => Some(NotFound),
     }
 }
 
@@ -110,6 +195,7 @@ mod test {
     use textwrap_macros::dedent;
 
     use super::*;
+    use crate::parser::Span;
 
     struct Param {
         input: &'static str,
@@ -303,37 +389,139 @@ mod test {
         &Param {
             input: dedent!(
                 "
-                ; no real data in this input
-                ; some silly comment"
+                ;assert CORESIZE == 8000
+                MOV 0, 1 ; not an assertion
+                ;assert MAXLENGTH >= 2
+                "
             ),
-            expected: Default::default()
+            expected: CommentsRemoved {
+                lines: vec!["MOV 0, 1".to_string()],
+                metadata: Metadata {
+                    assertion: Some("MAXLENGTH >= 2".to_string()),
+                    ..Default::default()
+                },
+                assertions: vec![
+                    Assertion {
+                        line: 1,
+                        expression: "CORESIZE == 8000".to_string(),
+                    },
+                    Assertion {
+                        line: 3,
+                        expression: "MAXLENGTH >= 2".to_string(),
+                    },
+                ],
+                ..Default::default()
+            }
         };
-        "empty result"
+        "collect assertions"
     )]
-    fn parse(param: &Param) {
-        let result = extract_from_string(param.input);
-
-        assert_eq!(result, param.expected);
-    }
-
     #[test_case(
         &Param {
             input: dedent!(
                 "
-                ORG
-                MOV 0, 1
+                spl 1, <300 ;\\
+                spl 1, <600 ; joined to the comment above
+                spl 1, <500 ;-\\ not at the end
+                mov 0, \\
+                    1
                 "
             ),
             expected: CommentsRemoved {
-                lines: vec!["MOV 0, 1".to_string()],
+                lines: vec![
+                    "spl 1, <300".to_string(),
+                    "spl 1, <500".to_string(),
+                    "mov 0,     1".to_string(),
+                ],
                 ..Default::default()
             }
         };
-        "inconclusive(should error): parse ORG without arg"
+        "join continued lines"
+    )]
+    #[test_case(
+        &Param {
+            input: dedent!(
+                "
+                ; no real data in this input
+                ; some silly comment"
+            ),
+            expected: Default::default()
+        };
+        "empty result"
     )]
-    fn parse_error(param: &Param) {
-        let result = extract_from_string(param.input);
+    fn parse(param: &Param) {
+        let result = extract_from_string(
+            param.input,
+            0..param.input.lines().count(),
+            &BTreeSet::new(),
+            &mut SourceMap::new(param.input),
+            &mut Vec::new(),
+        );
+
+        assert_eq!(result.as_ref(), Ok(&param.expected));
+    }
+
+    #[allow(clippy::single_range_in_vec_init)]
+    #[test_case("mov 0, 1\ndat 0, 0", &[0..2]; "no redcode")]
+    #[test_case("junk\n;redcode\nmov 0, 1", &[1..3]; "ignore before redcode")]
+    #[test_case(
+        ";redcode-94\n;name A\nmov 0, 1\n  ;redcode-x\n;name B\n; redcode not here",
+        &[0..3, 3..6];
+        "multiple warriors"
+    )]
+    fn split(input: &str, expected: &[Range<usize>]) {
+        assert_eq!(split_warriors(input), expected);
+    }
 
-        assert_eq!(result, param.expected);
+    #[test]
+    fn warn_origin_redefinition() {
+        let input = "ORG 5\nORG 2\n";
+        let mut warnings = Vec::new();
+
+        let result = extract_from_string(
+            input,
+            0..2,
+            &BTreeSet::new(),
+            &mut SourceMap::new(input),
+            &mut warnings,
+        )
+        .expect("Redefining ORG should not be an error");
+
+        assert_eq!(result.origin, Some("5".to_string()));
+        assert_eq!(
+            warnings,
+            vec![Warning::OriginRedefinition {
+                old: "5".to_string(),
+                new: "2".to_string(),
+                span: Span {
+                    line: 2,
+                    column: 5,
+                    len: 1,
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_org_without_argument() {
+        let input = "MOV 0, 1\n  ORG ; no argument\n";
+
+        let result = extract_from_string(
+            input,
+            0..2,
+            &BTreeSet::new(),
+            &mut SourceMap::new(input),
+            &mut Vec::new(),
+        );
+
+        assert_eq!(
+            result,
+            Err(Error::MissingOrigin {
+                span: Span {
+                    line: 2,
+                    column: 3,
+                    len: 3,
+                },
+            })
+        );
     }
 }
@@ -1,14 +1,26 @@
 //! Helper functions for evaluating an expression syntax tree.
 //!
 //! Most functions here panic instead of returning Result because at this point
-//! any errors should have been caught earlier during initial parsing.
+//! any errors should have been caught earlier during initial parsing. The
+//! exceptions are division by zero and overflow, which depend on the values
+//! being evaluated.
 
+use crate::parser::error::Error;
 use crate::parser::grammar::{Pair, Rule};
+use crate::parser::span::Span;
+
+/// An error for text from the expression that overflowed
+fn overflow(text: &str, span: &impl Fn(&str) -> Span) -> Error {
+    Error::Overflow {
+        expression: text.trim().to_owned(),
+        span: span(text.trim()),
+    }
+}
 
 /// Evaluate an Expression. Panics if the expression tree is invalid, which
 /// should only happen due to programmer error (either the grammar or this code
-/// is incorrect).
-pub fn evaluate(pair: Pair) -> i32 {
+/// is incorrect). `span` locates text from the expression in the input.
+pub fn evaluate(pair: Pair, span: &impl Fn(&str) -> Span) -> Result<i32, Error> {
     let mut result = None;
     let mut boolean_op: fn(i32, i32) -> i32 =
         |_, _| unreachable!("BooleanOp called before first operand");
@@ -16,7 +28,7 @@ pub fn evaluate(pair: Pair) -> i32 {
     for inner_pair in pair.into_inner() {
         match inner_pair.as_rule() {
             Rule::Value => {
-                let operand = evaluate_value(inner_pair);
+                let operand = evaluate_value(inner_pair, span)?;
                 result = result.map(|x| boolean_op(x, operand)).or(Some(operand));
             }
             Rule::BooleanOp => {
@@ -30,10 +42,10 @@ pub fn evaluate(pair: Pair) -> i32 {
         }
     }
 
-    result.unwrap_or_else(|| panic!("Invalid Expression"))
+    Ok(result.unwrap_or_else(|| panic!("Invalid Expression")))
 }
 
-fn evaluate_value(pair: Pair) -> i32 {
+fn evaluate_value(pair: Pair, span: &impl Fn(&str) -> Span) -> Result<i32, Error> {
     let mut result = None;
     let mut compare_op: fn(i32, i32) -> i32 =
         |_, _| unreachable!("CompareOp called before first operand");
@@ -41,7 +53,7 @@ fn evaluate_value(pair: Pair) -> i32 {
     for inner_pair in pair.into_inner() {
         match inner_pair.as_rule() {
             Rule::Sum => {
-                let operand = evaluate_sum(inner_pair);
+                let operand = evaluate_sum(inner_pair, span)?;
                 result = result.map(|x| compare_op(x, operand)).or(Some(operand));
             }
             Rule::CompareOp => {
@@ -60,23 +72,29 @@ fn evaluate_value(pair: Pair) -> i32 {
         }
     }
 
-    result.unwrap_or_else(|| panic!("Invalid Value"))
+    Ok(result.unwrap_or_else(|| panic!("Invalid Value")))
 }
 
-fn evaluate_sum(pair: Pair) -> i32 {
+fn evaluate_sum(pair: Pair, span: &impl Fn(&str) -> Span) -> Result<i32, Error> {
+    let sum = pair.as_str();
     let mut result = None;
-    let mut add_op: fn(i32, i32) -> i32 = |_, _| unreachable!("AddOp called before first operand");
+    // Returns None on overflow
+    let mut add_op: fn(i32, i32) -> Option<i32> =
+        |_, _| unreachable!("AddOp called before first operand");
 
     for inner_pair in pair.into_inner() {
         match inner_pair.as_rule() {
             Rule::Product => {
-                let operand = evaluate_product(inner_pair);
-                result = result.map(|x| add_op(x, operand)).or(Some(operand));
+                let operand = evaluate_product(inner_pair, span)?;
+                result = match result {
+                    Some(x) => Some(add_op(x, operand).ok_or_else(|| overflow(sum, span))?),
+                    None => Some(operand),
+                };
             }
             Rule::AddOp => {
                 add_op = match inner_pair.as_str() {
-                    "+" => |a, b| a + b,
-                    "-" => |a, b| a - b,
+                    "+" => i32::checked_add,
+                    "-" => i32::checked_sub,
                     op => unreachable!("Invalid AddOp {:?}", op),
                 };
             }
@@ -84,25 +102,38 @@ fn evaluate_sum(pair: Pair) -> i32 {
         }
     }
 
-    result.unwrap_or_else(|| panic!("Invalid Sum"))
+    Ok(result.unwrap_or_else(|| panic!("Invalid Sum")))
 }
 
-fn evaluate_product(pair: Pair) -> i32 {
+fn evaluate_product(pair: Pair, span: &impl Fn(&str) -> Span) -> Result<i32, Error> {
+    let product = pair.as_str();
     let mut result = None;
-    let mut mul_op: fn(i32, i32) -> i32 =
+    // Returns None on overflow, e.g. `i32::MIN / -1`, or dividing by zero
+    let mut mul_op: fn(i32, i32) -> Option<i32> =
         |_, _| unreachable!("MultiplyOp called before first operand");
+    let mut divides = false;
 
     for inner_pair in pair.into_inner() {
         match inner_pair.as_rule() {
             Rule::UnaryExpr => {
-                let operand = evaluate_unary(inner_pair);
-                result = result.map(|x| mul_op(x, operand)).or(Some(operand));
+                let operand = evaluate_unary(inner_pair, span)?;
+                result = match result {
+                    Some(_) if divides && operand == 0 => {
+                        return Err(Error::DivideByZero {
+                            expression: product.trim().to_owned(),
+                            span: span(product.trim()),
+                        })
+                    }
+                    Some(x) => Some(mul_op(x, operand).ok_or_else(|| overflow(product, span))?),
+                    None => Some(operand),
+                };
             }
             Rule::MultiplyOp => {
+                divides = inner_pair.as_str() != "*";
                 mul_op = match inner_pair.as_str() {
-                    "*" => |a, b| a * b,
-                    "/" => |a, b| a / b,
-                    "%" => |a, b| a % b,
+                    "*" => i32::checked_mul,
+                    "/" => i32::checked_div,
+                    "%" => i32::checked_rem,
                     op => unreachable!("Invalid MultiplyOp {:?}", op),
                 };
             }
@@ -114,21 +145,23 @@ fn evaluate_product(pair: Pair) -> i32 {
         }
     }
 
-    result.unwrap_or_else(|| panic!("Invalid Product"))
+    Ok(result.unwrap_or_else(|| panic!("Invalid Product")))
 }
 
-fn evaluate_unary(pair: Pair) -> i32 {
+fn evaluate_unary(pair: Pair, span: &impl Fn(&str) -> Span) -> Result<i32, Error> {
+    let unary = pair.as_str();
     let mut result = None;
-    let mut unary_ops: Vec<fn(i32) -> i32> = Vec::new();
+    // Each returns None on overflow
+    let mut unary_ops: Vec<fn(i32) -> Option<i32>> = Vec::new();
 
     for inner_pair in pair.into_inner() {
         match inner_pair.as_rule() {
-            Rule::Number => result = Some(evaluate_number(&inner_pair)),
-            Rule::Expression => result = Some(evaluate(inner_pair)),
+            Rule::Number => result = Some(evaluate_number(&inner_pair, span)?),
+            Rule::Expression => result = Some(evaluate(inner_pair, span)?),
             Rule::UnaryOp => match inner_pair.as_str() {
-                "-" => unary_ops.push(|x| -x),
+                "-" => unary_ops.push(i32::checked_neg),
                 "+" => (), // Identity function
-                "!" => unary_ops.push(|x| i32::from(x == 0)),
+                "!" => unary_ops.push(|x| Some(i32::from(x == 0))),
                 other => unreachable!("Invalid unary operator {:?}", other),
             },
             other => unreachable!(
@@ -139,18 +172,19 @@ fn evaluate_unary(pair: Pair) -> i32 {
         }
     }
 
+    let mut result = result.unwrap_or_else(|| panic!("UnaryExpr did not contain a value"));
     for op in unary_ops {
-        result = result.map(op);
+        result = op(result).ok_or_else(|| overflow(unary, span))?;
     }
 
-    result.unwrap_or_else(|| panic!("UnaryExpr did not contain a value"))
+    Ok(result)
 }
 
-fn evaluate_number(pair: &Pair) -> i32 {
+fn evaluate_number(pair: &Pair, span: &impl Fn(&str) -> Span) -> Result<i32, Error> {
     assert!(pair.as_rule() == Rule::Number);
     pair.as_str()
         .parse::<i32>()
-        .unwrap_or_else(|_| panic!("Invalid Number: {:?}", pair.as_str()))
+        .map_err(|_| overflow(pair.as_str(), span))
 }
 
 #[cfg(test)]
@@ -191,6 +225,34 @@ mod test {
     fn evaluates_expressions(input: &str) -> i32 {
         let pair = parse_expression(input).expect("Failed to parse as Expression");
 
-        evaluate(pair)
+        evaluate(pair, &|_| Span::default()).expect("Failed to evaluate Expression")
+    }
+
+    #[test_case("1 / 0"; "divide")]
+    #[test_case("5 % 0"; "modulo")]
+    #[test_case("2 * (1 / (1 - 1))"; "nested")]
+    fn divide_by_zero(input: &str) {
+        let pair = parse_expression(input).expect("Failed to parse as Expression");
+
+        assert!(matches!(
+            evaluate(pair, &|_| Span::default()),
+            Err(Error::DivideByZero { .. })
+        ));
+    }
+
+    #[test_case("99999999999"; "number")]
+    #[test_case("2147483647 * 2"; "product")]
+    #[test_case("2147483647 + 1"; "sum")]
+    #[test_case("-2147483647 - 2"; "difference")]
+    #[test_case("(-2147483647 - 1) / -1"; "quotient")]
+    #[test_case("(-2147483647 - 1) % (-1)"; "remainder")]
+    #[test_case("-(-2147483647 - 1)"; "negation")]
+    fn overflow(input: &str) {
+        let pair = parse_expression(input).expect("Failed to parse as Expression");
+
+        assert!(matches!(
+            evaluate(pair, &|_| Span::default()),
+            Err(Error::Overflow { .. })
+        ));
     }
 }
@@ -7,20 +7,43 @@ use std::str::FromStr;
 
 use crate::load_file;
 
-use crate::parser::grammar;
 use crate::parser::error::Error;
+use crate::parser::grammar;
+use crate::parser::phase::Assertion;
+use crate::parser::span::{SourceMap, Span};
 
 /// Convert the text input lines into in-memory data structures
-pub fn evaluate(lines: Vec<String>) -> Result<load_file::Instructions, Error> {
+pub fn evaluate(
+    lines: Vec<String>,
+    source_map: &SourceMap,
+) -> Result<load_file::Instructions, Error> {
     let mut instructions = Vec::with_capacity(lines.len());
 
-    for line in lines {
-        if let Some(parse_result) = grammar::parse_line(&line)?.next() {
+    for (i, line) in lines.iter().enumerate() {
+        let span = |text: &str| source_map.span(i, text);
+
+        let mut pairs = grammar::parse_line(line).map_err(|err| Error::InvalidSyntax {
+            span: span(grammar::error_token(&err, line)),
+            source: err,
+        })?;
+
+        if let Some(parse_result) = pairs.next() {
             match &parse_result.as_rule() {
                 grammar::Rule::Instruction => {
-                    instructions.push(parse_instruction(parse_result.into_inner())?);
+                    instructions.push(parse_instruction(parse_result.into_inner(), &span)?);
+                }
+                grammar::Rule::Label => {
+                    return Err(Error::LabelNotFound {
+                        label: parse_result.as_str().to_owned(),
+                        span: span(parse_result.as_str()),
+                    });
+                }
+                _ => {
+                    return Err(Error::UnexpectedStatement {
+                        statement: line.trim().to_owned(),
+                        span: span(line.trim()),
+                    });
                 }
-                rule => eprintln!("Unexpected rule {:?}", rule),
             }
         }
     }
@@ -28,30 +51,80 @@ pub fn evaluate(lines: Vec<String>) -> Result<load_file::Instructions, Error> {
     Ok(instructions)
 }
 
-/// Parse and evaluate a single expression string to find the entry point to
-/// a warrior.
-pub fn evaluate_expression(expr: &str) -> Result<u32, Error> {
-    let expr_pair = grammar::parse_expression(expr)?;
+/// Parse and evaluate a single expression string, whose labels should already
+/// have been substituted. `span` locates text from the expression in the input.
+pub fn evaluate_expression(expr: &str, span: impl Fn(&str) -> Span) -> Result<i32, Error> {
+    let expr_pair = grammar::parse_expression(expr).map_err(|err| Error::InvalidSyntax {
+        span: span(grammar::error_token(&err, expr)),
+        source: err,
+    })?;
+
+    check_labels(&expr_pair, &span)?;
+
+    expression::evaluate(expr_pair, &span)
+}
+
+/// Parse and evaluate the expression that sets the entry point to a warrior.
+pub fn evaluate_origin(expr: &str, source_map: &SourceMap) -> Result<u32, Error> {
+    let span = |text: &str| source_map.origin_span(text);
+    let origin = evaluate_expression(expr, span)?;
+
+    u32::try_from(origin).map_err(|err| Error::InvalidOrigin {
+        source: err,
+        span: span(expr),
+    })
+}
+
+/// Evaluate an `;assert` expression, whose labels should already have been
+/// substituted. Fails if it evaluates to zero, or still contains a label.
+pub fn check_assertion(assertion: &Assertion, source_map: &SourceMap) -> Result<(), Error> {
+    if assertion.expression.trim().is_empty() {
+        return Ok(());
+    }
+
+    let span = |text: &str| source_map.input_span(assertion.line, text);
 
-    let origin = expression::evaluate(expr_pair);
+    if evaluate_expression(&assertion.expression, span)? == 0 {
+        Err(Error::AssertionFailed {
+            assertion: assertion.expression.clone(),
+            span: span(&assertion.expression),
+        })
+    } else {
+        Ok(())
+    }
+}
 
-    Ok(u32::try_from(origin)?)
+/// Fail if an expression still contains a label, which must not have been
+/// defined, since all labels are substituted before evaluation.
+fn check_labels(expr_pair: &grammar::Pair, span: impl Fn(&str) -> Span) -> Result<(), Error> {
+    match expr_pair
+        .clone()
+        .into_inner()
+        .flatten()
+        .find(|pair| pair.as_rule() == grammar::Rule::Label)
+    {
+        Some(label) => Err(Error::LabelNotFound {
+            label: label.as_str().to_owned(),
+            span: span(label.as_str()),
+        }),
+        None => Ok(()),
+    }
 }
 
 #[allow(clippy::option_if_let_else)] // TODO
 fn parse_instruction(
     mut instruction_pairs: grammar::Pairs,
+    span: &impl Fn(&str) -> Span,
 ) -> Result<load_file::Instruction, Error> {
     let mut operation_pairs = instruction_pairs
         .next()
         .expect("Operation must be first pair after Label in Instruction")
         .into_inner();
 
-    let opcode = parse_opcode(
-        &operation_pairs
-            .next()
-            .expect("Opcode must be first pair in Operation"),
-    );
+    let opcode_pair = operation_pairs
+        .next()
+        .expect("Opcode must be first pair in Operation");
+    let opcode = parse_opcode(&opcode_pair);
 
     let maybe_modifier = operation_pairs
         .peek()
@@ -62,12 +135,14 @@ fn parse_instruction(
         instruction_pairs
             .next()
             .expect("Field must appear after Opcode"),
-    );
+        span,
+    )?;
 
     let b_field = instruction_pairs
         .next()
         .filter(|pair| pair.as_rule() == grammar::Rule::Field)
-        .map(parse_field);
+        .map(|pair| parse_field(pair, span))
+        .transpose()?;
 
     if let Some(b_field) = b_field {
         let modifier = maybe_modifier.unwrap_or_else(|| {
@@ -104,7 +179,10 @@ fn parse_instruction(
                 a_field,
                 b_field: load_file::Field::direct(0),
             }),
-            other => Err(Error::InvalidArguments { opcode: other }),
+            other => Err(Error::InvalidArguments {
+                opcode: other,
+                span: span(opcode_pair.as_str()),
+            }),
         }
     }
 }
@@ -117,7 +195,10 @@ fn parse_opcode(opcode_pair: &grammar::Pair) -> load_file::Opcode {
     load_file::Opcode::from_str(opcode_pair.as_str().to_uppercase().as_ref()).unwrap()
 }
 
-fn parse_field(field_pair: grammar::Pair) -> load_file::Field {
+fn parse_field(
+    field_pair: grammar::Pair,
+    span: &impl Fn(&str) -> Span,
+) -> Result<load_file::Field, Error> {
     let mut field_pairs = field_pair.into_inner();
 
     let address_mode = field_pairs
@@ -127,16 +208,16 @@ fn parse_field(field_pair: grammar::Pair) -> load_file::Field {
             load_file::AddressMode::from_str(pair.as_str()).expect("Invalid AddressMode")
         });
 
-    let offset = expression::evaluate(
-        field_pairs
-            .find(|pair| pair.as_rule() == grammar::Rule::Expression)
-            .unwrap_or_else(|| panic!("No expression found in Field: {:?}", field_pairs)),
-    );
+    let expr_pair = field_pairs
+        .find(|pair| pair.as_rule() == grammar::Rule::Expression)
+        .unwrap_or_else(|| panic!("No expression found in Field: {:?}", field_pairs));
+
+    check_labels(&expr_pair, span)?;
 
-    load_file::Field {
+    Ok(load_file::Field {
         address_mode,
-        value: load_file::Value::Literal(offset),
-    }
+        value: load_file::Value::Literal(expression::evaluate(expr_pair, span)?),
+    })
 }
 
 #[cfg(test)]
@@ -147,7 +228,7 @@ mod test {
 
     #[test]
     fn parse_simple_file() {
-        let simple_input = [
+        let simple_input: Vec<String> = [
             "mov 1, 3",
             "mov 100, #12",
             "dat #0, #0",
@@ -168,20 +249,88 @@ mod test {
             Instruction::new(Opcode::Jmp, Field::direct(-1), Field::direct(0)),
         ];
 
-        let parsed = evaluate(simple_input)
+        let source_map = SourceMap::identity(&simple_input);
+        let parsed = evaluate(simple_input, &source_map)
             .unwrap_or_else(|err| panic!("Failed to parse simple file: {}", err));
 
         assert_eq!(parsed, expected_core);
     }
 
+    #[test]
+    fn reports_unknown_labels() {
+        let lines: Vec<String> = vec!["mov 0, 1".into(), "jmp  loop".into()];
+        let source_map = SourceMap::identity(&lines);
+
+        assert_eq!(
+            evaluate(lines, &source_map),
+            Err(Error::LabelNotFound {
+                label: "loop".to_owned(),
+                span: Span {
+                    line: 2,
+                    column: 6,
+                    len: 4,
+                },
+            })
+        );
+    }
+
     #[test]
     fn evaluates_origin() {
-        let evaluated = evaluate_expression("2 * (4 + 3)").expect("Should parse successfully");
+        let mut source_map = SourceMap::new("ORG 2 * (4 + 3)\nORG -10");
+        source_map.set_origin(0);
+        let evaluated =
+            evaluate_origin("2 * (4 + 3)", &source_map).expect("Should parse successfully");
         assert_eq!(evaluated, 14);
     }
 
     #[test]
     fn fails_for_negative_origin() {
-        evaluate_expression("-10").expect_err("-10 should be an invalid origin");
+        let mut source_map = SourceMap::new("ORG 2 * (4 + 3)\nORG -10");
+        source_map.set_origin(1);
+        let err = evaluate_origin("-10", &source_map).expect_err("-10 should be an invalid origin");
+        assert_eq!(
+            err.span(),
+            Span {
+                line: 2,
+                column: 5,
+                len: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn checks_assertions() {
+        let source_map = SourceMap::new(";assert 800 == 8000 && 1\n;assert FOO > 1");
+        let assertion = |line: usize, expression: &str| Assertion {
+            line,
+            expression: expression.to_owned(),
+        };
+        let check = |line, expression| check_assertion(&assertion(line, expression), &source_map);
+
+        check(0, "8000 == 8000").expect("Assertion should pass");
+        check(0, "").expect("Empty assertion should pass");
+
+        assert_eq!(
+            check(0, "800 == 8000 && 1"),
+            Err(Error::AssertionFailed {
+                assertion: "800 == 8000 && 1".to_owned(),
+                span: Span {
+                    line: 1,
+                    column: 9,
+                    len: 16,
+                },
+            })
+        );
+        assert_eq!(
+            check(1, "FOO > 1"),
+            Err(Error::LabelNotFound {
+                label: "FOO".to_owned(),
+                span: Span {
+                    line: 2,
+                    column: 9,
+                    len: 3,
+                },
+            })
+        );
     }
 }
@@ -1,6 +1,6 @@
 //! This phase finds and expands substitutions, namely:
 //! - EQU definitions
-//! - FOR blocks (not yet implemented)
+//! - FOR blocks, including labels concatenated with the counter, e.g. `lbl&i`
 //! - Standard labels which alias an address
 //!
 //! Labels used in the right-hand side of an expression substituted in-place.
@@ -11,37 +11,61 @@ use std::string::ToString;
 
 use pest::Span;
 
-use crate::load_file::DEFAULT_CONSTANTS;
+use crate::parser::error::{Error, Warning};
 use crate::parser::grammar;
-use crate::parser::phase::evaluation;
+use crate::parser::phase::{evaluation, Assertion};
+use crate::parser::span::SourceMap;
+use crate::parser::ParseConfig;
 
 /// The result of expansion and substitution
 #[derive(Debug, Default, PartialEq, Eq)]
 pub struct Lines {
     pub text: Vec<String>,
     pub origin: Option<String>,
+    pub assertions: Vec<Assertion>,
 }
 
-/// Collect and subsitute all labels found in the input lines.
-pub fn expand(mut text: Vec<String>, mut origin: Option<String>) -> Lines {
-    let labels = collect_and_expand(&mut text);
+/// Collect and subsitute all labels found in the input lines. Lines added or
+/// removed by expansion are followed in `source_map`.
+pub fn expand(
+    mut text: Vec<String>,
+    mut origin: Option<String>,
+    mut assertions: Vec<Assertion>,
+    config: &ParseConfig,
+    source_map: &mut SourceMap,
+    warnings: &mut Vec<Warning>,
+) -> Result<Lines, Error> {
+    let labels = collect_and_expand(&mut text, config, source_map, warnings)?;
 
     substitute_offsets(&mut text, &labels);
 
     if let Some(origin_str) = origin.as_mut() {
-        substitute_offsets_in_line(origin_str, &labels, 0);
+        substitute_in_expression(origin_str, &labels);
     }
 
-    Lines { text, origin }
+    for assertion in &mut assertions {
+        substitute_in_expression(&mut assertion.expression, &labels);
+    }
+
+    Ok(Lines {
+        text,
+        origin,
+        assertions,
+    })
 }
 
 /// Collect and strip out offset-based label declarations, meanwhile expanding
 /// `EQU` labels.
 #[allow(clippy::too_many_lines)] // TODO(#74): fix this
-fn collect_and_expand(lines: &mut Vec<String>) -> Labels {
+fn collect_and_expand(
+    lines: &mut Vec<String>,
+    config: &ParseConfig,
+    source_map: &mut SourceMap,
+    warnings: &mut Vec<Warning>,
+) -> Result<Labels, Error> {
     use grammar::Rule;
 
-    let mut collector = Collector::new();
+    let mut collector = Collector::new(config);
 
     let mut i: usize = 0;
     let mut offset: u32 = 0;
@@ -51,13 +75,15 @@ fn collect_and_expand(lines: &mut Vec<String>) -> Labels {
         let tokenized_line = grammar::tokenize(&line);
 
         if tokenized_line.is_empty() {
+            i += 1;
             continue;
         }
 
         let first_token = &tokenized_line[0];
+        let first_token_span = source_map.span(i, first_token.as_str());
 
         // Returns true if anything was expanded, false otherwise
-        let mut expand_next_token = |collector: &Collector, is_for_expr: bool| {
+        let mut expand_next_token = |collector: &Collector, is_for_expr: bool| -> Result<_, _> {
             for token in tokenized_line[1..].iter() {
                 if token.as_rule() == Rule::Label {
                     let label_value = collector.get_label_value(token.as_str(), offset);
@@ -76,125 +102,111 @@ fn collect_and_expand(lines: &mut Vec<String>) -> Labels {
                                 let relative_offset = abs_offset - offset;
                                 expand_lines(
                                     lines,
+                                    source_map,
                                     i,
                                     &token.as_span(),
                                     &[relative_offset.to_string()],
                                 );
                             }
                             LabelValue::RelativeOffset(rel_offset) => {
-                                expand_lines(lines, i, &token.as_span(), &[rel_offset.to_string()]);
+                                expand_lines(
+                                    lines,
+                                    source_map,
+                                    i,
+                                    &token.as_span(),
+                                    &[rel_offset.to_string()],
+                                );
                             }
                             LabelValue::Substitution(subst) => {
-                                expand_lines(lines, i, &token.as_span(), &subst);
+                                expand_lines(lines, source_map, i, &token.as_span(), &subst);
                             }
                         }
 
-                        return true;
+                        return Ok(true);
                     }
 
                     if is_for_expr {
-                        panic!("No label value found for expr {:?}", token.as_str());
-                    } else {
-                        // this is probably a forward usage of a label not
-                        // yet declared, which _could_ be an error
+                        return Err(Error::LabelNotFound {
+                            label: token.as_str().to_owned(),
+                            span: source_map.span(i, token.as_str()),
+                        });
                     }
+                    // Otherwise this is probably a forward usage of a label
+                    // not yet declared, which will be substituted later
                 }
             }
 
-            false
+            Ok(false)
         };
 
         match first_token.as_rule() {
             Rule::For => {
                 collector.resolve_pending_labels(offset);
 
-                if expand_next_token(&collector, true) {
+                if expand_next_token(&collector, true)? {
                     continue;
                 }
 
                 let line_remainder = &line[first_token.as_span().end()..];
-                collector.push_for(None, i, offset, line_remainder);
-                // Continue processing lines as normal, since we still need to collect
-                // labels and potentially nested for loops
+                let count = for_count(line_remainder, config, |text| source_map.span(i, text))?;
+                let body_len = unroll_for(
+                    lines,
+                    source_map,
+                    i,
+                    None,
+                    count,
+                    first_token.as_str(),
+                    &collector.labels,
+                )?;
+
+                if count == 0 {
+                    warn_dropped_for(warnings, body_len, first_token_span);
+                }
+                continue;
             }
             Rule::Rof => {
-                let for_stmt = collector.pop_for();
-
-                let range_to_repeat = (for_stmt.start_line + 1)..i;
-                let i_range_len = i32::try_from(range_to_repeat.len())
-                    .expect("FOR expansion resulted in > i32::MAX lines");
-                let range_len = u32::try_from(range_to_repeat.len())
-                    .expect("FOR expansion resulted in > u32::MAX lines");
-
-                let insert_line_count = for_stmt.iter_count as usize * range_to_repeat.len();
-
-                // We need to subtract the offset, since we end up replacing
-                // those lines. They will be processed normally after substitution
-                offset -= range_len as u32;
-
-                let new_contents = lines[range_to_repeat.clone()]
-                    .iter()
-                    .cloned()
-                    .cycle()
-                    .take(insert_line_count)
-                    .enumerate()
-                    .map(|(i, mut line)| {
-                        if let Some(label) = &for_stmt.index_label {
-                            // UNWRAP: we already checked i_range_len above
-                            let i = i32::try_from(i).unwrap();
-
-                            let subst = i / i_range_len + 1;
-
-                            // This method of label stuff is a little hacky,
-                            // but seems reasonable I guess...
-                            collector
-                                .labels
-                                .insert(label.clone(), LabelValue::RelativeOffset(subst));
-
-                            // UNWRAP: we already checked range_len above
-                            let i = u32::try_from(i).unwrap();
-
-                            substitute_offsets_in_line(
-                                &mut line,
-                                &collector.labels,
-                                for_stmt.start_offset + i,
-                            );
-
-                            collector.labels.remove(label);
-                        }
-                        line
-                    })
-                    .collect::<Vec<_>>();
-
-                let range_to_replace = for_stmt.start_line..=i;
-                lines.splice(range_to_replace, new_contents);
-
-                i = for_stmt.start_line;
-
-                continue;
+                return Err(Error::UnmatchedRof {
+                    span: first_token_span,
+                });
             }
             Rule::Label => {
                 if let Some(next_token) = tokenized_line.get(1) {
                     match next_token.as_rule() {
                         Rule::Substitution => {
+                            if next_token.as_str().trim().is_empty() {
+                                warnings.push(Warning::EmptySubstitution {
+                                    label: first_token.as_str().to_owned(),
+                                    span: first_token_span,
+                                });
+                            }
                             collector.process_equ(first_token.as_str(), next_token.as_str());
                             lines.remove(i);
+                            source_map.remove(i);
                             continue;
                         }
                         Rule::For => {
                             collector.resolve_pending_labels(offset);
 
-                            if !expand_next_token(&collector, true) {
-                                let line_remainder = &line[next_token.as_span().end()..];
+                            if expand_next_token(&collector, true)? {
+                                continue;
+                            }
 
-                                collector.push_for(
-                                    first_token.as_str().to_string(),
-                                    i,
-                                    offset,
-                                    line_remainder,
-                                );
+                            let line_remainder = &line[next_token.as_span().end()..];
+                            let count =
+                                for_count(line_remainder, config, |text| source_map.span(i, text))?;
+                            let for_span = source_map.span(i, next_token.as_str());
+                            let body_len = unroll_for(
+                                lines,
+                                source_map,
+                                i,
+                                Some(first_token.as_str()),
+                                count,
+                                next_token.as_str(),
+                                &collector.labels,
+                            )?;
 
-                                i += 1;
+                            if count == 0 {
+                                warn_dropped_for(warnings, body_len, for_span);
                             }
                             continue;
                         }
@@ -207,13 +219,13 @@ fn collect_and_expand(lines: &mut Vec<String>) -> Labels {
                 if let Some(LabelValue::Substitution(substitution)) =
                     collector.get_label_value(first_token.as_str(), offset)
                 {
-                    expand_lines(lines, i, &first_token.as_span(), &substitution);
+                    expand_lines(lines, source_map, i, &first_token.as_span(), &substitution);
                     continue;
                 }
 
-                collector.add_pending_label(first_token.as_str());
+                collector.add_pending_label(first_token.as_str(), first_token_span);
 
-                if expand_next_token(&collector, false) {
+                if expand_next_token(&collector, false)? {
                     continue;
                 }
 
@@ -225,18 +237,21 @@ fn collect_and_expand(lines: &mut Vec<String>) -> Labels {
                     lines[i] = line[next_token.start()..].to_owned();
                 } else {
                     lines.remove(i);
+                    source_map.remove(i);
                     continue;
                 }
             }
             Rule::Substitution => {
-                collector.process_equ_continuation(first_token.as_str());
+                collector
+                    .process_equ_continuation(first_token.as_str(), || source_map.span(i, &line))?;
                 lines.remove(i);
+                source_map.remove(i);
                 continue;
             }
             other_rule => {
                 collector.resolve_pending_labels(offset);
 
-                if expand_next_token(&collector, false) {
+                if expand_next_token(&collector, false)? {
                     continue;
                 }
 
@@ -253,10 +268,230 @@ fn collect_and_expand(lines: &mut Vec<String>) -> Labels {
         i += 1;
     }
 
-    collector.finish()
+    Ok(collector.finish(warnings))
+}
+
+/// Evaluate the number of times to repeat a `FOR` block, which must be at
+/// most `MAXLENGTH` to stop a typo from unrolling into billions of lines
+fn for_count(
+    expression: &str,
+    config: &ParseConfig,
+    span: impl Fn(&str) -> crate::parser::Span,
+) -> Result<u32, Error> {
+    let count = evaluation::evaluate_expression(expression.trim(), &span)?;
+    let max = config.max_warrior_length;
+
+    if (0..=max).contains(&count) {
+        Ok(count as u32)
+    } else {
+        Err(Error::InvalidForCount {
+            count,
+            max,
+            span: span(expression.trim()),
+        })
+    }
+}
+
+/// Warn that the body of a `FOR` block with a count of 0 was dropped. A block
+/// nested in another is dropped from each copy, but only warned about once.
+fn warn_dropped_for(warnings: &mut Vec<Warning>, lines: usize, span: crate::parser::Span) {
+    let warning = Warning::DroppedFor { lines, span };
+
+    if lines > 0 && !warnings.contains(&warning) {
+        warnings.push(warning);
+    }
+}
+
+/// Replace the `FOR` block starting at line `start` with `count` copies of
+/// its body, and return the number of lines in the body. In each copy, the
+/// `counter` label is substituted with the number of the copy, starting from
+/// 1, including where it is used by an `EQU` in `labels`. Nested blocks are
+/// copied as they are, so they can use the counter, and are unrolled when the
+/// copies are expanded.
+fn unroll_for(
+    lines: &mut Vec<String>,
+    source_map: &mut SourceMap,
+    start: usize,
+    counter: Option<&str>,
+    count: u32,
+    for_token: &str,
+    labels: &Labels,
+) -> Result<usize, Error> {
+    let end = find_rof(lines, start).ok_or_else(|| Error::UnclosedFor {
+        span: source_map.span(start, for_token),
+    })?;
+    let body = start + 1..end;
+
+    let body_lines: Vec<String> = match counter {
+        Some(counter) => {
+            let substitutions = counter_substitutions(labels, counter);
+            lines[body.clone()]
+                .iter()
+                .map(|line| expand_counter_substitutions(line, &substitutions))
+                .collect()
+        }
+        None => lines[body.clone()].to_vec(),
+    };
+
+    let new_lines: Vec<String> = (1..=count)
+        .flat_map(|n| {
+            body_lines.iter().map(move |line| match counter {
+                Some(counter) => substitute_counter(line, counter, n),
+                None => line.clone(),
+            })
+        })
+        .collect();
+
+    source_map.repeat(start..end + 1, body.clone(), count as usize);
+    lines.splice(start..=end, new_lines);
+
+    Ok(body.len())
+}
+
+/// Find the single line `EQU`s which use `counter`, directly or through
+/// another `EQU`, like `Lookat equ look+8*(i-1)`. These are expanded in the
+/// body of a `FOR` block, so that the counter can be substituted.
+fn counter_substitutions<'a>(labels: &'a Labels, counter: &str) -> HashMap<&'a str, &'a str> {
+    let single_line: Vec<(&str, &str)> = labels
+        .iter()
+        .filter_map(|(label, value)| match value {
+            LabelValue::Substitution(lines) if lines.len() == 1 => {
+                Some((label.as_str(), lines[0].as_str()))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let mut substitutions = HashMap::new();
+
+    loop {
+        let found = substitutions.len();
+
+        for &(label, text) in &single_line {
+            let uses_counter = text
+                .split(|c: char| !is_word(c))
+                .any(|word| word == counter || substitutions.contains_key(word));
+
+            if uses_counter {
+                substitutions.insert(label, text);
+            }
+        }
+
+        if substitutions.len() == found {
+            return substitutions;
+        }
+    }
+}
+
+/// Expand the `substitutions` used in a line, including those used by the
+/// substitutions themselves
+fn expand_counter_substitutions(line: &str, substitutions: &HashMap<&str, &str>) -> String {
+    let mut line = line.to_owned();
+
+    // Each pass expands one level of substitutions, so this also stops any
+    // which are defined in terms of each other
+    for _ in 0..=substitutions.len() {
+        let expanded = replace_words(&line, |result, word| {
+            result.push_str(substitutions.get(word).unwrap_or(&word));
+        });
+
+        if expanded == line {
+            break;
+        }
+        line = expanded;
+    }
+
+    line
+}
+
+/// Find the `ROF` which closes the `FOR` on line `start`
+fn find_rof(lines: &[String], start: usize) -> Option<usize> {
+    let mut depth = 0;
+
+    for (i, line) in lines.iter().enumerate().skip(start + 1) {
+        let tokenized_line = grammar::tokenize(line);
+        let mut rules = tokenized_line.iter().map(grammar::Pair::as_rule);
+
+        match (rules.next(), rules.next()) {
+            (Some(grammar::Rule::For), _) | (_, Some(grammar::Rule::For)) => depth += 1,
+            (Some(grammar::Rule::Rof), _) if depth == 0 => return Some(i),
+            (Some(grammar::Rule::Rof), _) => depth -= 1,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Substitute the `counter` of a `FOR` block in a line of its body, including
+/// the right-hand side of an `EQU`. A label concatenated with the counter,
+/// like `label&counter`, has the counter replaced with its value padded to two
+/// digits, e.g. `label01`. In a chain like `label&j&i`, each counter is its
+/// own segment, and is only joined to the label once the segments before it
+/// have been substituted, so the counters can be substituted in any order.
+fn substitute_counter(line: &str, counter: &str, value: u32) -> String {
+    replace_words(line, |result, word| {
+        let in_chain = is_concatenation(result);
+        let word = if word != counter {
+            word.to_owned()
+        } else if in_chain {
+            format!("{:02}", value)
+        } else {
+            value.to_string()
+        };
+
+        let previous = result[..result.len().saturating_sub(1)].trim_end_matches(is_word);
+        if in_chain && word.chars().all(|c| c.is_ascii_digit()) && !is_concatenation(previous) {
+            result.pop();
+        }
+        result.push_str(&word);
+    })
+}
+
+/// Whether `line` ends with a single `&` after a label, so that the next word
+/// is concatenated with it. `&&` is a logical and instead.
+fn is_concatenation(line: &str) -> bool {
+    line.strip_suffix('&')
+        .is_some_and(|before| before.ends_with(is_word))
+}
+
+/// Rebuild a line, calling `replace` to add each word, i.e. a label or number,
+/// to the line so far. Words after a `.` are modifiers and kept as they are,
+/// so that e.g. a counter `i` doesn't replace `MOV.I`.
+fn replace_words(line: &str, mut replace: impl FnMut(&mut String, &str)) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut rest = line;
+
+    while let Some(start) = rest.find(is_word) {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = rest.find(|c: char| !is_word(c)).unwrap_or(rest.len());
+        let (word, after) = rest.split_at(end);
+        rest = after;
+
+        if result.ends_with('.') {
+            result.push_str(word);
+        } else {
+            replace(&mut result, word);
+        }
+    }
+
+    result.push_str(rest);
+    result
 }
 
-fn expand_lines(lines: &mut Vec<String>, index: usize, span: &Span, substitution: &[String]) {
+fn is_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn expand_lines(
+    lines: &mut Vec<String>,
+    source_map: &mut SourceMap,
+    index: usize,
+    span: &Span,
+    substitution: &[String],
+) {
     let line = &lines[index];
 
     let before = &line[..span.start()];
@@ -268,6 +503,7 @@ fn expand_lines(lines: &mut Vec<String>, index: usize, span: &Span, substitution
     new_lines[0] = before.to_owned() + &new_lines[0];
     new_lines.last_mut().unwrap().push_str(after);
 
+    source_map.expand(index, new_lines.len());
     lines.splice(index..=index, new_lines);
 }
 
@@ -332,6 +568,37 @@ fn substitute_offsets_in_line(line: &mut String, labels: &Labels, from_offset: u
     }
 }
 
+/// Substitute every label in an expression outside the program, like an
+/// `;assert` or the origin, as if it were used at the start of the program
+fn substitute_in_expression(expression: &mut String, labels: &Labels) {
+    *expression = substitute_labels(expression, labels, &mut Vec::new());
+}
+
+/// Substitute labels in `expression`, including any labels in the value of
+/// an `EQU`. Labels in `expanding` are being substituted already, so they are
+/// left as they are rather than recursing forever.
+fn substitute_labels<'a>(
+    expression: &str,
+    labels: &'a Labels,
+    expanding: &mut Vec<&'a str>,
+) -> String {
+    replace_words(expression, |result, word| {
+        match labels.get_key_value(word) {
+            Some((_, &LabelValue::AbsoluteOffset(offset))) => result.push_str(&offset.to_string()),
+            Some((_, &LabelValue::RelativeOffset(offset))) => result.push_str(&offset.to_string()),
+            Some((label, LabelValue::Substitution(lines)))
+                if lines.len() == 1 && !expanding.contains(&label.as_str()) =>
+            {
+                expanding.push(label);
+                result.push_str(&substitute_labels(&lines[0], labels, expanding));
+                expanding.pop();
+            }
+            None if word == "CURLINE" => result.push('0'),
+            _ => result.push_str(word),
+        }
+    })
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 enum LabelValue {
     AbsoluteOffset(u32),
@@ -341,51 +608,48 @@ enum LabelValue {
 
 type Labels = HashMap<String, LabelValue>;
 
-fn default_labels() -> Labels {
-    DEFAULT_CONSTANTS
-        .iter()
+fn default_labels(config: &ParseConfig) -> Labels {
+    config
+        .constants()
+        .into_iter()
+        .chain(
+            config
+                .labels
+                .iter()
+                .map(|(lbl, &value)| (lbl.as_str(), value)),
+        )
         // Counterintuitively, we use a relative offset here so that it doesn't
         // get translated like absolute offset labels would be
-        .map(|(lbl, value)| {
-            let value = i32::try_from(*value).expect("Default constant values contain invalid i32");
-            (lbl.clone(), LabelValue::RelativeOffset(value))
-        })
+        .map(|(lbl, value)| (lbl.to_owned(), LabelValue::RelativeOffset(value)))
         .collect()
 }
 
-#[derive(Debug)]
-struct ForStatement {
-    index_label: Option<String>,
-    iter_count: u32,
-    start_line: usize,
-    start_offset: u32,
-}
-
 #[derive(Debug)]
 struct Collector {
     labels: Labels,
     current_equ: Option<(String, Vec<String>)>,
-    pending_labels: HashSet<String>,
-    for_stack: Vec<ForStatement>,
-    for_offsets: HashMap<String, u32>,
+    pending_labels: HashMap<String, crate::parser::Span>,
+    /// Labels defined by the config, which the warrior can't redefine
+    fixed_labels: HashSet<String>,
 }
 
 impl Collector {
-    fn new() -> Self {
+    fn new(config: &ParseConfig) -> Self {
         Self {
-            labels: default_labels(),
+            labels: default_labels(config),
             current_equ: None,
-            pending_labels: HashSet::new(),
-            for_stack: Vec::new(),
-            for_offsets: HashMap::new(),
+            pending_labels: HashMap::new(),
+            fixed_labels: config.labels.keys().cloned().collect(),
         }
     }
 
-    fn process_equ(&mut self, label: &str, substitution: &str) {
-        if substitution.is_empty() {
-            // TODO #25 warning empty RHS of EQU (see docs/pmars-redcode-94.txt:170)
+    fn define(&mut self, label: String, value: LabelValue) {
+        if !self.fixed_labels.contains(&label) {
+            self.labels.insert(label, value);
         }
+    }
 
+    fn process_equ(&mut self, label: &str, substitution: &str) {
         if self.current_equ.is_some() {
             self.resolve_pending_equ();
         }
@@ -393,30 +657,36 @@ impl Collector {
         self.current_equ = Some((label.to_owned(), vec![substitution.to_owned()]));
     }
 
-    fn process_equ_continuation(&mut self, substitution: &str) {
+    fn process_equ_continuation(
+        &mut self,
+        substitution: &str,
+        span: impl FnOnce() -> crate::parser::Span,
+    ) -> Result<(), Error> {
         if let Some((_, ref mut values)) = self.current_equ {
             values.push(substitution.to_string());
+            Ok(())
         } else {
-            // TODO #25 real error
-            eprintln!("Error: first occurrence of EQU without label");
+            Err(Error::EquWithoutLabel { span: span() })
         }
     }
 
-    fn add_pending_label(&mut self, label: &str) {
-        self.pending_labels.insert(label.to_owned());
+    fn add_pending_label(&mut self, label: &str, span: crate::parser::Span) {
+        self.pending_labels.insert(label.to_owned(), span);
     }
 
     fn resolve_pending_labels(&mut self, offset: u32) {
         let mut result = HashMap::new();
 
         let pending_labels = std::mem::take(&mut self.pending_labels);
-        for pending_label in pending_labels {
+        for pending_label in pending_labels.into_keys() {
             result.insert(pending_label, LabelValue::AbsoluteOffset(offset));
         }
 
         self.resolve_pending_equ();
 
-        self.labels.extend(result.into_iter());
+        for (label, value) in result {
+            self.define(label, value);
+        }
     }
 
     fn resolve_pending_equ(&mut self) {
@@ -424,72 +694,36 @@ impl Collector {
 
         if let Some((multiline_equ_label, values)) = current_equ {
             // Reached the last line in an equ, add to table and reset
-            self.labels
-                .insert(multiline_equ_label, LabelValue::Substitution(values));
+            self.define(multiline_equ_label, LabelValue::Substitution(values));
         }
     }
 
-    fn push_for<T: Into<Option<String>>>(
-        &mut self,
-        label: T,
-        line: usize,
-        offset: u32,
-        expression: &str,
-    ) {
-        // TODO handle errors instead of unwrap
-        let expr_value = evaluation::evaluate_expression(expression).unwrap();
-
-        self.for_stack.push(ForStatement {
-            index_label: label.into(),
-            iter_count: expr_value,
-            start_line: line,
-            start_offset: offset,
-        });
-    }
-
-    fn pop_for(&mut self) -> ForStatement {
-        let for_stmt = self.for_stack.pop().unwrap();
-        if let Some(label) = &for_stmt.index_label {
-            self.for_offsets
-                .insert(label.clone(), for_stmt.start_offset);
-        }
-        for_stmt
-    }
-
     fn get_label_value(&self, label: &str, current_offset: u32) -> Option<LabelValue> {
         let value = self.labels.get(label).cloned();
 
-        if let Some(LabelValue::Substitution(_)) = value {
-            // Always return substitutions even if we are in a for loop
+        if value.is_some() {
             value
-        } else if self.for_stack.is_empty() {
-            // Otherwise, only expand labels if we are finished unrolling loops
-            // This ensures the relative offsets are calculated after the unroll
-            if value.is_some() {
-                value
-            } else if label == "CURLINE" {
-                // Special-case for current line number
-                let current_offset = i32::try_from(current_offset).expect("CURLINE > i32::MAX");
-                Some(LabelValue::RelativeOffset(current_offset))
-            } else {
-                None
-            }
+        } else if label == "CURLINE" {
+            // Special-case for current line number
+            let current_offset = i32::try_from(current_offset).expect("CURLINE > i32::MAX");
+            Some(LabelValue::RelativeOffset(current_offset))
         } else {
             None
         }
     }
 
-    fn finish(mut self) -> Labels {
-        if !self.pending_labels.is_empty() {
-            // TODO #25 warning for empty definition for each pending label
-        }
+    fn finish(mut self, warnings: &mut Vec<Warning>) -> Labels {
+        let mut pending_labels: Vec<_> = self.pending_labels.drain().collect();
+        pending_labels.sort_by_key(|(_, span)| (span.line, span.column));
 
-        self.labels.extend(
-            self.current_equ
-                .take()
-                .map(|(label, values)| (label, LabelValue::Substitution(values))),
+        warnings.extend(
+            pending_labels
+                .into_iter()
+                .map(|(label, span)| Warning::EmptyOffset { label, span }),
         );
 
+        self.resolve_pending_equ();
+
         self.labels
     }
 }
@@ -504,10 +738,10 @@ mod test {
 
     #[test]
     fn collects_equ() {
-        let mut collector = Collector::new();
+        let mut collector = Collector::new(&ParseConfig::default());
 
         collector.process_equ("foo", "1");
-        let labels = collector.finish();
+        let labels = collector.finish(&mut Vec::new());
 
         assert_eq!(
             Some(&Substitution(vec![String::from("1")])),
@@ -517,11 +751,13 @@ mod test {
 
     #[test]
     fn collects_multi_line_equ() {
-        let mut collector = Collector::new();
+        let mut collector = Collector::new(&ParseConfig::default());
 
         collector.process_equ("foo", "mov 1, 1");
-        collector.process_equ_continuation("jne 0, -1");
-        let labels = collector.finish();
+        collector
+            .process_equ_continuation("jne 0, -1", Default::default)
+            .expect("EQU continuation should follow an EQU");
+        let labels = collector.finish(&mut Vec::new());
 
         assert_eq!(
             Some(&Substitution(vec![
@@ -534,19 +770,50 @@ mod test {
 
     #[test]
     fn collects_label_offset() {
-        let mut collector = Collector::new();
+        let mut collector = Collector::new(&ParseConfig::default());
 
-        collector.add_pending_label("foo");
-        collector.add_pending_label("bar");
+        let span = |line| crate::parser::Span {
+            line,
+            column: 1,
+            len: 3,
+        };
+
+        collector.add_pending_label("foo", span(1));
+        collector.add_pending_label("bar", span(2));
         collector.resolve_pending_labels(1);
 
-        collector.add_pending_label("zip");
-        collector.add_pending_label("zap");
-        collector.add_pending_label("gone");
-        let labels = collector.finish();
+        collector.add_pending_label("zip", span(3));
+        collector.add_pending_label("zap", span(4));
+        let mut warnings = Vec::new();
+        let labels = collector.finish(&mut warnings);
 
         assert_eq!(Some(&AbsoluteOffset(1)), labels.get("foo"),);
         assert_eq!(Some(&AbsoluteOffset(1)), labels.get("bar"),);
+        assert_eq!(
+            warnings,
+            vec![
+                Warning::EmptyOffset {
+                    label: "zip".into(),
+                    span: span(3),
+                },
+                Warning::EmptyOffset {
+                    label: "zap".into(),
+                    span: span(4),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_equ_continuation_without_label() {
+        let mut collector = Collector::new(&ParseConfig::default());
+
+        assert_eq!(
+            collector.process_equ_continuation("jne 0, -1", Default::default),
+            Err(Error::EquWithoutLabel {
+                span: Default::default()
+            })
+        );
     }
 
     #[test_case("step", 0, 4, &["a"], &["a"]; "single line")]
@@ -608,7 +875,8 @@ mod test {
 
         let mut lines = vec![line.to_string()];
 
-        expand_lines(&mut lines, 0, &span, &substitution);
+        let mut source_map = SourceMap::identity(&lines);
+        expand_lines(&mut lines, &mut source_map, 0, &span, &substitution);
 
         assert_eq!(lines, expected);
     }
@@ -676,8 +944,15 @@ mod test {
         "label with expansion"
     )]
     fn collects_and_expands_labels(lines: &[&str], expected: &Labels) {
-        let mut lines = lines.iter().map(ToString::to_string).collect();
-        let result = collect_and_expand(&mut lines);
+        let mut lines: Vec<String> = lines.iter().map(ToString::to_string).collect();
+        let mut source_map = SourceMap::identity(&lines);
+        let result = collect_and_expand(
+            &mut lines,
+            &ParseConfig::default(),
+            &mut source_map,
+            &mut Vec::new(),
+        )
+        .expect("Should expand successfully");
 
         for (k, v) in expected {
             assert_eq!(Some(v), result.get(k));
@@ -835,13 +1110,308 @@ mod test {
         ];
         "expand expr labels"
     )]
+    #[test_case(
+        &[
+            "i for 2",
+            "lbl&i dat 0, lbl&i",
+            "rof",
+            "jmp lbl02",
+        ],
+        &[
+            "dat 0, lbl01",
+            "dat 0, lbl02",
+            "jmp -1",
+        ];
+        "concatenate index"
+    )]
+    #[test_case(
+        &[
+            "i for 2",
+            "j for i",
+            "dat i, j",
+            "rof",
+            "rof",
+        ],
+        &[
+            "dat 1, 1",
+            "dat 2, 1",
+            "dat 2, 2",
+        ];
+        "nested count uses outer index"
+    )]
+    #[test_case(
+        &[
+            "i for 2",
+            "j for 2",
+            "x&i&j dat i, j",
+            "rof",
+            "rof",
+            "jmp x0201",
+        ],
+        &[
+            "dat 1, 1",
+            "dat 1, 2",
+            "dat 2, 1",
+            "dat 2, 2",
+            "jmp -2",
+        ];
+        "nested concatenation"
+    )]
+    #[test_case(
+        &[
+            "i for 2",
+            "j for 2",
+            "x&j&i dat i, j",
+            "rof",
+            "rof",
+            "jmp x0201",
+        ],
+        &[
+            "dat 1, 1",
+            "dat 1, 2",
+            "dat 2, 1",
+            "dat 2, 2",
+            "jmp -3",
+        ];
+        "nested concatenation inner counter first"
+    )]
+    #[test_case(
+        &[
+            "i for 2",
+            "j for i == 1",
+            "dat 0, 0",
+            "rof",
+            "rof",
+        ],
+        &[
+            "dat 0, 0",
+        ];
+        "nested conditional"
+    )]
+    #[test_case(
+        &[
+            "step equ 10",
+            "look equ 3 + step * (i - 1)",
+            "i for 2",
+            "dat look, i",
+            "rof",
+        ],
+        &[
+            "dat 3 + 10 * (1 - 1), 1",
+            "dat 3 + 10 * (2 - 1), 2",
+        ];
+        "index in equ"
+    )]
+    #[test_case(
+        &[
+            "here equ CURLINE",
+            "dat CURLINE, 0",
+            "i for 2",
+            "dat CURLINE, here",
+            "for WARRIORS",
+            "dat CURLINE, i",
+            "rof",
+            "rof",
+            "for MAXLENGTH - CURLINE - 93",
+            "dat CURLINE, 0",
+            "rof",
+        ],
+        &[
+            "dat 0, 0",
+            "dat 1, 1",
+            "dat 2, 1",
+            "dat 3, 3",
+            "dat 4, 2",
+            "dat 5, 0",
+            "dat 6, 0",
+        ];
+        "predefined labels in body"
+    )]
     fn collects_and_expands_forrof(lines: &[&str], expected: &[&str]) {
-        let mut lines = lines.iter().map(ToString::to_string).collect();
-        collect_and_expand(&mut lines);
+        let mut lines: Vec<String> = lines.iter().map(ToString::to_string).collect();
+        let mut source_map = SourceMap::identity(&lines);
+        collect_and_expand(
+            &mut lines,
+            &ParseConfig::default(),
+            &mut source_map,
+            &mut Vec::new(),
+        )
+        .expect("Should expand successfully");
 
         let expected_lines: Vec<String> = expected.iter().map(ToString::to_string).collect();
 
         assert_eq!(lines, expected_lines);
+        assert_eq!(source_map.input_lines().len(), lines.len());
+    }
+
+    #[test_case("boot&i", 1, "boot01"; "concatenation")]
+    #[test_case("mov.i <src&i, (i-1)*step", 2, "mov.i <src02, (2-1)*step"; "modifier")]
+    #[test_case("ptr&i equ (pump&i+i)", 12, "ptr12 equ (pump12+12)"; "equ")]
+    #[test_case("dat x&&i, ii", 3, "dat x&&3, ii"; "boolean and")]
+    #[test_case("lbl&j&i", 1, "lbl&j&01"; "waits for earlier segment")]
+    #[test_case("lbl&02&i", 1, "lbl0201"; "joins after earlier segment")]
+    #[test_case("lbl&i&j", 1, "lbl01&j"; "joins before later segment")]
+    fn substitutes_counter(line: &str, value: u32, expected: &str) {
+        assert_eq!(substitute_counter(line, "i", value), expected);
+    }
+
+    #[test_case(&["rof"], "ROF"; "unmatched rof")]
+    #[test_case(&["for 2", "dat 0, 0"], "FOR"; "unclosed for")]
+    #[test_case(&["for -1", "rof"], "FOR count"; "negative count")]
+    #[test_case(&["for n", "rof"], "no such label"; "unknown count")]
+    #[test_case(
+        &["for (CORESIZE == 8000) - 2", "rof"],
+        "found -1";
+        "negative condition"
+    )]
+    #[test_case(&["for 2000000000", "dat 0, 0", "rof"], "found 2000000000"; "huge count")]
+    #[test_case(&["for MAXLENGTH + 1", "rof"], "MAXLENGTH (100), found 101"; "count above max length")]
+    fn rejects_bad_forrof(lines: &[&str], message: &str) {
+        let mut lines: Vec<String> = lines.iter().map(ToString::to_string).collect();
+        let mut source_map = SourceMap::identity(&lines);
+
+        let err = collect_and_expand(
+            &mut lines,
+            &ParseConfig::default(),
+            &mut source_map,
+            &mut Vec::new(),
+        )
+        .expect_err("Should fail to expand");
+
+        assert!(err.to_string().contains(message), "{}", err);
+        assert_eq!(err.span().line, 1);
+    }
+
+    #[test_case("CORESIZE == 8000", &["mov 0, 1", "jmp -1"], &[]; "condition met")]
+    #[test_case("CORESIZE == 800", &["jmp 0"], &[(2, 1)]; "condition not met")]
+    #[test_case("CORESIZE > 4000 && MAXLENGTH >= 2", &["mov 0, 1", "jmp -1"], &[]; "compound")]
+    #[test_case("(CORESIZE % 3) * 0", &["jmp 0"], &[(2, 1)]; "zero expression")]
+    fn expands_conditional_forrof(
+        condition: &str,
+        expected: &[&str],
+        expected_dropped: &[(usize, usize)],
+    ) {
+        let mut lines = vec![
+            "top".to_owned(),
+            format!("for ({})", condition),
+            "mov 0, 1".to_owned(),
+            "rof".to_owned(),
+            "jmp top".to_owned(),
+        ];
+        let mut source_map = SourceMap::identity(&lines);
+        let mut warnings = Vec::new();
+
+        let labels = collect_and_expand(
+            &mut lines,
+            &ParseConfig::default(),
+            &mut source_map,
+            &mut warnings,
+        )
+        .expect("Should expand successfully");
+        substitute_offsets(&mut lines, &labels);
+
+        let lines: Vec<&str> = lines
+            .iter()
+            .map(String::as_str)
+            .filter(|l| !l.is_empty())
+            .collect();
+        assert_eq!(lines, expected);
+
+        let dropped: Vec<_> = warnings
+            .iter()
+            .map(|warning| match warning {
+                Warning::DroppedFor { lines, span } => (span.line, *lines),
+                other => panic!("Unexpected warning {:?}", other),
+            })
+            .collect();
+        assert_eq!(dropped, expected_dropped);
+    }
+
+    #[test]
+    fn warns_once_for_nested_dropped_for() {
+        let mut lines: Vec<String> = ["i for 3", "for i == 2", "dat i, 0", "rof", "rof"]
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        let mut source_map = SourceMap::identity(&lines);
+        let mut warnings = Vec::new();
+
+        collect_and_expand(
+            &mut lines,
+            &ParseConfig::default(),
+            &mut source_map,
+            &mut warnings,
+        )
+        .expect("Should expand successfully");
+
+        assert_eq!(lines, vec!["dat 2, 0"]);
+        assert_eq!(warnings.len(), 1);
+        assert!(
+            matches!(warnings[0], Warning::DroppedFor { lines: 1, span } if span.line == 2),
+            "{:?}",
+            warnings
+        );
+    }
+
+    #[test]
+    fn overrides_defined_labels() {
+        let lines: Vec<String> = [
+            "step equ 4",
+            "bomb equ 1",
+            "top add #step, bomb",
+            "jmp top, size",
+        ]
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+        let mut source_map = SourceMap::identity(&lines);
+        let config = ParseConfig {
+            labels: [("step", 3044), ("size", 800), ("top", 5)]
+                .iter()
+                .map(|&(label, value)| (label.to_owned(), value))
+                .collect(),
+            ..ParseConfig::default()
+        };
+
+        let expanded = expand(
+            lines,
+            None,
+            Vec::new(),
+            &config,
+            &mut source_map,
+            &mut Vec::new(),
+        )
+        .expect("Should expand successfully");
+
+        assert_eq!(expanded.text, vec!["add #3044, 1", "jmp 5, 800"]);
+    }
+
+    #[test]
+    fn maps_expanded_lines_to_input() {
+        let mut lines: Vec<String> = [
+            "two equ mov 0, 1",
+            "equ jmp -1",
+            "for 2",
+            "dat 0, 0",
+            "rof",
+            "lbl",
+            "two",
+        ]
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+        let mut source_map = SourceMap::identity(&lines);
+
+        collect_and_expand(
+            &mut lines,
+            &ParseConfig::default(),
+            &mut source_map,
+            &mut Vec::new(),
+        )
+        .expect("Should expand successfully");
+
+        assert_eq!(lines, vec!["dat 0, 0", "dat 0, 0", "mov 0, 1", "jmp -1"]);
+        assert_eq!(source_map.input_lines(), &[3, 3, 6, 6]);
     }
 
     #[test_case(
@@ -928,25 +1498,38 @@ mod test {
             "mov 1, CORESIZE-1",
             "add 3, CURLINE",
             "dat MAXLENGTH, MAXLENGTH",
+            "dat PSPACESIZE, WARRIORS",
+            "dat VERSION, CURLINE",
         ],
         &[
             "add 2, 0",
             "mov 1, 8000-1",
             "add 3, 2",
             "dat 100, 100",
+            "dat 500, 1",
+            "dat 94, 5",
         ];
         "expand default labels"
     )]
     fn expands_substitutions(lines: &[&str], expected: &[&str]) {
-        let lines = lines.iter().map(ToString::to_string).collect();
+        let lines: Vec<String> = lines.iter().map(ToString::to_string).collect();
+        let mut source_map = SourceMap::identity(&lines);
         let expected: Vec<String> = expected.iter().map(ToString::to_string).collect();
 
         assert_eq!(
-            Lines {
+            Ok(Lines {
                 text: expected,
                 origin: None,
-            },
-            expand(lines, None),
+                assertions: Vec::new(),
+            }),
+            expand(
+                lines,
+                None,
+                Vec::new(),
+                &ParseConfig::default(),
+                &mut source_map,
+                &mut Vec::new(),
+            ),
         );
     }
 
@@ -1016,15 +1599,24 @@ mod test {
         origin: Option<String>,
         expected_origin: Option<String>,
     ) {
-        let lines = lines.iter().map(ToString::to_string).collect();
+        let lines: Vec<String> = lines.iter().map(ToString::to_string).collect();
+        let mut source_map = SourceMap::identity(&lines);
         let expected: Vec<String> = expected_lines.iter().map(ToString::to_string).collect();
 
         assert_eq!(
-            expand(lines, origin),
-            Lines {
+            expand(
+                lines,
+                origin,
+                Vec::new(),
+                &ParseConfig::default(),
+                &mut source_map,
+                &mut Vec::new(),
+            ),
+            Ok(Lines {
                 text: expected,
                 origin: expected_origin,
-            }
+                assertions: Vec::new(),
+            })
         );
     }
 }
@@ -2,14 +2,19 @@
 //! is a submodule within this module.
 
 use std::convert::TryFrom;
+use std::ops::Range;
 
 mod comment;
 mod evaluation;
 mod expansion;
 
+pub use comment::split_warriors;
+
 use crate::load_file;
 
-use super::error::Error;
+use super::error::{Error, Warning};
+use super::span::SourceMap;
+use super::ParseConfig;
 
 /// The data type that is passed through the parser phases. This is a simple state
 /// machine, which transitions to the next state by passing through a parser phase.
@@ -17,22 +22,52 @@ use super::error::Error;
 pub struct Phase<PhaseState> {
     /// The original input to the parser, which can be used for spans / string views
     buffer: String,
+    /// The environment the warrior is being parsed for
+    config: ParseConfig,
+    /// The input line each line of the current phase came from
+    source_map: SourceMap,
+    /// Warnings found by the phases so far
+    pub warnings: Vec<Warning>,
     /// State specific to the current phase of the state machine
     pub state: PhaseState,
 }
 
 /// The initial state of parsing, before any preprocessing has occurred.
-pub struct Raw;
+pub struct Raw {
+    /// The lines of the input which contain the warrior to parse
+    warrior: Range<usize>,
+}
 
-impl From<&str> for Phase<Raw> {
-    fn from(buf: &str) -> Self {
+impl Phase<Raw> {
+    /// Start parsing the warrior found in the `warrior` lines of `buf`
+    pub fn new(buf: &str, warrior: Range<usize>, config: &ParseConfig) -> Self {
         Phase {
             buffer: buf.to_string(),
-            state: Raw,
+            config: config.clone(),
+            source_map: SourceMap::new(buf),
+            warnings: Vec::new(),
+            state: Raw { warrior },
         }
     }
 }
 
+/// An error which stopped the parser, with the warnings found before it
+#[derive(Debug)]
+pub struct Failure {
+    pub error: Error,
+    pub warnings: Vec<Warning>,
+}
+
+/// An `;assert` directive, which must evaluate to a non-zero value for the
+/// warrior to assemble
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Assertion {
+    /// The line of the input the directive is on, starting from 0
+    pub line: usize,
+    /// The expression to evaluate
+    pub expression: String,
+}
+
 /// The Phase after comments have been removed and metadata parsed from comments.
 /// This phase also parses ORG and END, and removes any text after END
 #[derive(Debug, Default, PartialEq, Eq)]
@@ -40,14 +75,32 @@ pub struct CommentsRemoved {
     pub lines: Vec<String>,
     pub metadata: load_file::Metadata,
     pub origin: Option<String>,
+    /// Every `;assert` in the input, in order
+    pub assertions: Vec<Assertion>,
 }
 
-impl From<Phase<Raw>> for Phase<CommentsRemoved> {
-    fn from(prev: Phase<Raw>) -> Self {
-        let state = comment::extract_from_string(&prev.buffer);
-        Self {
-            buffer: prev.buffer,
-            state,
+impl TryFrom<Phase<Raw>> for Phase<CommentsRemoved> {
+    type Error = Failure;
+
+    fn try_from(prev: Phase<Raw>) -> Result<Self, Failure> {
+        let mut source_map = prev.source_map;
+        let mut warnings = prev.warnings;
+
+        match comment::extract_from_string(
+            &prev.buffer,
+            prev.state.warrior,
+            &prev.config.directives,
+            &mut source_map,
+            &mut warnings,
+        ) {
+            Ok(state) => Ok(Self {
+                buffer: prev.buffer,
+                config: prev.config,
+                source_map,
+                warnings,
+                state,
+            }),
+            Err(error) => Err(Failure { error, warnings }),
         }
     }
 }
@@ -65,20 +118,42 @@ pub struct Expanded {
     /// The entrypoint to the program, gathered in previous phase. This is still
     /// a string because it may be an expression to be evaluated later
     origin: Option<String>,
+
+    /// Assertions gathered in previous phase, with labels substituted
+    assertions: Vec<Assertion>,
 }
 
-impl From<Phase<CommentsRemoved>> for Phase<Expanded> {
-    fn from(prev: Phase<CommentsRemoved>) -> Self {
-        let lines = expansion::expand(prev.state.lines, prev.state.origin);
+impl TryFrom<Phase<CommentsRemoved>> for Phase<Expanded> {
+    type Error = Failure;
+
+    fn try_from(prev: Phase<CommentsRemoved>) -> Result<Self, Failure> {
+        let mut source_map = prev.source_map;
+        let mut warnings = prev.warnings;
+
+        let lines = match expansion::expand(
+            prev.state.lines,
+            prev.state.origin,
+            prev.state.assertions,
+            &prev.config,
+            &mut source_map,
+            &mut warnings,
+        ) {
+            Ok(lines) => lines,
+            Err(error) => return Err(Failure { error, warnings }),
+        };
 
-        Self {
+        Ok(Self {
             buffer: prev.buffer,
+            config: prev.config,
+            source_map,
+            warnings,
             state: Expanded {
                 lines: lines.text,
                 origin: lines.origin,
                 metadata: prev.state.metadata,
+                assertions: lines.assertions,
             },
-        }
+        })
     }
 }
 
@@ -94,21 +169,41 @@ pub struct Evaluated {
 }
 
 impl TryFrom<Phase<Expanded>> for Phase<Evaluated> {
-    type Error = Error;
-
-    fn try_from(prev: Phase<Expanded>) -> Result<Self, Error> {
-        let instructions = evaluation::evaluate(prev.state.lines)?;
-        let origin = prev
-            .state
-            .origin
-            .as_ref()
-            .map(|s| evaluation::evaluate_expression(s))
-            .transpose()?;
-
-        // TODO evaluate assertions
+    type Error = Failure;
+
+    fn try_from(prev: Phase<Expanded>) -> Result<Self, Failure> {
+        let source_map = &prev.source_map;
+        let assertions = &prev.state.assertions;
+        let origin = prev.state.origin.as_ref();
+
+        let evaluated =
+            evaluation::evaluate(prev.state.lines, source_map).and_then(|instructions| {
+                let origin = origin
+                    .map(|s| evaluation::evaluate_origin(s, source_map))
+                    .transpose()?;
+
+                for assertion in assertions {
+                    evaluation::check_assertion(assertion, source_map)?;
+                }
+
+                Ok((instructions, origin))
+            });
+
+        let (instructions, origin) = match evaluated {
+            Ok(evaluated) => evaluated,
+            Err(error) => {
+                return Err(Failure {
+                    error,
+                    warnings: prev.warnings,
+                })
+            }
+        };
 
         Ok(Self {
             buffer: prev.buffer,
+            config: prev.config,
+            source_map: prev.source_map,
+            warnings: prev.warnings,
             state: Evaluated {
                 metadata: prev.state.metadata,
                 program: load_file::Program {
@@ -130,6 +225,9 @@ impl From<Phase<Evaluated>> for Phase<Output> {
     fn from(prev: Phase<Evaluated>) -> Self {
         Self {
             buffer: prev.buffer,
+            config: prev.config,
+            source_map: prev.source_map,
+            warnings: prev.warnings,
             state: Output {
                 warrior: load_file::Warrior {
                     metadata: prev.state.metadata,
@@ -0,0 +1,243 @@
+//! Locations in the input of the parser, for reporting errors and warnings.
+//!
+//! The parser rewrites lines as it goes: comments are removed, labels are
+//! stripped and substituted, and `EQU` and `FOR` expand into several lines.
+//! A [`SourceMap`] follows these changes, so each line of rewritten text can
+//! be traced back to the line of input it came from.
+
+use std::fmt;
+use std::ops::Range;
+
+/// A range of characters on a single line of the parser's input
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    /// The line number, starting from 1
+    pub line: usize,
+
+    /// The column of the first character, starting from 1
+    pub column: usize,
+
+    /// The number of characters in the span
+    pub len: usize,
+}
+
+impl Span {
+    /// Find `text` in the `source` text of a line, where `line` starts from 0.
+    /// Text that was rewritten by the parser may not appear in the source, in
+    /// which case the span covers the code on the line, without comments or
+    /// indentation. A line with only a comment, like `;assert`, is covered
+    /// entirely.
+    fn find(source: &str, line: usize, text: &str) -> Self {
+        let (start, len) = match find_token(source, text) {
+            Some(start) => (start, text.chars().count()),
+            None => {
+                let code = Some(source.split(';').next().unwrap_or_default())
+                    .filter(|code| !code.trim().is_empty())
+                    .unwrap_or(source);
+                let start = code.len() - code.trim_start().len();
+                (start, code.trim().chars().count())
+            }
+        };
+
+        Self {
+            line: line + 1,
+            column: source[..start].chars().count() + 1,
+            len: len.max(1),
+        }
+    }
+}
+
+/// Find the byte offset of `text` in `source`, where it isn't part of a
+/// longer label or number
+fn find_token(source: &str, text: &str) -> Option<usize> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    if text.trim().is_empty() {
+        return None;
+    }
+
+    source
+        .match_indices(text)
+        .map(|(start, _)| start)
+        .find(|&start| {
+            let before = source[..start].chars().next_back();
+            let after = source[start + text.len()..].chars().next();
+            let joined = |outer: Option<char>, inner: Option<char>| {
+                outer.is_some_and(is_word) && inner.is_some_and(is_word)
+            };
+            !joined(before, text.chars().next()) && !joined(after, text.chars().next_back())
+        })
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Maps each line of rewritten text to the line of input it came from
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    /// The lines of the original input
+    input: Vec<String>,
+
+    /// The input line of each line of text, starting from 0
+    lines: Vec<usize>,
+
+    /// The input line the origin was set on, if any
+    origin: Option<usize>,
+}
+
+impl SourceMap {
+    /// Create a map for the given input, without any lines of text yet
+    pub fn new(input: &str) -> Self {
+        Self {
+            input: input.lines().map(ToOwned::to_owned).collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Create a map for lines of text which are the input, unchanged
+    #[cfg(test)]
+    pub fn identity(lines: &[String]) -> Self {
+        Self {
+            input: lines.to_vec(),
+            lines: (0..lines.len()).collect(),
+            origin: None,
+        }
+    }
+
+    /// The input line of each line of text
+    #[cfg(test)]
+    pub fn input_lines(&self) -> &[usize] {
+        &self.lines
+    }
+
+    /// Add a line of text which came from the given input line
+    pub fn push(&mut self, input_line: usize) {
+        self.lines.push(input_line);
+    }
+
+    /// Record the input line the origin was set on
+    pub fn set_origin(&mut self, input_line: usize) {
+        self.origin = Some(input_line);
+    }
+
+    /// Find `text` in the input line that a line of text came from
+    pub fn span(&self, index: usize, text: &str) -> Span {
+        self.input_span(self.lines.get(index).copied().unwrap_or_default(), text)
+    }
+
+    /// Find `text` in the input line the origin was set on
+    pub fn origin_span(&self, text: &str) -> Span {
+        self.input_span(self.origin.unwrap_or_default(), text)
+    }
+
+    /// Find `text` in a line of the input, starting from 0
+    pub fn input_span(&self, input_line: usize, text: &str) -> Span {
+        let source = self.input.get(input_line).map_or("", String::as_str);
+        Span::find(source, input_line, text)
+    }
+
+    /// Follow the removal of a line of text
+    pub fn remove(&mut self, index: usize) {
+        self.lines.remove(index);
+    }
+
+    /// Follow a line of text being replaced by `count` lines, which all come
+    /// from the same input line
+    pub fn expand(&mut self, index: usize, count: usize) {
+        let input_line = self.lines[index];
+        self.lines
+            .splice(index..=index, std::iter::repeat_n(input_line, count));
+    }
+
+    /// Follow the lines in `replaced` being replaced by the lines in `body`
+    /// repeated `times` times, as when unrolling a `FOR` loop
+    pub fn repeat(&mut self, replaced: Range<usize>, body: Range<usize>, times: usize) {
+        let repeated: Vec<usize> = self.lines[body.clone()]
+            .iter()
+            .copied()
+            .cycle()
+            .take(times * body.len())
+            .collect();
+        self.lines.splice(replaced, repeated);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn find_span() {
+        let map = SourceMap::new("; comment\n  start  MOV foobar, foo ; foo\n");
+
+        assert_eq!(
+            map.input_span(1, "foo"),
+            Span {
+                line: 2,
+                column: 22,
+                len: 3
+            }
+        );
+
+        // Rewritten text spans the code on the line
+        assert_eq!(
+            map.input_span(1, "MOV 4, 1"),
+            Span {
+                line: 2,
+                column: 3,
+                len: 22
+            }
+        );
+
+        // Or the comment, if there is no code
+        assert_eq!(
+            map.input_span(0, "bar"),
+            Span {
+                line: 1,
+                column: 1,
+                len: 9
+            }
+        );
+    }
+
+    #[test]
+    fn follow_expansion() {
+        let mut map = SourceMap::new("");
+        for line in [0, 2, 3, 4, 5] {
+            map.push(line);
+        }
+
+        // An EQU used on the first line expands to two lines
+        map.expand(0, 2);
+        // The label on line 2 is removed
+        map.remove(2);
+        // A FOR loop from line 3 to 5 is repeated twice
+        map.repeat(2..5, 3..4, 2);
+
+        assert_eq!(map.lines, vec![0, 0, 4, 4]);
+    }
+
+    #[test]
+    fn span_parse_errors() {
+        use crate::parser::{parse, Error, Result};
+
+        let input = "; a comment\nstep equ 4\n  for 2\nloop mov step, far\n  rof\n";
+
+        match parse(input) {
+            Result::Err(Error::LabelNotFound { label, span }, _) => {
+                assert_eq!(label, "far");
+                assert_eq!(
+                    span,
+                    Span {
+                        line: 4,
+                        column: 16,
+                        len: 3,
+                    }
+                );
+            }
+            other => panic!("Expected an unknown label, got {:?}", other),
+        }
+    }
+}
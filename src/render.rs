@@ -0,0 +1,331 @@
+//! Rendering a battle to image files, e.g. for write-ups or as artefacts of a
+//! CI run. Unlike the terminal view of a [`Visualiser`](crate::visualiser::Visualiser),
+//! this needs no terminal, but uses the same record the core keeps of which
+//! warrior owns each cell.
+//!
+//! A [`heatmap`](Image::heatmap) draws one square per cell, coloured by the
+//! warrior that last wrote it. The more often a cell was written, the brighter
+//! it is, and cells that a task will execute next are white. Images can be
+//! saved as PNG, or collected into an [`Animation`] saved as a GIF.
+
+use std::io::Write;
+
+use thiserror::Error as ThisError;
+
+use crate::core::Core;
+use crate::load_file::Offset;
+
+/// The colours used for warriors, in order of warrior ID. These match the
+/// ANSI colours used by the [`Visualiser`](crate::visualiser::Visualiser).
+const PALETTE: &[[u8; 3]] = &[
+    [230, 60, 60],
+    [60, 200, 60],
+    [230, 200, 50],
+    [70, 110, 240],
+    [210, 70, 210],
+    [60, 200, 210],
+];
+
+/// The colour of cells that a task will execute next
+const TASK: [u8; 3] = [255, 255, 255];
+
+/// The brightness of a cell that was loaded but never written, relative to
+/// the most written cell
+const MIN_BRIGHTNESS: f64 = 0.25;
+
+/// The most pixels a heatmap may have, so that a large core or scale fails
+/// instead of allocating gigabytes
+pub const MAX_PIXELS: u64 = 1 << 27;
+
+/// An error occurred while encoding an image
+#[derive(ThisError, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Png(#[from] png::EncodingError),
+
+    #[error(transparent)]
+    Gif(#[from] gif::EncodingError),
+
+    /// The image was too large to draw, or to encode in the format
+    #[error("image of {0}x{1} pixels is too large")]
+    TooLarge(u64, u64),
+
+    /// A frame of an animation was a different size to the first frame
+    #[error("frame of {0}x{1} pixels doesn't match the size of the animation")]
+    FrameSize(u32, u32),
+}
+
+/// An RGB image
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Draw the core as a grid of `columns` cells per row, with each cell a
+    /// square of `scale` pixels. How often each cell was written is only
+    /// known if the core has [`CoreConfig::cell_stats`](crate::core::CoreConfig::cell_stats)
+    /// enabled, otherwise every owned cell is drawn at the lowest brightness.
+    ///
+    /// Returns an error if the image would have more than [`MAX_PIXELS`].
+    pub fn heatmap(core: &Core, columns: u32, scale: u32) -> Result<Self, Error> {
+        let columns = u64::from(columns.max(1));
+        let scale = u64::from(scale.max(1));
+        let rows = (core.len() as u64).div_ceil(columns);
+
+        let (width, height) = (columns * scale, rows * scale);
+        if width
+            .checked_mul(height)
+            .is_none_or(|pixels| pixels > MAX_PIXELS)
+        {
+            return Err(Error::TooLarge(width, height));
+        }
+
+        // Both sides are at most MAX_PIXELS, so the casts can't truncate
+        let mut image = Self {
+            width: width as u32,
+            height: height as u32,
+            pixels: vec![0; (width * height * 3) as usize],
+        };
+        let (columns, scale) = (columns as usize, scale as usize);
+
+        let write_count = |index| {
+            core.cell_stats()
+                .map_or(0, |stats| stats.writes(Offset::new(index, core.len())))
+        };
+        let max_writes = (0..core.len()).map(write_count).max().unwrap_or(0);
+
+        for index in 0..core.len() {
+            let colour = match core.last_writer(index) {
+                Some(warrior) => brighten(
+                    PALETTE[warrior % PALETTE.len()],
+                    write_count(index),
+                    max_writes,
+                ),
+                None => continue,
+            };
+            image.fill(index as usize, columns, scale, colour);
+        }
+
+        for entry in core.process_queue().iter() {
+            image.fill(entry.offset.value() as usize, columns, scale, TASK);
+        }
+
+        Ok(image)
+    }
+
+    #[must_use]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[must_use]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Get the colour of the pixel at the given coordinates
+    #[must_use]
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 3] {
+        let start = (y as usize * self.width as usize + x as usize) * 3;
+        [
+            self.pixels[start],
+            self.pixels[start + 1],
+            self.pixels[start + 2],
+        ]
+    }
+
+    /// Fill the square of the cell at `index` with a colour
+    fn fill(&mut self, index: usize, columns: usize, scale: usize, colour: [u8; 3]) {
+        let left = index % columns * scale;
+        let top = index / columns * scale;
+        for y in top..top + scale {
+            for x in left..left + scale {
+                let start = (y * self.width as usize + x) * 3;
+                self.pixels[start..start + 3].copy_from_slice(&colour);
+            }
+        }
+    }
+
+    /// Encode the image as a PNG
+    pub fn write_png<W: Write>(&self, writer: W) -> Result<(), Error> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)?;
+        Ok(())
+    }
+}
+
+/// Scale a colour by how often a cell was written, on a log scale so that
+/// cells written a few times are still visible next to a busy loop
+fn brighten(colour: [u8; 3], writes: u32, max_writes: u32) -> [u8; 3] {
+    let brightness = if max_writes == 0 {
+        MIN_BRIGHTNESS
+    } else {
+        let fraction = f64::from(writes).ln_1p() / f64::from(max_writes).ln_1p();
+        MIN_BRIGHTNESS + (1.0 - MIN_BRIGHTNESS) * fraction
+    };
+    colour.map(|channel| (f64::from(channel) * brightness).round() as u8)
+}
+
+/// An animated GIF, built from images of the same size
+pub struct Animation<W: Write> {
+    encoder: gif::Encoder<W>,
+    width: u32,
+    height: u32,
+    delay: u16,
+}
+
+impl<W: Write> Animation<W> {
+    /// Start an animation of `width` by `height` pixels, which shows each
+    /// frame for `delay` hundredths of a second and loops forever
+    pub fn new(writer: W, width: u32, height: u32, delay: u16) -> Result<Self, Error> {
+        let (gif_width, gif_height) = match (u16::try_from(width), u16::try_from(height)) {
+            (Ok(width), Ok(height)) => (width, height),
+            _ => return Err(Error::TooLarge(width.into(), height.into())),
+        };
+
+        let mut encoder = gif::Encoder::new(writer, gif_width, gif_height, &[])?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+
+        Ok(Self {
+            encoder,
+            width,
+            height,
+            delay,
+        })
+    }
+
+    /// Add an image as the next frame of the animation
+    pub fn add_frame(&mut self, image: &Image) -> Result<(), Error> {
+        if (image.width, image.height) != (self.width, self.height) {
+            return Err(Error::FrameSize(image.width, image.height));
+        }
+
+        let mut frame =
+            gif::Frame::from_rgb_speed(image.width as u16, image.height as u16, &image.pixels, 10);
+        frame.delay = self.delay;
+        self.encoder.write_frame(&frame)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    use crate::core::tests::build_core_with;
+    use crate::core::CoreConfig;
+
+    fn build_core(program: &str) -> Core {
+        let (core, _) = build_core_with(
+            CoreConfig {
+                core_size: 16,
+                min_distance: 4,
+                max_warrior_length: 4,
+                cell_stats: true,
+                ..CoreConfig::default()
+            },
+            &[program],
+        );
+        core
+    }
+
+    #[test]
+    fn heatmap_colours() {
+        let mut core = build_core(
+            "
+            mov 2, 4
+            jmp -1
+            dat 0, 0
+            ",
+        );
+        for _ in 0..4 {
+            core.step().expect("Failed to step");
+        }
+
+        let image = Image::heatmap(&core, 8, 2).expect("Failed to draw heatmap");
+        assert_eq!((image.width(), image.height()), (16, 4));
+
+        let red = PALETTE[0];
+        let dim = red.map(|channel| (f64::from(channel) * MIN_BRIGHTNESS).round() as u8);
+        // mov 2, 4 has executed twice, and will execute next
+        assert_eq!(image.pixel(0, 0), TASK);
+        assert_eq!(image.pixel(3, 1), dim);
+        assert_eq!(image.pixel(4, 0), dim);
+        // The only cell written during the battle
+        assert_eq!(image.pixel(9, 1), red);
+        assert_eq!(image.pixel(6, 0), [0, 0, 0]);
+        assert_eq!(image.pixel(15, 3), [0, 0, 0]);
+    }
+
+    #[test]
+    fn encode_png() {
+        let core = build_core("jmp 0");
+        let image = Image::heatmap(&core, 4, 1).expect("Failed to draw heatmap");
+
+        let mut data = Vec::new();
+        image.write_png(&mut data).expect("Failed to encode PNG");
+
+        let mut reader = png::Decoder::new(data.as_slice())
+            .read_info()
+            .expect("Failed to decode PNG");
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader
+            .next_frame(&mut pixels)
+            .expect("Failed to decode PNG");
+        assert_eq!(pixels, image.pixels);
+    }
+
+    #[test]
+    fn encode_gif() {
+        let mut core = build_core("mov 0, 1");
+
+        let mut data = Vec::new();
+        let mut animation = Animation::new(&mut data, 4, 4, 5).expect("Failed to start GIF");
+        for _ in 0..3 {
+            animation
+                .add_frame(&Image::heatmap(&core, 4, 1).expect("Failed to draw heatmap"))
+                .expect("Failed to add frame");
+            core.step().expect("Failed to step");
+        }
+        assert!(matches!(
+            animation.add_frame(&Image::heatmap(&core, 8, 1).expect("Failed to draw heatmap")),
+            Err(Error::FrameSize(8, 2))
+        ));
+        drop(animation);
+
+        let mut decoder = gif::DecodeOptions::new()
+            .read_info(data.as_slice())
+            .expect("Failed to decode GIF");
+        let mut frames = 0;
+        while let Some(frame) = decoder.read_next_frame().expect("Failed to decode GIF") {
+            assert_eq!(frame.delay, 5);
+            frames += 1;
+        }
+        assert_eq!(frames, 3);
+    }
+
+    #[test]
+    fn heatmap_too_large() {
+        let core = build_core("jmp 0");
+
+        assert!(matches!(
+            Image::heatmap(&core, u32::MAX, u32::MAX),
+            Err(Error::TooLarge(..))
+        ));
+        assert!(matches!(
+            Image::heatmap(&core, 1, 1 << 20),
+            Err(Error::TooLarge(1_048_576, 16_777_216))
+        ));
+    }
+}
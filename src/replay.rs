@@ -0,0 +1,710 @@
+//! A compact binary format for recording a battle, so that it can be played
+//! back exactly, e.g. to reproduce a bug or discuss a strategy.
+//!
+//! A replay is recorded with a [`Recorder`] observing a [`Core`], and played
+//! back with a [`Player`], which runs the battle again and checks that each
+//! cycle matches the recording.
+//!
+//! # Format
+//!
+//! All integers are unsigned LEB128 varints, unless noted otherwise. Signed
+//! config values are zigzag encoded first.
+//!
+//! | Field    | Contents                                                    |
+//! |----------|-------------------------------------------------------------|
+//! | Magic    | The bytes `CWRP`                                            |
+//! | Version  | [`VERSION`], as a little-endian `u16`                        |
+//! | Config   | Core size, max cycles, max processes, max warrior length, min distance and P-space size |
+//! | Seed     | A `0` byte if there is no seed, or a `1` byte followed by a little-endian `u64` |
+//! | Parsing  | The number of rounds, then the number of labels defined for the warriors, then for each one its name as a length-prefixed UTF-8 string and its value |
+//! | Warriors | The number of warriors, then for each one its offset in the core and its load file as a length-prefixed UTF-8 string |
+//! | Cycles   | The number of cycles, then for each one the warrior ID, the program counter and the number of writes, followed by the writes |
+//!
+//! Each write is the offset written to, then the instruction written: its
+//! opcode, modifier, and address modes (A in the high nibble, B in the low
+//! nibble) as single bytes, followed by the A and B values.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+use thiserror::Error as ThisError;
+
+use crate::core::{self, Core, CoreConfig, CoreObserver, StepOutcome, Tee, WarriorID};
+use crate::load_file::{AddressMode, Field, Instruction, Modifier, Offset, Opcode, Warrior};
+use crate::parser;
+
+/// The magic bytes at the start of every replay
+const MAGIC: &[u8; 4] = b"CWRP";
+
+/// The current version of the replay format
+pub const VERSION: u16 = 1;
+
+/// An error occurred while reading or playing back a replay
+#[derive(ThisError, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    /// The data did not start with the replay magic bytes
+    #[error("not a replay file")]
+    InvalidMagic,
+
+    /// The replay was written with a version of the format that isn't supported
+    #[error("unsupported replay version {0}; expected version {}", VERSION)]
+    UnsupportedVersion(u16),
+
+    /// The replay data was malformed
+    #[error("invalid replay: {0}")]
+    Invalid(String),
+
+    /// The load file of a warrior in the replay could not be parsed
+    #[error("failed to parse warrior {0} in replay: {1}")]
+    InvalidWarrior(WarriorID, String),
+
+    #[error(transparent)]
+    Core(#[from] core::Error),
+
+    /// Playing back the replay did not produce the recorded cycle
+    #[error("replay diverged from the simulation at cycle {0}")]
+    Diverged(usize),
+}
+
+/// A recorded battle
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Replay {
+    /// The configuration of the core. Only the sizes and limits are recorded,
+    /// other settings are left as their default.
+    pub config: CoreConfig,
+
+    /// The number of rounds in the battle the replay was recorded from, which
+    /// is the value of `ROUNDS` for the warriors
+    pub rounds: i32,
+
+    /// Extra labels the warriors were parsed with, as in
+    /// [`ParseConfig::labels`](parser::ParseConfig::labels)
+    pub labels: BTreeMap<String, i32>,
+
+    /// The warriors in the battle, indexed by warrior ID
+    pub warriors: Vec<LoadedWarrior>,
+
+    /// Every cycle of the battle, in order
+    pub cycles: Vec<Cycle>,
+}
+
+/// A warrior as it was loaded into the core
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoadedWarrior {
+    /// The offset the warrior was loaded at
+    pub offset: i32,
+
+    /// The load file of the warrior, as formatted by its `Display` implementation
+    pub load_file: String,
+}
+
+/// A single cycle of a battle
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cycle {
+    /// The warrior whose task executed
+    pub warrior: WarriorID,
+
+    /// The offset of the executed instruction
+    pub program_counter: i32,
+
+    /// The cells written during the cycle, in order
+    pub writes: Vec<CellWrite>,
+}
+
+/// An instruction written to the core
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CellWrite {
+    pub offset: i32,
+    pub instruction: Instruction,
+}
+
+/// A [`CoreObserver`] which records every cycle of a battle
+#[derive(Debug, Default)]
+pub struct Recorder {
+    cycles: Vec<Cycle>,
+    writes: Vec<CellWrite>,
+}
+
+impl Recorder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a replay from the recorded cycles, with the warriors that were
+    /// loaded into `core` after being parsed with `parse_config`.
+    #[must_use]
+    pub fn finish(
+        self,
+        core: &Core,
+        warriors: &[Warrior],
+        parse_config: &parser::ParseConfig,
+    ) -> Replay {
+        Replay {
+            config: core.config().clone(),
+            rounds: parse_config.rounds,
+            labels: parse_config.labels.clone(),
+            warriors: warriors
+                .iter()
+                .zip(core.placements())
+                .map(|(warrior, offset)| LoadedWarrior {
+                    offset: offset.value(),
+                    load_file: warrior.to_string(),
+                })
+                .collect(),
+            cycles: self.cycles,
+        }
+    }
+}
+
+impl CoreObserver for Recorder {
+    fn instruction_executed(&mut self, step: &core::Step) {
+        self.cycles.push(Cycle {
+            warrior: step.warrior,
+            program_counter: step.program_counter.value(),
+            writes: std::mem::take(&mut self.writes),
+        });
+    }
+
+    fn cell_written(
+        &mut self,
+        _warrior: WarriorID,
+        offset: Offset,
+        _old: &Instruction,
+        new: &Instruction,
+    ) {
+        self.writes.push(CellWrite {
+            offset: offset.value(),
+            instruction: new.clone(),
+        });
+    }
+}
+
+impl Replay {
+    /// Write the replay in the binary format
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+
+        for &value in &[
+            self.config.core_size,
+            self.config.max_cycles,
+            self.config.max_processes,
+            self.config.max_warrior_length,
+            self.config.min_distance,
+            self.config.p_space,
+        ] {
+            write_signed(&mut writer, value)?;
+        }
+
+        match self.config.seed {
+            Some(seed) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&seed.to_le_bytes())?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+
+        write_signed(&mut writer, self.rounds)?;
+        write_varint(&mut writer, self.labels.len() as u64)?;
+        for (label, &value) in &self.labels {
+            write_string(&mut writer, label)?;
+            write_signed(&mut writer, value)?;
+        }
+
+        write_varint(&mut writer, self.warriors.len() as u64)?;
+        for warrior in &self.warriors {
+            write_varint(&mut writer, warrior.offset as u64)?;
+            write_string(&mut writer, &warrior.load_file)?;
+        }
+
+        write_varint(&mut writer, self.cycles.len() as u64)?;
+        for cycle in &self.cycles {
+            write_varint(&mut writer, cycle.warrior as u64)?;
+            write_varint(&mut writer, cycle.program_counter as u64)?;
+            write_varint(&mut writer, cycle.writes.len() as u64)?;
+            for write in &cycle.writes {
+                write_varint(&mut writer, write.offset as u64)?;
+                write_instruction(&mut writer, &write.instruction)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a replay in the binary format
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self, Error> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+
+        let mut version = [0; 2];
+        reader.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let config = CoreConfig {
+            core_size: read_signed(&mut reader)?,
+            max_cycles: read_signed(&mut reader)?,
+            max_processes: read_signed(&mut reader)?,
+            max_warrior_length: read_signed(&mut reader)?,
+            min_distance: read_signed(&mut reader)?,
+            p_space: read_signed(&mut reader)?,
+            seed: match read_byte(&mut reader)? {
+                0 => None,
+                1 => {
+                    let mut seed = [0; 8];
+                    reader.read_exact(&mut seed)?;
+                    Some(u64::from_le_bytes(seed))
+                }
+                flag => return Err(Error::Invalid(format!("seed flag {}", flag))),
+            },
+            ..CoreConfig::default()
+        };
+
+        let rounds = read_signed(&mut reader)?;
+        let labels = (0..read_varint(&mut reader)?)
+            .map(|_| {
+                Ok((
+                    read_string(&mut reader, "label")?,
+                    read_signed(&mut reader)?,
+                ))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        let warriors = (0..read_varint(&mut reader)?)
+            .map(|_| {
+                Ok(LoadedWarrior {
+                    offset: read_int(&mut reader)?,
+                    load_file: read_string(&mut reader, "load file")?,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+
+        let cycles = (0..read_varint(&mut reader)?)
+            .map(|_| {
+                let warrior = read_varint(&mut reader)? as WarriorID;
+                let program_counter = read_int(&mut reader)?;
+                let writes = (0..read_varint(&mut reader)?)
+                    .map(|_| {
+                        Ok(CellWrite {
+                            offset: read_int(&mut reader)?,
+                            instruction: read_instruction(&mut reader)?,
+                        })
+                    })
+                    .collect::<Result<_, Error>>()?;
+
+                Ok(Cycle {
+                    warrior,
+                    program_counter,
+                    writes,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+
+        Ok(Self {
+            config,
+            rounds,
+            labels,
+            warriors,
+            cycles,
+        })
+    }
+
+    /// Parse the load files of the warriors in the replay, for the core
+    /// the replay was recorded with
+    pub fn parse_warriors(&self) -> Result<Vec<Warrior>, Error> {
+        let parse_config = parser::ParseConfig {
+            rounds: self.rounds,
+            warriors: i32::try_from(self.warriors.len()).unwrap_or(i32::MAX),
+            labels: self.labels.clone(),
+            ..parser::ParseConfig::from(&self.config)
+        };
+        self.warriors
+            .iter()
+            .enumerate()
+            .map(|(id, warrior)| {
+                match parser::parse_with_config(&warrior.load_file, &parse_config) {
+                    parser::Result::Ok(warrior, _) => Ok(warrior),
+                    parser::Result::Err(err, _) => Err(Error::InvalidWarrior(id, err.to_string())),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Plays back a [`Replay`] one cycle at a time, by running the battle in a
+/// core and checking it against the recording.
+pub struct Player<'a> {
+    replay: &'a Replay,
+    core: Core,
+    cycle: usize,
+}
+
+impl<'a> Player<'a> {
+    /// Load the warriors of a replay into a new core, ready to play it back.
+    /// The core collects [`CellStats`](core::CellStats), since replays are
+    /// played back to be looked at, e.g. rendered as a heatmap.
+    pub fn new(replay: &'a Replay) -> Result<Self, Error> {
+        let warriors = replay.parse_warriors()?;
+        let offsets: Vec<i32> = replay.warriors.iter().map(|w| w.offset).collect();
+
+        let mut core = Core::new(CoreConfig {
+            cell_stats: true,
+            ..replay.config.clone()
+        })?;
+        core.load_warriors_at(&warriors, &offsets)?;
+
+        Ok(Self {
+            replay,
+            core,
+            cycle: 0,
+        })
+    }
+
+    /// The core the replay is being played back in
+    #[must_use]
+    pub fn core(&self) -> &Core {
+        &self.core
+    }
+
+    /// Play back the next cycle, returning the recorded cycle and what
+    /// happened when it was simulated, or `None` when the replay is over.
+    /// Returns an error if the simulation no longer matches the recording.
+    pub fn step(&mut self) -> Result<Option<(&'a Cycle, core::Step)>, Error> {
+        self.step_observed(None)
+    }
+
+    /// Play back the next cycle like [`step`](Self::step), reporting what
+    /// happened to the given observer.
+    pub fn step_with_observer(
+        &mut self,
+        observer: &mut dyn CoreObserver,
+    ) -> Result<Option<(&'a Cycle, core::Step)>, Error> {
+        self.step_observed(Some(observer))
+    }
+
+    fn step_observed(
+        &mut self,
+        observer: Option<&mut dyn CoreObserver>,
+    ) -> Result<Option<(&'a Cycle, core::Step)>, Error> {
+        let expected = match self.replay.cycles.get(self.cycle) {
+            Some(cycle) => cycle,
+            None => return Ok(None),
+        };
+
+        let mut recorder = Recorder::new();
+        let outcome = match observer {
+            Some(observer) => self
+                .core
+                .step_with_observer(&mut Tee(&mut recorder, observer)),
+            None => self.core.step_with_observer(&mut recorder),
+        };
+        let step = match outcome? {
+            StepOutcome::Executed(step) => step,
+            StepOutcome::Halted => return Err(Error::Diverged(self.cycle)),
+        };
+
+        if recorder.cycles.first() != Some(expected) {
+            return Err(Error::Diverged(self.cycle));
+        }
+
+        self.cycle += 1;
+        Ok(Some((expected, step)))
+    }
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn write_signed<W: Write>(writer: &mut W, value: i32) -> io::Result<()> {
+    let zigzag = ((value << 1) ^ (value >> 31)) as u32;
+    write_varint(writer, u64::from(zigzag))
+}
+
+/// Write a length-prefixed UTF-8 string
+fn write_string<W: Write>(writer: &mut W, value: &str) -> io::Result<()> {
+    write_varint(writer, value.len() as u64)?;
+    writer.write_all(value.as_bytes())
+}
+
+fn write_instruction<W: Write>(writer: &mut W, instruction: &Instruction) -> io::Result<()> {
+    writer.write_all(&[
+        instruction.opcode as u8,
+        instruction.modifier as u8,
+        (instruction.a_field.address_mode as u8) << 4 | instruction.b_field.address_mode as u8,
+    ])?;
+    write_varint(writer, instruction.a_field.unwrap_value() as u64)?;
+    write_varint(writer, instruction.b_field.unwrap_value() as u64)
+}
+
+fn read_byte<R: Read>(reader: &mut R) -> Result<u8, Error> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> Result<u64, Error> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = read_byte(reader)?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::Invalid("varint is too long".into()))
+}
+
+/// Read a varint which must fit in an `i32`
+fn read_int<R: Read>(reader: &mut R) -> Result<i32, Error> {
+    let value = read_varint(reader)?;
+    i32::try_from(value).map_err(|_| Error::Invalid(format!("value {} is too large", value)))
+}
+
+fn read_signed<R: Read>(reader: &mut R) -> Result<i32, Error> {
+    let value = read_varint(reader)?;
+    let zigzag = u32::try_from(value)
+        .map_err(|_| Error::Invalid(format!("value {} is too large", value)))?;
+    Ok((zigzag >> 1) as i32 ^ -((zigzag & 1) as i32))
+}
+
+/// Read a length-prefixed UTF-8 string, described by `name` in errors
+fn read_string<R: Read>(reader: &mut R, name: &str) -> Result<String, Error> {
+    let len = read_varint(reader)?;
+    let mut value = Vec::new();
+    reader.by_ref().take(len).read_to_end(&mut value)?;
+    if value.len() as u64 != len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    String::from_utf8(value).map_err(|err| Error::Invalid(format!("{}: {}", name, err)))
+}
+
+fn read_instruction<R: Read>(reader: &mut R) -> Result<Instruction, Error> {
+    fn lookup<T: Copy>(values: &[T], index: u8, name: &str) -> Result<T, Error> {
+        values
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| Error::Invalid(format!("{} {}", name, index)))
+    }
+
+    let opcode = lookup(
+        Opcode::iter_values().as_slice(),
+        read_byte(reader)?,
+        "opcode",
+    )?;
+    let modifier = lookup(
+        Modifier::iter_values().as_slice(),
+        read_byte(reader)?,
+        "modifier",
+    )?;
+    let modes_byte = read_byte(reader)?;
+    let modes = AddressMode::iter_values().as_slice();
+    let a_mode = lookup(modes, modes_byte >> 4, "address mode")?;
+    let b_mode = lookup(modes, modes_byte & 0xf, "address mode")?;
+
+    Ok(Instruction {
+        opcode,
+        modifier,
+        a_field: Field {
+            address_mode: a_mode,
+            value: read_int(reader)?.into(),
+        },
+        b_field: Field {
+            address_mode: b_mode,
+            value: read_int(reader)?.into(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn record_battle() -> (Replay, Vec<Warrior>) {
+        let warriors: Vec<Warrior> = ["mov 0, 1", "spl 0\nmov.i }1, <-1\ndat 5, 5"]
+            .iter()
+            .map(|program| parser::parse(program).expect("Failed to parse warrior"))
+            .collect();
+
+        let mut core = Core::new(CoreConfig {
+            core_size: 800,
+            max_cycles: 2000,
+            seed: Some(1234),
+            ..CoreConfig::default()
+        })
+        .expect("Failed to create core");
+        core.load_warriors(&warriors)
+            .expect("Failed to load warriors");
+
+        let mut recorder = Recorder::new();
+        core.run_with_observer(&mut recorder)
+            .expect("Failed to run");
+        (
+            recorder.finish(&core, &warriors, &parser::ParseConfig::default()),
+            warriors,
+        )
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for &value in &[0, 1, 127, 128, 300, u64::from(u32::MAX), u64::MAX] {
+            let mut buffer = Vec::new();
+            write_varint(&mut buffer, value).unwrap();
+            assert_eq!(read_varint(&mut buffer.as_slice()).unwrap(), value);
+        }
+
+        for &value in &[0, 1, -1, 8000, -8000, i32::MAX, i32::MIN] {
+            let mut buffer = Vec::new();
+            write_signed(&mut buffer, value).unwrap();
+            assert_eq!(read_signed(&mut buffer.as_slice()).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn replay_round_trip() {
+        let (replay, _) = record_battle();
+        assert!(!replay.cycles.is_empty());
+        assert_eq!(replay.config.seed, Some(1234));
+
+        let mut buffer = Vec::new();
+        replay
+            .write_to(&mut buffer)
+            .expect("Failed to write replay");
+        let read = Replay::read_from(buffer.as_slice()).expect("Failed to read replay");
+
+        assert_eq!(read, replay);
+    }
+
+    #[test]
+    fn round_trip_parse_config() {
+        let parse_config = parser::ParseConfig {
+            rounds: 3,
+            labels: [("STEP".to_owned(), -7)].into_iter().collect(),
+            ..parser::ParseConfig::default()
+        };
+        let warrior = match parser::parse_with_config(
+            ";redcode-94\n;assert ROUNDS == 3 && STEP == -7\nadd #STEP, 1\njmp -1",
+            &parse_config,
+        ) {
+            parser::Result::Ok(warrior, _) => warrior,
+            parser::Result::Err(err, _) => panic!("Failed to parse warrior: {}", err),
+        };
+
+        let mut core = Core::default();
+        core.load_warriors(std::slice::from_ref(&warrior))
+            .expect("Failed to load warrior");
+        let mut recorder = Recorder::new();
+        for _ in 0..10 {
+            core.step_with_observer(&mut recorder)
+                .expect("Failed to step");
+        }
+        let replay = recorder.finish(&core, &[warrior], &parse_config);
+
+        let mut buffer = Vec::new();
+        replay
+            .write_to(&mut buffer)
+            .expect("Failed to write replay");
+        let read = Replay::read_from(buffer.as_slice()).expect("Failed to read replay");
+        assert_eq!(read.rounds, 3);
+        assert_eq!(read.labels, parse_config.labels);
+
+        // The assertion only passes with the recorded rounds and labels
+        let mut player = Player::new(&read).expect("Failed to load replay");
+        while player.step().expect("Replay diverged").is_some() {}
+    }
+
+    #[test]
+    fn play_back() {
+        let (replay, warriors) = record_battle();
+
+        let mut player = Player::new(&replay).expect("Failed to load replay");
+        assert_eq!(
+            player.core().placements(),
+            &[
+                Offset::new(0, 800),
+                Offset::new(replay.warriors[1].offset, 800)
+            ]
+        );
+        assert_eq!(
+            replay.parse_warriors().unwrap()[1].program.instructions,
+            warriors[1].program.instructions
+        );
+
+        let mut count = 0;
+        while let Some((cycle, step)) = player.step().expect("Replay diverged") {
+            assert_eq!(cycle.program_counter, step.program_counter.value());
+            count += 1;
+        }
+        assert_eq!(count, replay.cycles.len());
+    }
+
+    #[test]
+    fn play_back_with_observer() {
+        let (replay, _) = record_battle();
+
+        let mut player = Player::new(&replay).expect("Failed to load replay");
+        let mut recorder = Recorder::new();
+        while player
+            .step_with_observer(&mut recorder)
+            .expect("Replay diverged")
+            .is_some()
+        {}
+
+        assert_eq!(recorder.cycles, replay.cycles);
+    }
+
+    #[test]
+    fn detect_divergence() {
+        let (mut replay, _) = record_battle();
+        replay.cycles[10].program_counter += 1;
+
+        let mut player = Player::new(&replay).expect("Failed to load replay");
+        for _ in 0..10 {
+            player.step().expect("Replay diverged early");
+        }
+        assert!(matches!(player.step(), Err(Error::Diverged(10))));
+    }
+
+    #[test]
+    fn reject_invalid_data() {
+        assert!(matches!(
+            Replay::read_from(&b"NOPE"[..]),
+            Err(Error::InvalidMagic)
+        ));
+
+        let mut buffer = MAGIC.to_vec();
+        buffer.extend_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            Replay::read_from(buffer.as_slice()),
+            Err(Error::UnsupportedVersion(version)) if version == VERSION + 1
+        ));
+
+        let (replay, _) = record_battle();
+        let mut buffer = Vec::new();
+        replay.write_to(&mut buffer).unwrap();
+        buffer.truncate(buffer.len() / 2);
+        assert!(matches!(
+            Replay::read_from(buffer.as_slice()),
+            Err(Error::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,367 @@
+//! A structured trace of a battle in [JSON Lines](https://jsonlines.org/)
+//! format, for analysis with other tools.
+//!
+//! # Schema
+//!
+//! Each line is a JSON object with a `"type"` field. The first line is a
+//! header, followed by a step for every cycle of the battle. Instructions are
+//! written in load file syntax, e.g. `"MOV.I $0, $1"`, and all offsets are
+//! absolute positions in the core.
+//!
+//! The header describes the battle:
+//!
+//! | Field       | Type   | Contents                                          |
+//! |-------------|--------|---------------------------------------------------|
+//! | `type`      | string | `"header"`                                        |
+//! | `version`   | number | The version of this schema, currently [`VERSION`] |
+//! | `core_size` | number | The number of cells in the core                   |
+//! | `max_cycles`| number | The maximum number of cycles in the battle        |
+//! | `warriors`  | array  | An object for each warrior, in order of ID, with its `id`, `name` (or `null`) and the `offset` it was loaded at |
+//!
+//! Each step describes a single instruction being executed:
+//!
+//! | Field         | Type    | Contents                                          |
+//! |---------------|---------|---------------------------------------------------|
+//! | `type`        | string  | `"step"`                                          |
+//! | `cycle`       | number  | The cycle of the battle, starting from 0          |
+//! | `warrior`     | number  | The ID of the warrior whose task executed         |
+//! | `thread`      | number  | The ID of the task within the warrior             |
+//! | `pc`          | number  | The offset of the executed instruction            |
+//! | `instruction` | string  | The executed instruction                          |
+//! | `a_pointer`   | number  | The offset the A operand pointed to               |
+//! | `b_pointer`   | number  | The offset the B operand pointed to               |
+//! | `writes`      | array   | An object for each cell written, in order, with its `offset` and new `instruction` |
+//! | `task`        | string  | What happened to the task: `"continued"`, `"jumped"`, `"split"` or `"died"` |
+//! | `next`        | number  | The offset the task jumped to, or the new task starts at. Only present for `"jumped"` and `"split"` |
+//! | `error`       | string  | Why the task died. Only present for `"died"`      |
+//! | `eliminated`  | boolean | Whether this was the warrior's last task          |
+//!
+//! Fields may be added without changing the version, so readers should
+//! ignore fields they don't know about. Any other change to the schema
+//! increments [`VERSION`].
+
+use std::io::{self, Write};
+
+use serde_json::{json, Value};
+
+use crate::core::{Core, CoreObserver, Step, TaskOutcome, WarriorID};
+use crate::load_file::{Instruction, Offset, Warrior};
+
+/// The current version of the trace schema
+pub const VERSION: u32 = 1;
+
+/// A [`CoreObserver`] which writes a line of JSON for each step of a battle
+pub struct Tracer<W: Write> {
+    writer: W,
+    cycle: i32,
+    reads: Vec<i32>,
+    writes: Vec<Value>,
+
+    /// The first error writing the trace. Observers can't return errors, so
+    /// later steps are skipped and this is returned by [`finish`](Self::finish).
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+    /// Start tracing a core which `warriors` have been loaded into, by
+    /// writing the header
+    pub fn new(mut writer: W, core: &Core, warriors: &[Warrior]) -> io::Result<Self> {
+        let warriors: Vec<Value> = warriors
+            .iter()
+            .zip(core.placements())
+            .enumerate()
+            .map(|(id, (warrior, offset))| {
+                json!({
+                    "id": id,
+                    "name": warrior.metadata.name,
+                    "offset": offset.value(),
+                })
+            })
+            .collect();
+
+        let header = json!({
+            "type": "header",
+            "version": VERSION,
+            "core_size": core.len(),
+            "max_cycles": core.config().max_cycles,
+            "warriors": warriors,
+        });
+        writeln!(writer, "{}", header)?;
+
+        Ok(Self {
+            writer,
+            cycle: core.steps_taken(),
+            reads: Vec::with_capacity(2),
+            writes: Vec::new(),
+            error: None,
+        })
+    }
+
+    /// Flush the trace, returning the writer or the first error that
+    /// occurred while writing
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_step(&mut self, step: &Step) -> io::Result<()> {
+        let mut line = json!({
+            "type": "step",
+            "cycle": self.cycle,
+            "warrior": step.warrior,
+            "thread": step.thread,
+            "pc": step.program_counter.value(),
+            "instruction": format_instruction(&step.instruction),
+            "a_pointer": self.reads.first(),
+            "b_pointer": self.reads.get(1),
+            "writes": std::mem::take(&mut self.writes),
+            "eliminated": step.eliminated,
+        });
+
+        let fields = line.as_object_mut().expect("step is an object");
+        let task = match &step.task {
+            TaskOutcome::Continued => "continued",
+            TaskOutcome::Jumped(next) => {
+                fields.insert("next".into(), next.value().into());
+                "jumped"
+            }
+            TaskOutcome::Split(next) => {
+                fields.insert("next".into(), next.value().into());
+                "split"
+            }
+            TaskOutcome::Died(err) => {
+                fields.insert("error".into(), err.to_string().into());
+                "died"
+            }
+        };
+        fields.insert("task".into(), task.into());
+
+        writeln!(self.writer, "{}", line)
+    }
+}
+
+impl<W: Write> CoreObserver for Tracer<W> {
+    fn instruction_executed(&mut self, step: &Step) {
+        if self.error.is_none() {
+            if let Err(err) = self.write_step(step) {
+                self.error = Some(err);
+            }
+        }
+        self.reads.clear();
+        self.writes.clear();
+        self.cycle += 1;
+    }
+
+    fn cell_read(&mut self, _warrior: WarriorID, offset: Offset) {
+        self.reads.push(offset.value());
+    }
+
+    fn cell_written(
+        &mut self,
+        _warrior: WarriorID,
+        offset: Offset,
+        _old: &Instruction,
+        new: &Instruction,
+    ) {
+        self.writes.push(json!({
+            "offset": offset.value(),
+            "instruction": format_instruction(new),
+        }));
+    }
+}
+
+/// Format an instruction in load file syntax, without the column alignment
+/// of its `Display` implementation
+fn format_instruction(instruction: &Instruction) -> String {
+    format!(
+        "{}.{} {}, {}",
+        instruction.opcode, instruction.modifier, instruction.a_field, instruction.b_field
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    use crate::core::CoreConfig;
+    use crate::parser;
+
+    fn trace(program: &str) -> Vec<Value> {
+        let warrior = parser::parse(program).expect("Failed to parse warrior");
+        let mut core = Core::new(CoreConfig {
+            core_size: 16,
+            max_cycles: 10,
+            min_distance: 4,
+            max_warrior_length: 4,
+            ..CoreConfig::default()
+        })
+        .expect("Failed to create core");
+        core.load_warriors_at(std::slice::from_ref(&warrior), &[2])
+            .expect("Failed to load warrior");
+
+        let mut tracer =
+            Tracer::new(Vec::new(), &core, &[warrior]).expect("Failed to write header");
+        core.run_with_observer(&mut tracer).expect("Failed to run");
+        let output = tracer.finish().expect("Failed to write trace");
+
+        String::from_utf8(output)
+            .expect("Trace was not UTF-8")
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("Line was not valid JSON"))
+            .collect()
+    }
+
+    #[test]
+    fn trace_steps() {
+        let lines = trace(
+            "
+            ;name Tracer
+            spl 2
+            mov 1, <2
+            dat 0, 0
+            ",
+        );
+
+        assert_eq!(
+            lines,
+            vec![
+                json!({
+                    "type": "header",
+                    "version": 1,
+                    "core_size": 16,
+                    "max_cycles": 10,
+                    "warriors": [{"id": 0, "name": "Tracer", "offset": 2}],
+                }),
+                json!({
+                    "type": "step",
+                    "cycle": 0,
+                    "warrior": 0,
+                    "thread": 0,
+                    "pc": 2,
+                    "instruction": "SPL.B $2, $0",
+                    "a_pointer": 4,
+                    "b_pointer": 2,
+                    "writes": [],
+                    "task": "split",
+                    "next": 4,
+                    "eliminated": false,
+                }),
+                json!({
+                    "type": "step",
+                    "cycle": 1,
+                    "warrior": 0,
+                    "thread": 0,
+                    "pc": 3,
+                    "instruction": "MOV.I $1, <2",
+                    "a_pointer": 4,
+                    "b_pointer": 4,
+                    "writes": [
+                        {"offset": 5, "instruction": "DAT.F $0, $15"},
+                        {"offset": 4, "instruction": "DAT.F $0, $0"},
+                    ],
+                    "task": "continued",
+                    "eliminated": false,
+                }),
+                json!({
+                    "type": "step",
+                    "cycle": 2,
+                    "warrior": 0,
+                    "thread": 1,
+                    "pc": 4,
+                    "instruction": "DAT.F $0, $0",
+                    "a_pointer": 4,
+                    "b_pointer": 4,
+                    "writes": [],
+                    "task": "died",
+                    "error": "reached a DAT at offset 4",
+                    "eliminated": false,
+                }),
+                json!({
+                    "type": "step",
+                    "cycle": 3,
+                    "warrior": 0,
+                    "thread": 0,
+                    "pc": 4,
+                    "instruction": "DAT.F $0, $0",
+                    "a_pointer": 4,
+                    "b_pointer": 4,
+                    "writes": [],
+                    "task": "died",
+                    "error": "reached a DAT at offset 4",
+                    "eliminated": true,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn report_write_errors() {
+        struct Broken;
+
+        impl Write for Broken {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("broken"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let core = Core::default();
+        assert!(Tracer::new(Broken, &core, &[]).is_err());
+    }
+
+    #[test]
+    fn report_step_write_errors() {
+        /// Accepts `lines` lines, then fails every write
+        #[derive(Debug)]
+        struct FailAfter {
+            lines: usize,
+            output: Vec<u8>,
+        }
+
+        impl Write for FailAfter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if self.lines == 0 {
+                    return Err(io::Error::other("broken"));
+                }
+                self.lines -= buf.iter().filter(|&&byte| byte == b'\n').count();
+                self.output.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let warrior = parser::parse("mov 0, 1").expect("Failed to parse warrior");
+        let mut core = Core::default();
+        core.load_warriors(std::slice::from_ref(&warrior))
+            .expect("Failed to load warrior");
+
+        let mut writer = FailAfter {
+            lines: 2,
+            output: Vec::new(),
+        };
+        let mut tracer =
+            Tracer::new(&mut writer, &core, &[warrior]).expect("Failed to write header");
+        for _ in 0..3 {
+            core.step_with_observer(&mut tracer)
+                .expect("Failed to step");
+        }
+
+        let err = tracer.finish().expect_err("The second step should fail");
+        assert_eq!(err.to_string(), "broken");
+
+        // The header and the first step were written, and nothing after
+        let output = String::from_utf8(writer.output).expect("Trace was not UTF-8");
+        assert_eq!(output.lines().count(), 2);
+    }
+}
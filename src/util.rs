@@ -31,12 +31,12 @@ macro_rules! enum_string {
     (
         $(#[$attr:meta])*
         $vis:vis enum $name:ident {
-            $($variant:ident => $value:expr),* $(,)?
+            $($(#[$variant_attr:meta])* $variant:ident => $value:expr),* $(,)?
         }
     ) => {
         $(#[$attr])*
         $vis enum $name {
-            $($variant,)*
+            $($(#[$variant_attr])* $variant,)*
         }
 
         impl ::std::fmt::Display for $name {
@@ -91,8 +91,9 @@ mod test {
     }
 
     enum_string! {
-        #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+        #[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
         enum Foo {
+            #[default]
             Bar => "Bar",
             Baz => "Baz",
             SomethingElse => "blahblah",
@@ -124,6 +125,11 @@ mod test {
         );
     }
 
+    #[test]
+    fn variant_attributes() {
+        assert_eq!(Foo::default(), Foo::Bar);
+    }
+
     #[test]
     fn iter_values() {
         let values_from_iter: Vec<Foo> = Foo::iter_values().copied().collect();
@@ -0,0 +1,261 @@
+//! Drawing the core in a terminal with ANSI colours, so a battle can be
+//! watched as it runs.
+//!
+//! A [`Visualiser`] observes a [`Core`] to track when each cell was last
+//! written, and renders the core as a grid of characters, using the core's
+//! record of which warrior last wrote each cell:
+//!
+//! | Glyph | Meaning                                                |
+//! |-------|--------------------------------------------------------|
+//! | `@`   | A live task of the warrior will execute this cell next |
+//! | `x`   | The warrior last wrote a `DAT` to this cell            |
+//! | `#`   | The warrior last wrote any other instruction here      |
+//! | `.`   | No warrior has written to this cell                    |
+//!
+//! Each glyph is coloured by its warrior. When the core doesn't fit in the
+//! space given, each glyph stands for several consecutive cells, showing the
+//! most recently written one.
+
+use crate::core::{Core, CoreObserver, Step, WarriorID};
+use crate::load_file::{Instruction, Offset, Opcode, Warrior};
+
+/// ANSI foreground colour codes used for warriors, in order of warrior ID
+const COLOURS: &[u8] = &[31, 32, 33, 34, 35, 36];
+
+const RESET: &str = "\x1b[0m";
+
+/// Tracks when every cell in a core was last written for rendering
+#[derive(Debug)]
+pub struct Visualiser {
+    names: Vec<String>,
+
+    /// The cycle each cell was last written in, to show the most recent write
+    /// when a glyph stands for several cells. Cells written when loading the
+    /// warriors are written in cycle 0.
+    written_at: Vec<i32>,
+
+    cycle: i32,
+}
+
+impl Visualiser {
+    /// Start visualising a core, which `warriors` have already been loaded into
+    #[must_use]
+    pub fn new(core: &Core, warriors: &[Warrior]) -> Self {
+        let names = warriors
+            .iter()
+            .enumerate()
+            .map(|(id, warrior)| {
+                warrior
+                    .metadata
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| id.to_string())
+            })
+            .collect();
+
+        Self {
+            names,
+            written_at: vec![0; core.len() as usize],
+            cycle: 0,
+        }
+    }
+
+    /// Render the core and a status line for each warrior into at most
+    /// `height` lines of `width` characters, not counting ANSI escapes.
+    #[must_use]
+    pub fn render(&self, core: &Core, width: usize, height: usize) -> Vec<String> {
+        let width = width.max(1);
+        let rows = height.saturating_sub(self.names.len() + 1).max(1);
+        let core_size = core.len() as usize;
+        let cells_per_glyph = core_size.div_ceil(width * rows).max(1);
+        let glyphs = core_size.div_ceil(cells_per_glyph);
+
+        let mut tasks = vec![None; glyphs];
+        for entry in core.process_queue().iter() {
+            tasks[entry.offset.value() as usize / cells_per_glyph].get_or_insert(entry.id);
+        }
+
+        let mut lines: Vec<String> = (0..glyphs)
+            .collect::<Vec<_>>()
+            .chunks(width)
+            .map(|row| {
+                let mut line = String::new();
+                for &glyph in row {
+                    let start = glyph * cells_per_glyph;
+                    let end = (start + cells_per_glyph).min(core_size);
+                    line.push_str(&self.render_glyph(core, start..end, tasks[glyph]));
+                }
+                line
+            })
+            .collect();
+
+        lines.push(format!(
+            "Cycle {}/{}, {} cell(s) per glyph",
+            core.steps_taken(),
+            core.config().max_cycles,
+            cells_per_glyph
+        ));
+        for (id, name) in self.names.iter().enumerate() {
+            let threads = core.process_queue().thread_count(id);
+            let status = if threads == 0 {
+                "eliminated".to_owned()
+            } else {
+                format!("{} process(es)", threads)
+            };
+            lines.push(format!("{}# {}{}: {}", colour(id), name, RESET, status));
+        }
+
+        lines
+    }
+
+    fn render_glyph(
+        &self,
+        core: &Core,
+        cells: std::ops::Range<usize>,
+        task: Option<WarriorID>,
+    ) -> String {
+        if let Some(warrior) = task {
+            return format!("\x1b[1m{}@{}", colour(warrior), RESET);
+        }
+
+        let latest = cells
+            .filter_map(|index| Some((index, core.last_writer(index as i32)?)))
+            .max_by_key(|&(index, _)| self.written_at[index]);
+
+        match latest {
+            Some((index, warrior)) => {
+                let glyph = if core.get(index as i32).opcode == Opcode::Dat {
+                    'x'
+                } else {
+                    '#'
+                };
+                format!("{}{}{}", colour(warrior), glyph, RESET)
+            }
+            None => ".".to_owned(),
+        }
+    }
+}
+
+impl CoreObserver for Visualiser {
+    fn instruction_executed(&mut self, _step: &Step) {
+        self.cycle += 1;
+    }
+
+    fn cell_written(
+        &mut self,
+        _warrior: WarriorID,
+        offset: Offset,
+        _old: &Instruction,
+        _new: &Instruction,
+    ) {
+        // Writes are reported before the instruction is counted as executed
+        self.written_at[offset.value() as usize] = self.cycle + 1;
+    }
+}
+
+fn colour(warrior: WarriorID) -> String {
+    format!("\x1b[{}m", COLOURS[warrior % COLOURS.len()])
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    use crate::core::tests::build_core_with;
+    use crate::core::CoreConfig;
+
+    /// Remove ANSI escapes from rendered lines, leaving just the glyphs
+    fn strip_escapes(line: &str) -> String {
+        let mut stripped = String::new();
+        let mut in_escape = false;
+        for c in line.chars() {
+            match c {
+                '\x1b' => in_escape = true,
+                'm' if in_escape => in_escape = false,
+                _ if in_escape => {}
+                _ => stripped.push(c),
+            }
+        }
+        stripped
+    }
+
+    fn build_core(programs: &[&str]) -> (Core, Vec<Warrior>) {
+        build_core_with(
+            CoreConfig {
+                core_size: 16,
+                max_cycles: 100,
+                min_distance: 4,
+                max_warrior_length: 4,
+                ..CoreConfig::default()
+            },
+            programs,
+        )
+    }
+
+    #[test]
+    fn render_writes_and_tasks() {
+        let (mut core, warriors) = build_core(&[
+            "
+            mov 1, 3
+            dat 0, 0
+            ",
+            "jmp 0",
+        ]);
+        let mut visualiser = Visualiser::new(&core, &warriors);
+
+        core.step_with_observer(&mut visualiser)
+            .expect("Failed to step");
+        core.step_with_observer(&mut visualiser)
+            .expect("Failed to step");
+
+        let lines = visualiser.render(&core, 8, 10);
+        assert_eq!(
+            lines
+                .iter()
+                .map(|line| strip_escapes(line))
+                .collect::<Vec<_>>(),
+            vec![
+                "#@.x....",
+                "@.......",
+                "Cycle 2/100, 1 cell(s) per glyph",
+                "# 0: 1 process(es)",
+                "# 1: 1 process(es)",
+            ]
+        );
+        assert!(lines[1].starts_with("\x1b[1m\x1b[32m@"));
+    }
+
+    #[test]
+    fn render_scaled_core() {
+        let (mut core, warriors) = build_core(&["mov 0, 5"]);
+        let mut visualiser = Visualiser::new(&core, &warriors);
+        core.step_with_observer(&mut visualiser)
+            .expect("Failed to step");
+
+        let lines = visualiser.render(&core, 4, 3);
+        assert_eq!(
+            lines
+                .iter()
+                .map(|line| strip_escapes(line))
+                .collect::<Vec<_>>(),
+            vec![
+                "@#..",
+                "Cycle 1/100, 4 cell(s) per glyph",
+                "# 0: 1 process(es)",
+            ]
+        );
+    }
+
+    #[test]
+    fn render_eliminated_warrior() {
+        let (mut core, warriors) = build_core(&["dat 0, 0", "jmp 0"]);
+        let mut visualiser = Visualiser::new(&core, &warriors);
+        core.step_with_observer(&mut visualiser)
+            .expect("Failed to step");
+
+        let lines = visualiser.render(&core, 16, 4);
+        assert_eq!(strip_escapes(&lines[2]), "# 0: eliminated");
+    }
+}
@@ -1,5 +1,5 @@
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
 use normalize_line_endings::normalized;
 use pretty_assertions::assert_eq;
@@ -37,7 +37,7 @@ fn read_dir(input_file: &str) {
     core.load_warriors(&warriors)
         .expect("Failed to load warrior into core");
 
-    let program_subset = &core[0..(warriors[0].len() as usize)];
+    let program_subset = core.instructions().take(warriors[0].len() as usize);
 
     // This is kinda cheaty, but is the same impl as Program::fmt
     let org = format!(
@@ -47,7 +47,7 @@ fn read_dir(input_file: &str) {
     );
 
     let actual_lines: Vec<String> = std::iter::once(org)
-        .chain(program_subset.iter().map(ToString::to_string))
+        .chain(program_subset.map(|instruction| instruction.to_string()))
         .collect();
 
     let expected_lines: Vec<&str> = expected_output.lines().collect();
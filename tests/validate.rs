@@ -22,10 +22,31 @@ fn validate_redcode() {
         core_size: 8_000,
         max_cycles: 10_000,
         ..CoreConfig::default()
-    });
-    core.load_warriors(&vec![warrior]).unwrap();
+    })
+    .unwrap();
+    core.load_warriors(&[warrior]).unwrap();
 
     eprintln!("Before run:\n{:?}\n==============================", core);
 
-    core.run();
+    core.run().expect("Failed to run");
+}
+
+#[test]
+fn refuse_wrong_core_size() {
+    let input_file =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/input/wilkie/irongate.redcode");
+    let input = fs::read_to_string(input_file).unwrap();
+
+    let config = parser::ParseConfig::from(&CoreConfig {
+        core_size: 800,
+        ..CoreConfig::default()
+    });
+
+    match parser::parse_with_config(&input, &config) {
+        parser::Result::Err(parser::Error::AssertionFailed { assertion, span }, _) => {
+            assert_eq!(assertion, "800==8000");
+            assert_eq!(span.line, 5);
+        }
+        other => panic!("Expected the assertion to fail, got {:?}", other),
+    }
 }